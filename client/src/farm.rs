@@ -0,0 +1,920 @@
+//! Instruction builders for the yield-farming program.
+//!
+//! Vault addresses are derived from the farm, so builders only take the
+//! caller's own token accounts. Receipt tokens always live in the holder's
+//! associated token account; see [`create_receipt_account`].
+
+use anchor_lang::prelude::Pubkey;
+use anchor_lang::solana_program::instruction::{AccountMeta, Instruction};
+use anchor_lang::{system_program, InstructionData, ToAccountMetas};
+use anchor_spl::associated_token::{self, get_associated_token_address};
+use anchor_spl::token;
+use yield_farming::EmissionSegment;
+
+use crate::pda;
+
+pub fn initialize_registry(payer: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: yield_farming::accounts::InitializeRegistry {
+            registry: pda::registry().0,
+            payer: *payer,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: yield_farming::instruction::InitializeRegistry {}.data(),
+    }
+}
+
+/// `index` must be the registry's current farm count.
+pub fn initialize_farm(
+    authority: &Pubkey,
+    index: u64,
+    reward_token_mint: &Pubkey,
+    staking_token_mint: &Pubkey,
+    reward_rate: u64,
+    farm_duration: i64,
+) -> Instruction {
+    let farm = pda::farm(authority, index).0;
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: yield_farming::accounts::InitializeFarm {
+            registry: pda::registry().0,
+            farm,
+            authority: *authority,
+            reward_token_mint: *reward_token_mint,
+            staking_token_mint: *staking_token_mint,
+            staking_vault: pda::stake_vault(&farm).0,
+            reward_vault: pda::reward_vault(&farm).0,
+            receipt_mint: pda::receipt_mint(&farm).0,
+            token_program: token::ID,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: yield_farming::instruction::InitializeFarm {
+            reward_rate,
+            farm_duration,
+        }
+        .data(),
+    }
+}
+
+/// The `user`'s associated account for the farm's receipt token.
+pub fn receipt_account(farm: &Pubkey, user: &Pubkey) -> Pubkey {
+    get_associated_token_address(user, &pda::receipt_mint(farm).0)
+}
+
+/// Creates `user`'s receipt account if it does not exist yet. Staking
+/// requires it.
+pub fn create_receipt_account(payer: &Pubkey, farm: &Pubkey, user: &Pubkey) -> Instruction {
+    let mint = pda::receipt_mint(farm).0;
+    Instruction {
+        program_id: associated_token::ID,
+        accounts: vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(get_associated_token_address(user, &mint), false),
+            AccountMeta::new_readonly(*user, false),
+            AccountMeta::new_readonly(mint, false),
+            AccountMeta::new_readonly(system_program::ID, false),
+            AccountMeta::new_readonly(token::ID, false),
+        ],
+        // `CreateIdempotent`
+        data: vec![1],
+    }
+}
+
+/// Re-syncs `owner`'s position with their receipt balance; callable by
+/// anyone after receipts move. Checkpoint the sender before the recipient,
+/// whose new receipts count only once the sender has released them.
+pub fn checkpoint(farm: &Pubkey, owner: &Pubkey, payer: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: yield_farming::accounts::Checkpoint {
+            farm: *farm,
+            user_stake: pda::user_stake(farm, owner).0,
+            owner: *owner,
+            receipt_mint: pda::receipt_mint(farm).0,
+            receipt_account: receipt_account(farm, owner),
+            payer: *payer,
+            token_program: token::ID,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: yield_farming::instruction::Checkpoint {}.data(),
+    }
+}
+
+pub fn stake_tokens(
+    farm: &Pubkey,
+    user: &Pubkey,
+    user_token_account: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: stake_accounts(farm, user, user_token_account),
+        data: yield_farming::instruction::StakeTokens { amount }.data(),
+    }
+}
+
+/// Stakes `amount` and locks the whole position for at least `lock_weeks`.
+pub fn stake_locked(
+    farm: &Pubkey,
+    user: &Pubkey,
+    user_token_account: &Pubkey,
+    amount: u64,
+    lock_weeks: u8,
+) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: stake_accounts(farm, user, user_token_account),
+        data: yield_farming::instruction::StakeLocked { amount, lock_weeks }.data(),
+    }
+}
+
+/// Drops the boost of `user`'s expired lock; callable by anyone.
+pub fn expire_lock(farm: &Pubkey, user: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: yield_farming::accounts::ExpireLock {
+            farm: *farm,
+            user_stake: pda::user_stake(farm, user).0,
+        }
+        .to_account_metas(None),
+        data: yield_farming::instruction::ExpireLock {}.data(),
+    }
+}
+
+pub fn unstake_tokens(
+    farm: &Pubkey,
+    user: &Pubkey,
+    user_token_account: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: unstake_accounts(farm, user, user_token_account),
+        data: yield_farming::instruction::UnstakeTokens {
+            amount,
+            close_if_empty: false,
+        }
+        .data(),
+    }
+}
+
+/// Unstakes `amount` and, if that empties the position, closes it and
+/// refunds its rent. Unclaimed rewards keep the position open.
+pub fn unstake_and_close(
+    farm: &Pubkey,
+    user: &Pubkey,
+    user_token_account: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: unstake_accounts(farm, user, user_token_account),
+        data: yield_farming::instruction::UnstakeTokens {
+            amount,
+            close_if_empty: true,
+        }
+        .data(),
+    }
+}
+
+pub fn close_user_stake(farm: &Pubkey, user: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: yield_farming::accounts::CloseUserStake {
+            farm: *farm,
+            user_stake: pda::user_stake(farm, user).0,
+            user: *user,
+            receipt_mint: pda::receipt_mint(farm).0,
+            receipt_account: receipt_account(farm, user),
+        }
+        .to_account_metas(None),
+        data: yield_farming::instruction::CloseUserStake {}.data(),
+    }
+}
+
+pub fn claim_rewards(farm: &Pubkey, user: &Pubkey, user_reward_account: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: claim_accounts(farm, user, user_reward_account),
+        data: yield_farming::instruction::ClaimRewards {}.data(),
+    }
+}
+
+pub fn notify_reward_amount(
+    authority: &Pubkey,
+    farm: &Pubkey,
+    funder_token_account: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: yield_farming::accounts::NotifyRewardAmount {
+            farm: *farm,
+            authority: *authority,
+            funder_token_account: *funder_token_account,
+            reward_token_account: pda::reward_vault(farm).0,
+            token_program: token::ID,
+        }
+        .to_account_metas(None),
+        data: yield_farming::instruction::NotifyRewardAmount { amount }.data(),
+    }
+}
+
+pub fn add_reward_stream(
+    authority: &Pubkey,
+    farm: &Pubkey,
+    reward_token_mint: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: yield_farming::accounts::AddRewardStream {
+            farm: *farm,
+            authority: *authority,
+            reward_token_mint: *reward_token_mint,
+            reward_token_account: pda::stream_vault(farm, reward_token_mint).0,
+            token_program: token::ID,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: yield_farming::instruction::AddRewardStream {}.data(),
+    }
+}
+
+pub fn fund_reward_stream(
+    authority: &Pubkey,
+    farm: &Pubkey,
+    funder_token_account: &Pubkey,
+    reward_token_mint: &Pubkey,
+    index: u8,
+    amount: u64,
+) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: yield_farming::accounts::FundRewardStream {
+            farm: *farm,
+            authority: *authority,
+            funder_token_account: *funder_token_account,
+            reward_token_account: pda::stream_vault(farm, reward_token_mint).0,
+            token_program: token::ID,
+        }
+        .to_account_metas(None),
+        data: yield_farming::instruction::FundRewardStream { index, amount }.data(),
+    }
+}
+
+/// Claims the primary reward and every extra stream. `streams` lists the
+/// `(reward token mint, user token account)` pair of each active stream in
+/// order.
+pub fn claim_all_rewards(
+    farm: &Pubkey,
+    user: &Pubkey,
+    user_reward_account: &Pubkey,
+    streams: &[(Pubkey, Pubkey)],
+) -> Instruction {
+    let mut accounts = claim_accounts(farm, user, user_reward_account);
+    for (mint, user_account) in streams {
+        accounts.push(AccountMeta::new(pda::stream_vault(farm, mint).0, false));
+        accounts.push(AccountMeta::new(*user_account, false));
+    }
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts,
+        data: yield_farming::instruction::ClaimAllRewards {}.data(),
+    }
+}
+
+pub fn pause_farm(authority: &Pubkey, farm: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: status_accounts(authority, farm),
+        data: yield_farming::instruction::PauseFarm {}.data(),
+    }
+}
+
+pub fn unpause_farm(authority: &Pubkey, farm: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: status_accounts(authority, farm),
+        data: yield_farming::instruction::UnpauseFarm {}.data(),
+    }
+}
+
+/// Withdraws the user's whole stake and forfeits pending rewards.
+pub fn emergency_withdraw(
+    farm: &Pubkey,
+    user: &Pubkey,
+    user_token_account: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: unstake_accounts(farm, user, user_token_account),
+        data: yield_farming::instruction::EmergencyWithdraw {}.data(),
+    }
+}
+
+pub fn set_cooldown(authority: &Pubkey, farm: &Pubkey, cooldown_seconds: i64) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: config_accounts(authority, farm),
+        data: yield_farming::instruction::SetCooldown { cooldown_seconds }.data(),
+    }
+}
+
+/// Caps new deposits into the farm; zero leaves a cap off.
+pub fn set_deposit_caps(
+    authority: &Pubkey,
+    farm: &Pubkey,
+    max_total_staked: u64,
+    max_stake_per_user: u64,
+) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: config_accounts(authority, farm),
+        data: yield_farming::instruction::SetDepositCaps {
+            max_total_staked,
+            max_stake_per_user,
+        }
+        .data(),
+    }
+}
+
+/// Starts the farm's cooldown on `amount` of the user's stake.
+pub fn request_unstake(
+    farm: &Pubkey,
+    user: &Pubkey,
+    user_token_account: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: unstake_accounts(farm, user, user_token_account),
+        data: yield_farming::instruction::RequestUnstake { amount }.data(),
+    }
+}
+
+/// Releases the user's unbonded tokens once the cooldown has elapsed.
+pub fn withdraw_unstaked(farm: &Pubkey, user: &Pubkey, user_token_account: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: unstake_accounts(farm, user, user_token_account),
+        data: yield_farming::instruction::WithdrawUnstaked {}.data(),
+    }
+}
+
+/// Fees are in basis points and capped by the program.
+pub fn set_fee_config(
+    authority: &Pubkey,
+    farm: &Pubkey,
+    deposit_fee_bps: u16,
+    withdraw_fee_bps: u16,
+    performance_fee_bps: u16,
+    fee_recipient: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: config_accounts(authority, farm),
+        data: yield_farming::instruction::SetFeeConfig {
+            deposit_fee_bps,
+            withdraw_fee_bps,
+            performance_fee_bps,
+            fee_recipient: *fee_recipient,
+        }
+        .data(),
+    }
+}
+
+/// Changes the emission rate for the rest of the current period.
+pub fn set_reward_rate(authority: &Pubkey, farm: &Pubkey, reward_rate: u64) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: yield_farming::accounts::SetRewardRate {
+            farm: *farm,
+            authority: *authority,
+            reward_vault: pda::reward_vault(farm).0,
+        }
+        .to_account_metas(None),
+        data: yield_farming::instruction::SetRewardRate { reward_rate }.data(),
+    }
+}
+
+/// Takes effect from the next `notify_reward_amount`; fails while a period is running.
+pub fn set_duration(authority: &Pubkey, farm: &Pubkey, farm_duration: i64) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: config_accounts(authority, farm),
+        data: yield_farming::instruction::SetDuration { farm_duration }.data(),
+    }
+}
+
+/// Replaces the flat reward rate with `segments`; the reward vault must
+/// already hold their remaining emission. No segments restores the flat rate.
+pub fn set_emission_schedule(
+    authority: &Pubkey,
+    farm: &Pubkey,
+    segments: Vec<EmissionSegment>,
+) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: yield_farming::accounts::SetRewardRate {
+            farm: *farm,
+            authority: *authority,
+            reward_vault: pda::reward_vault(farm).0,
+        }
+        .to_account_metas(None),
+        data: yield_farming::instruction::SetEmissionSchedule { segments }.data(),
+    }
+}
+
+/// First step of an authority transfer; `new_authority` must call
+/// [`accept_authority`].
+pub fn set_authority(authority: &Pubkey, farm: &Pubkey, new_authority: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: config_accounts(authority, farm),
+        data: yield_farming::instruction::SetAuthority {
+            new_authority: *new_authority,
+        }
+        .data(),
+    }
+}
+
+pub fn accept_authority(farm: &Pubkey, pending_authority: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: yield_farming::accounts::AcceptAuthority {
+            farm: *farm,
+            pending_authority: *pending_authority,
+        }
+        .to_account_metas(None),
+        data: yield_farming::instruction::AcceptAuthority {}.data(),
+    }
+}
+
+/// Sweeps accrued fees into the fee recipient's staking and reward token
+/// accounts.
+pub fn collect_fees(
+    farm: &Pubkey,
+    stake_fee_account: &Pubkey,
+    reward_fee_account: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: yield_farming::accounts::CollectFees {
+            farm: *farm,
+            staking_vault: pda::stake_vault(farm).0,
+            reward_vault: pda::reward_vault(farm).0,
+            stake_fee_account: *stake_fee_account,
+            reward_fee_account: *reward_fee_account,
+            token_program: token::ID,
+        }
+        .to_account_metas(None),
+        data: yield_farming::instruction::CollectFees {}.data(),
+    }
+}
+
+pub fn compound(farm: &Pubkey, user: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: yield_farming::accounts::Compound {
+            farm: *farm,
+            user_stake: pda::user_stake(farm, user).0,
+            user: *user,
+            staking_vault: pda::stake_vault(farm).0,
+            reward_vault: pda::reward_vault(farm).0,
+            receipt_mint: pda::receipt_mint(farm).0,
+            user_receipt_account: receipt_account(farm, user),
+            token_program: token::ID,
+        }
+        .to_account_metas(None),
+        data: yield_farming::instruction::Compound {}.data(),
+    }
+}
+
+pub fn set_auto_compound(farm: &Pubkey, user: &Pubkey, enabled: bool) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: yield_farming::accounts::SetAutoCompound {
+            farm: *farm,
+            user_stake: pda::user_stake(farm, user).0,
+            user: *user,
+        }
+        .to_account_metas(None),
+        data: yield_farming::instruction::SetAutoCompound { enabled }.data(),
+    }
+}
+
+/// Compounds `user`'s rewards, paying the keeper's tip into
+/// `keeper_reward_account`.
+pub fn compound_for(
+    farm: &Pubkey,
+    user: &Pubkey,
+    keeper: &Pubkey,
+    keeper_reward_account: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: yield_farming::accounts::CompoundFor {
+            farm: *farm,
+            user_stake: pda::user_stake(farm, user).0,
+            keeper: *keeper,
+            keeper_reward_account: *keeper_reward_account,
+            staking_vault: pda::stake_vault(farm).0,
+            reward_vault: pda::reward_vault(farm).0,
+            receipt_mint: pda::receipt_mint(farm).0,
+            user_receipt_account: receipt_account(farm, user),
+            token_program: token::ID,
+        }
+        .to_account_metas(None),
+        data: yield_farming::instruction::CompoundFor {}.data(),
+    }
+}
+
+/// Turns reward vesting on for `vesting_period > 0`, creating the vesting
+/// vault on first use.
+pub fn set_vesting(
+    authority: &Pubkey,
+    farm: &Pubkey,
+    reward_token_mint: &Pubkey,
+    vesting_period: i64,
+    early_exit_penalty_bps: u16,
+) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: yield_farming::accounts::SetVesting {
+            farm: *farm,
+            authority: *authority,
+            reward_token_mint: *reward_token_mint,
+            vesting_vault: pda::vesting_vault(farm).0,
+            token_program: token::ID,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: yield_farming::instruction::SetVesting {
+            vesting_period,
+            early_exit_penalty_bps,
+        }
+        .data(),
+    }
+}
+
+pub fn vest_rewards(farm: &Pubkey, user: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: yield_farming::accounts::VestRewards {
+            farm: *farm,
+            user_stake: pda::user_stake(farm, user).0,
+            vesting: pda::vesting(farm, user).0,
+            user: *user,
+            reward_vault: pda::reward_vault(farm).0,
+            vesting_vault: pda::vesting_vault(farm).0,
+            receipt_mint: pda::receipt_mint(farm).0,
+            receipt_account: receipt_account(farm, user),
+            token_program: token::ID,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: yield_farming::instruction::VestRewards {}.data(),
+    }
+}
+
+pub fn withdraw_vested(farm: &Pubkey, user: &Pubkey, user_reward_account: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: yield_farming::accounts::WithdrawVested {
+            farm: *farm,
+            vesting: pda::vesting(farm, user).0,
+            user: *user,
+            user_reward_account: *user_reward_account,
+            vesting_vault: pda::vesting_vault(farm).0,
+            token_program: token::ID,
+        }
+        .to_account_metas(None),
+        data: yield_farming::instruction::WithdrawVested {}.data(),
+    }
+}
+
+pub fn exit_vesting(farm: &Pubkey, user: &Pubkey, user_reward_account: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: yield_farming::accounts::ExitVesting {
+            farm: *farm,
+            vesting: pda::vesting(farm, user).0,
+            user: *user,
+            user_reward_account: *user_reward_account,
+            vesting_vault: pda::vesting_vault(farm).0,
+            reward_vault: pda::reward_vault(farm).0,
+            token_program: token::ID,
+        }
+        .to_account_metas(None),
+        data: yield_farming::instruction::ExitVesting {}.data(),
+    }
+}
+
+/// Stakes `funder`'s tokens into `beneficiary`'s position. The
+/// beneficiary's receipt account must exist.
+pub fn stake_for(
+    farm: &Pubkey,
+    funder: &Pubkey,
+    funder_token_account: &Pubkey,
+    beneficiary: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: yield_farming::accounts::StakeFor {
+            farm: *farm,
+            user_stake: pda::user_stake(farm, beneficiary).0,
+            beneficiary: *beneficiary,
+            funder: *funder,
+            funder_token_account: *funder_token_account,
+            farm_token_account: pda::stake_vault(farm).0,
+            receipt_mint: pda::receipt_mint(farm).0,
+            beneficiary_receipt_account: receipt_account(farm, beneficiary),
+            token_program: token::ID,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: yield_farming::instruction::StakeFor { amount }.data(),
+    }
+}
+
+/// Moves `user`'s whole stake to `new_owner`, whose receipt account must
+/// exist.
+pub fn transfer_stake(farm: &Pubkey, user: &Pubkey, new_owner: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: yield_farming::accounts::TransferStake {
+            farm: *farm,
+            user_stake: pda::user_stake(farm, user).0,
+            user: *user,
+            new_owner: *new_owner,
+            new_user_stake: pda::user_stake(farm, new_owner).0,
+            receipt_mint: pda::receipt_mint(farm).0,
+            user_receipt_account: receipt_account(farm, user),
+            new_owner_receipt_account: receipt_account(farm, new_owner),
+            token_program: token::ID,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: yield_farming::instruction::TransferStake {}.data(),
+    }
+}
+
+fn stake_accounts(farm: &Pubkey, user: &Pubkey, user_token_account: &Pubkey) -> Vec<AccountMeta> {
+    yield_farming::accounts::StakeTokens {
+        farm: *farm,
+        user_stake: pda::user_stake(farm, user).0,
+        user: *user,
+        user_token_account: *user_token_account,
+        farm_token_account: pda::stake_vault(farm).0,
+        receipt_mint: pda::receipt_mint(farm).0,
+        user_receipt_account: receipt_account(farm, user),
+        token_program: token::ID,
+        system_program: system_program::ID,
+    }
+    .to_account_metas(None)
+}
+
+fn unstake_accounts(farm: &Pubkey, user: &Pubkey, user_token_account: &Pubkey) -> Vec<AccountMeta> {
+    yield_farming::accounts::UnstakeTokens {
+        farm: *farm,
+        user_stake: pda::user_stake(farm, user).0,
+        user: *user,
+        user_token_account: *user_token_account,
+        farm_token_account: pda::stake_vault(farm).0,
+        receipt_mint: pda::receipt_mint(farm).0,
+        user_receipt_account: receipt_account(farm, user),
+        token_program: token::ID,
+    }
+    .to_account_metas(None)
+}
+
+pub fn initialize_controller(
+    authority: &Pubkey,
+    reward_token_mint: &Pubkey,
+    emission_rate: u64,
+) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: yield_farming::accounts::InitializeController {
+            controller: pda::controller().0,
+            authority: *authority,
+            reward_token_mint: *reward_token_mint,
+            reward_vault: pda::controller_vault().0,
+            token_program: token::ID,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: yield_farming::instruction::InitializeController { emission_rate }.data(),
+    }
+}
+
+pub fn set_emission_rate(authority: &Pubkey, emission_rate: u64) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: yield_farming::accounts::SetEmissionRate {
+            controller: pda::controller().0,
+            authority: *authority,
+        }
+        .to_account_metas(None),
+        data: yield_farming::instruction::SetEmissionRate { emission_rate }.data(),
+    }
+}
+
+/// Sets `farm`'s share of the controller emission, paying out what it earned
+/// under its old share.
+pub fn set_alloc_point(authority: &Pubkey, farm: &Pubkey, alloc_point: u64) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: yield_farming::accounts::SetAllocPoint {
+            controller: pda::controller().0,
+            authority: *authority,
+            controller_vault: pda::controller_vault().0,
+            farm: *farm,
+            farm_reward_vault: pda::reward_vault(farm).0,
+            gauge: pda::gauge(farm).0,
+            token_program: token::ID,
+        }
+        .to_account_metas(None),
+        data: yield_farming::instruction::SetAllocPoint { alloc_point }.data(),
+    }
+}
+
+/// Pays each of `farms` the controller emissions it has earned.
+pub fn mass_update_pools(farms: &[Pubkey]) -> Instruction {
+    let mut accounts = yield_farming::accounts::MassUpdatePools {
+        controller: pda::controller().0,
+        controller_vault: pda::controller_vault().0,
+        token_program: token::ID,
+    }
+    .to_account_metas(None);
+    for farm in farms {
+        accounts.push(AccountMeta::new(*farm, false));
+        accounts.push(AccountMeta::new(pda::reward_vault(farm).0, false));
+    }
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts,
+        data: yield_farming::instruction::MassUpdatePools {}.data(),
+    }
+}
+
+/// `governance_token_mint` must be the token of the governance program's
+/// [`pda::governance`] account.
+pub fn initialize_gauges(
+    authority: &Pubkey,
+    governance_token_mint: &Pubkey,
+    epoch_duration: i64,
+) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: yield_farming::accounts::InitializeGauges {
+            controller: pda::controller().0,
+            gauge_controller: pda::gauge_controller().0,
+            authority: *authority,
+            governance: pda::governance().0,
+            governance_token_mint: *governance_token_mint,
+            lock_vault: pda::gauge_lock_vault().0,
+            token_program: token::ID,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: yield_farming::instruction::InitializeGauges { epoch_duration }.data(),
+    }
+}
+
+pub fn create_gauge(authority: &Pubkey, farm: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: yield_farming::accounts::CreateGauge {
+            controller: pda::controller().0,
+            gauge_controller: pda::gauge_controller().0,
+            farm: *farm,
+            gauge: pda::gauge(farm).0,
+            authority: *authority,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: yield_farming::instruction::CreateGauge {}.data(),
+    }
+}
+
+/// Locks `amount` more governance tokens until `unlock_time`, which cannot be
+/// earlier than the current unlock time.
+pub fn lock_governance_tokens(
+    owner: &Pubkey,
+    owner_token_account: &Pubkey,
+    amount: u64,
+    unlock_time: i64,
+) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: yield_farming::accounts::LockGovernanceTokens {
+            gauge_controller: pda::gauge_controller().0,
+            voter_lock: pda::voter_lock(owner).0,
+            owner: *owner,
+            owner_token_account: *owner_token_account,
+            lock_vault: pda::gauge_lock_vault().0,
+            token_program: token::ID,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: yield_farming::instruction::LockGovernanceTokens {
+            amount,
+            unlock_time,
+        }
+        .data(),
+    }
+}
+
+pub fn withdraw_governance_tokens(owner: &Pubkey, owner_token_account: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: yield_farming::accounts::WithdrawGovernanceTokens {
+            gauge_controller: pda::gauge_controller().0,
+            voter_lock: pda::voter_lock(owner).0,
+            owner: *owner,
+            owner_token_account: *owner_token_account,
+            lock_vault: pda::gauge_lock_vault().0,
+            token_program: token::ID,
+        }
+        .to_account_metas(None),
+        data: yield_farming::instruction::WithdrawGovernanceTokens {}.data(),
+    }
+}
+
+/// Splits the owner's locked weight across the gauges of `votes`, given as
+/// `(farm, weight in basis points)`.
+pub fn vote_gauges(owner: &Pubkey, votes: &[(Pubkey, u16)]) -> Instruction {
+    let mut accounts = yield_farming::accounts::VoteGauges {
+        gauge_controller: pda::gauge_controller().0,
+        voter_lock: pda::voter_lock(owner).0,
+        owner: *owner,
+    }
+    .to_account_metas(None);
+    accounts.extend(
+        votes
+            .iter()
+            .map(|(farm, _)| AccountMeta::new(pda::gauge(farm).0, false)),
+    );
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts,
+        data: yield_farming::instruction::VoteGauges {
+            weights_bps: votes.iter().map(|&(_, weight_bps)| weight_bps).collect(),
+        }
+        .data(),
+    }
+}
+
+/// Applies the last epoch's votes to `farm`'s share of the controller emission.
+pub fn update_gauge(farm: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: yield_farming::ID,
+        accounts: yield_farming::accounts::UpdateGauge {
+            controller: pda::controller().0,
+            controller_vault: pda::controller_vault().0,
+            gauge_controller: pda::gauge_controller().0,
+            gauge: pda::gauge(farm).0,
+            farm: *farm,
+            farm_reward_vault: pda::reward_vault(farm).0,
+            token_program: token::ID,
+        }
+        .to_account_metas(None),
+        data: yield_farming::instruction::UpdateGauge {}.data(),
+    }
+}
+
+fn config_accounts(authority: &Pubkey, farm: &Pubkey) -> Vec<AccountMeta> {
+    yield_farming::accounts::SetFarmConfig {
+        farm: *farm,
+        authority: *authority,
+    }
+    .to_account_metas(None)
+}
+
+fn status_accounts(authority: &Pubkey, farm: &Pubkey) -> Vec<AccountMeta> {
+    yield_farming::accounts::SetFarmStatus {
+        farm: *farm,
+        authority: *authority,
+    }
+    .to_account_metas(None)
+}
+
+fn claim_accounts(farm: &Pubkey, user: &Pubkey, user_reward_account: &Pubkey) -> Vec<AccountMeta> {
+    yield_farming::accounts::ClaimRewards {
+        farm: *farm,
+        user_stake: pda::user_stake(farm, user).0,
+        user: *user,
+        user_reward_account: *user_reward_account,
+        reward_token_account: pda::reward_vault(farm).0,
+        receipt_mint: pda::receipt_mint(farm).0,
+        receipt_account: receipt_account(farm, user),
+        token_program: token::ID,
+    }
+    .to_account_metas(None)
+}
@@ -0,0 +1,222 @@
+//! Instruction builders for the governance program.
+
+use anchor_lang::prelude::Pubkey;
+use anchor_lang::solana_program::instruction::{AccountMeta, Instruction};
+use anchor_lang::{
+    system_program, AnchorDeserialize, AnchorSerialize, InstructionData, ToAccountMetas,
+};
+use governance::{ProposalAccountMeta, ProposalInstruction, ProposalType, VoteType};
+
+use crate::{pda, ClientError, Proposal, Result};
+
+pub fn initialize_governance(
+    authority: &Pubkey,
+    governance_token_mint: &Pubkey,
+    voting_period: i64,
+    execution_delay: i64,
+    proposal_threshold: u64,
+    quorum_threshold: u64,
+) -> Instruction {
+    Instruction {
+        program_id: governance::ID,
+        accounts: governance::accounts::InitializeGovernance {
+            governance: pda::governance().0,
+            authority: *authority,
+            governance_token_mint: *governance_token_mint,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: governance::instruction::InitializeGovernance {
+            voting_period,
+            execution_delay,
+            proposal_threshold,
+            quorum_threshold,
+        }
+        .data(),
+    }
+}
+
+/// `proposal_id` must be the governance account's current `proposal_count`.
+pub fn create_proposal(
+    proposer: &Pubkey,
+    user_token_account: &Pubkey,
+    proposal_id: u64,
+    title: String,
+    description: String,
+    proposal_type: ProposalType,
+    execution_data: Vec<u8>,
+) -> Instruction {
+    let governance = pda::governance().0;
+    Instruction {
+        program_id: governance::ID,
+        accounts: governance::accounts::CreateProposal {
+            governance,
+            proposal: pda::proposal(&governance, proposal_id).0,
+            proposer: *proposer,
+            user_token_account: *user_token_account,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: governance::instruction::CreateProposal {
+            title,
+            description,
+            proposal_type,
+            execution_data,
+        }
+        .data(),
+    }
+}
+
+pub fn cast_vote(
+    voter: &Pubkey,
+    user_token_account: &Pubkey,
+    proposal_id: u64,
+    vote: VoteType,
+    voting_power: u64,
+) -> Instruction {
+    let proposal = pda::proposal(&pda::governance().0, proposal_id).0;
+    Instruction {
+        program_id: governance::ID,
+        accounts: governance::accounts::CastVote {
+            proposal,
+            vote_record: pda::vote(&proposal, voter).0,
+            voter: *voter,
+            user_token_account: *user_token_account,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: governance::instruction::CastVote {
+            proposal_id,
+            vote,
+            voting_power,
+        }
+        .data(),
+    }
+}
+
+pub fn queue_proposal(proposal_id: u64) -> Instruction {
+    let governance = pda::governance().0;
+    Instruction {
+        program_id: governance::ID,
+        accounts: governance::accounts::QueueProposal {
+            governance,
+            proposal: pda::proposal(&governance, proposal_id).0,
+        }
+        .to_account_metas(None),
+        data: governance::instruction::QueueProposal { proposal_id }.data(),
+    }
+}
+
+pub fn execute_proposal(executor: &Pubkey, proposal_id: u64) -> Instruction {
+    let governance = pda::governance().0;
+    Instruction {
+        program_id: governance::ID,
+        accounts: governance::accounts::ExecuteProposal {
+            governance,
+            proposal: pda::proposal(&governance, proposal_id).0,
+            executor: *executor,
+        }
+        .to_account_metas(None),
+        data: governance::instruction::ExecuteProposal { proposal_id }.data(),
+    }
+}
+
+/// Encodes `instruction` as the execution data of a parameter change
+/// proposal. Accounts the governance PDA signs for must be marked as signers.
+pub fn proposal_instruction(instruction: &Instruction) -> Result<Vec<u8>> {
+    ProposalInstruction {
+        program_id: instruction.program_id,
+        accounts: instruction
+            .accounts
+            .iter()
+            .map(|meta| ProposalAccountMeta {
+                pubkey: meta.pubkey,
+                is_signer: meta.is_signer,
+                is_writable: meta.is_writable,
+            })
+            .collect(),
+        data: instruction.data.clone(),
+    }
+    .try_to_vec()
+    .map_err(ClientError::Encode)
+}
+
+/// Decodes the instruction a parameter change proposal executes, if any.
+pub fn decode_proposal_instruction(proposal: &Proposal) -> Option<Instruction> {
+    if proposal.proposal_type != ProposalType::ParameterChange || proposal.execution_data.is_empty()
+    {
+        return None;
+    }
+    let instruction = ProposalInstruction::try_from_slice(&proposal.execution_data).ok()?;
+    Some(Instruction {
+        program_id: instruction.program_id,
+        accounts: instruction
+            .accounts
+            .into_iter()
+            .map(|meta| AccountMeta {
+                pubkey: meta.pubkey,
+                is_signer: meta.is_signer,
+                is_writable: meta.is_writable,
+            })
+            .collect(),
+        data: instruction.data,
+    })
+}
+
+/// Executes a proposal created with [`proposal_instruction`], passing the
+/// accounts `instruction` needs.
+pub fn execute_proposal_instruction(
+    executor: &Pubkey,
+    proposal_id: u64,
+    instruction: &Instruction,
+) -> Instruction {
+    let mut execute = execute_proposal(executor, proposal_id);
+    execute
+        .accounts
+        .push(AccountMeta::new_readonly(instruction.program_id, false));
+    // Only the governance PDA signs, and it does so inside the program
+    execute
+        .accounts
+        .extend(instruction.accounts.iter().map(|meta| AccountMeta {
+            is_signer: false,
+            ..meta.clone()
+        }));
+    execute
+}
+
+pub fn cancel_proposal(canceller: &Pubkey, proposal_id: u64) -> Instruction {
+    let governance = pda::governance().0;
+    Instruction {
+        program_id: governance::ID,
+        accounts: governance::accounts::CancelProposal {
+            governance,
+            proposal: pda::proposal(&governance, proposal_id).0,
+            canceller: *canceller,
+        }
+        .to_account_metas(None),
+        data: governance::instruction::CancelProposal { proposal_id }.data(),
+    }
+}
+
+pub fn delegate_voting_power(
+    delegator: &Pubkey,
+    user_token_account: &Pubkey,
+    delegate_to: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    Instruction {
+        program_id: governance::ID,
+        accounts: governance::accounts::DelegateVotingPower {
+            delegation: pda::delegation(delegator).0,
+            delegator: *delegator,
+            user_token_account: *user_token_account,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: governance::instruction::DelegateVotingPower {
+            delegate_to: *delegate_to,
+            amount,
+        }
+        .data(),
+    }
+}
@@ -0,0 +1,94 @@
+//! Rust client for the governance and yield-farming programs.
+//!
+//! Instruction builders live in [`governance`] and [`farm`], address
+//! derivation in [`pda`]. Account reads and transaction submission go through
+//! the [`RpcClient`] trait so the same code can drive a validator over JSON-RPC
+//! or an in-process bank in tests.
+
+use anchor_lang::prelude::Pubkey;
+use anchor_lang::solana_program::instruction::Instruction;
+use anchor_lang::AccountDeserialize;
+
+pub mod farm;
+pub mod governance;
+pub mod pda;
+
+pub use ::governance::{
+    Delegation, Governance, Proposal, ProposalStatus, ProposalType, VoteRecord, VoteType,
+};
+pub use yield_farming::{
+    EmissionController, EmissionSegment, Farm, FarmRegistry, Gauge, GaugeController, RewardVesting,
+    UserStake, VoterLock,
+};
+
+pub type Result<T> = std::result::Result<T, ClientError>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    #[error("account {0} not found")]
+    AccountNotFound(Pubkey),
+    #[error("failed to decode account {0}: {1}")]
+    Decode(Pubkey, anchor_lang::error::Error),
+    #[error("rpc error: {0}")]
+    Rpc(String),
+    #[error("failed to encode instruction: {0}")]
+    Encode(std::io::Error),
+}
+
+/// Minimal view of a cluster needed by the client.
+pub trait RpcClient {
+    /// Returns the data of `address`, or `None` if the account does not exist.
+    fn get_account_data(&self, address: &Pubkey) -> Result<Option<Vec<u8>>>;
+
+    /// Signs and submits `instructions` as a single transaction, returning an
+    /// identifier for it (the signature, for RPC-backed implementations).
+    fn send_instructions(&self, instructions: &[Instruction]) -> Result<String>;
+}
+
+/// Fetches and decodes an Anchor account, checking its discriminator.
+pub fn fetch<T: AccountDeserialize>(rpc: &impl RpcClient, address: &Pubkey) -> Result<T> {
+    let data = rpc
+        .get_account_data(address)?
+        .ok_or(ClientError::AccountNotFound(*address))?;
+    T::try_deserialize(&mut data.as_slice()).map_err(|err| ClientError::Decode(*address, err))
+}
+
+pub fn fetch_governance(rpc: &impl RpcClient) -> Result<Governance> {
+    fetch(rpc, &pda::governance().0)
+}
+
+pub fn fetch_proposal(rpc: &impl RpcClient, proposal_id: u64) -> Result<Proposal> {
+    fetch(rpc, &pda::proposal(&pda::governance().0, proposal_id).0)
+}
+
+pub fn fetch_registry(rpc: &impl RpcClient) -> Result<FarmRegistry> {
+    fetch(rpc, &pda::registry().0)
+}
+
+pub fn fetch_controller(rpc: &impl RpcClient) -> Result<EmissionController> {
+    fetch(rpc, &pda::controller().0)
+}
+
+pub fn fetch_gauge_controller(rpc: &impl RpcClient) -> Result<GaugeController> {
+    fetch(rpc, &pda::gauge_controller().0)
+}
+
+pub fn fetch_gauge(rpc: &impl RpcClient, farm: &Pubkey) -> Result<Gauge> {
+    fetch(rpc, &pda::gauge(farm).0)
+}
+
+pub fn fetch_voter_lock(rpc: &impl RpcClient, owner: &Pubkey) -> Result<VoterLock> {
+    fetch(rpc, &pda::voter_lock(owner).0)
+}
+
+pub fn fetch_farm(rpc: &impl RpcClient, farm: &Pubkey) -> Result<Farm> {
+    fetch(rpc, farm)
+}
+
+pub fn fetch_user_stake(rpc: &impl RpcClient, farm: &Pubkey, user: &Pubkey) -> Result<UserStake> {
+    fetch(rpc, &pda::user_stake(farm, user).0)
+}
+
+pub fn fetch_vesting(rpc: &impl RpcClient, farm: &Pubkey, user: &Pubkey) -> Result<RewardVesting> {
+    fetch(rpc, &pda::vesting(farm, user).0)
+}
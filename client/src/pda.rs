@@ -0,0 +1,100 @@
+//! Program derived addresses used by the governance and yield-farming programs.
+
+use anchor_lang::prelude::Pubkey;
+
+pub fn governance() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"governance"], &governance::ID)
+}
+
+pub fn proposal(governance: &Pubkey, proposal_id: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[b"proposal", governance.as_ref(), &proposal_id.to_le_bytes()],
+        &governance::ID,
+    )
+}
+
+pub fn vote(proposal: &Pubkey, voter: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[b"vote", proposal.as_ref(), voter.as_ref()],
+        &governance::ID,
+    )
+}
+
+pub fn delegation(delegator: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"delegation", delegator.as_ref()], &governance::ID)
+}
+
+pub fn registry() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"registry"], &yield_farming::ID)
+}
+
+pub fn controller() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"controller"], &yield_farming::ID)
+}
+
+pub fn controller_vault() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"controller_vault"], &yield_farming::ID)
+}
+
+pub fn gauge_controller() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"gauge_controller"], &yield_farming::ID)
+}
+
+pub fn gauge_lock_vault() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"gauge_lock_vault"], &yield_farming::ID)
+}
+
+pub fn gauge(farm: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"gauge", farm.as_ref()], &yield_farming::ID)
+}
+
+pub fn voter_lock(owner: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"voter_lock", owner.as_ref()], &yield_farming::ID)
+}
+
+/// `authority` is the farm's creator, which stays in its seeds after an
+/// authority transfer.
+pub fn farm(authority: &Pubkey, index: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[b"farm", authority.as_ref(), &index.to_le_bytes()],
+        &yield_farming::ID,
+    )
+}
+
+pub fn stake_vault(farm: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"stake_vault", farm.as_ref()], &yield_farming::ID)
+}
+
+pub fn reward_vault(farm: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"reward_vault", farm.as_ref()], &yield_farming::ID)
+}
+
+/// Vault of an extra reward stream paying `reward_token_mint`.
+pub fn stream_vault(farm: &Pubkey, reward_token_mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[b"reward_vault", farm.as_ref(), reward_token_mint.as_ref()],
+        &yield_farming::ID,
+    )
+}
+
+pub fn receipt_mint(farm: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"receipt_mint", farm.as_ref()], &yield_farming::ID)
+}
+
+pub fn user_stake(farm: &Pubkey, user: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[b"user_stake", farm.as_ref(), user.as_ref()],
+        &yield_farming::ID,
+    )
+}
+
+pub fn vesting_vault(farm: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"vesting_vault", farm.as_ref()], &yield_farming::ID)
+}
+
+pub fn vesting(farm: &Pubkey, user: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[b"vesting", farm.as_ref(), user.as_ref()],
+        &yield_farming::ID,
+    )
+}
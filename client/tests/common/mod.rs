@@ -0,0 +1,761 @@
+//! In-process bank for integration tests.
+//!
+//! Programs run natively. Each invocation lays its accounts out in the BPF
+//! loader's input format and hands them to the program's Anchor `entry`, the
+//! SPL token processor or the associated token account processor. Syscalls
+//! are served by [`BankStubs`], which answers `Clock`/`Rent` reads, captures
+//! event logs and return data, and executes CPIs the same way.
+//!
+//! As in the runtime, only wallets the bank created can sign. A read-only
+//! account cannot change. Only the owning program can change an account's
+//! data or spend its lamports, and it can only hand the account over zeroed.
+//! An invocation must leave the lamport total unchanged. A transaction may
+//! not leave an account it touched below rent exemption. Compute limits and
+//! account locking are not modelled.
+
+#![allow(dead_code)]
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::sync::Once;
+
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::entrypoint::{
+    self, ProgramResult, MAX_PERMITTED_DATA_INCREASE, NON_DUP_MARKER,
+};
+use anchor_lang::solana_program::instruction::Instruction;
+use anchor_lang::solana_program::program_pack::Pack;
+use anchor_lang::solana_program::program_stubs::{self, SyscallStubs};
+use anchor_lang::solana_program::system_instruction::{self, SystemInstruction};
+use anchor_lang::solana_program::{bpf_loader, system_program};
+use anchor_lang::Event;
+use anchor_spl::associated_token::{self, get_associated_token_address};
+use anchor_spl::token::spl_token;
+use client::{ClientError, RpcClient};
+
+/// Unix timestamp every bank starts at.
+pub const GENESIS_TIME: i64 = 1_700_000_000;
+
+pub const SOL: u64 = 1_000_000_000;
+
+#[derive(Clone, PartialEq)]
+struct StoredAccount {
+    lamports: u64,
+    data: Vec<u8>,
+    owner: Pubkey,
+    executable: bool,
+}
+
+impl StoredAccount {
+    /// What an address nothing was ever stored at holds.
+    fn empty() -> Self {
+        Self {
+            lamports: 0,
+            data: Vec::new(),
+            owner: system_program::ID,
+            executable: false,
+        }
+    }
+
+    fn read(info: &AccountInfo) -> std::result::Result<Self, ProgramError> {
+        Ok(Self {
+            lamports: info.try_lamports()?,
+            data: info.try_borrow_data()?.to_vec(),
+            owner: *info.owner,
+            executable: info.executable,
+        })
+    }
+}
+
+/// An account as a program invocation received it.
+#[derive(Clone)]
+struct FrameAccount {
+    key: Pubkey,
+    account: StoredAccount,
+    is_signer: bool,
+    is_writable: bool,
+}
+
+/// A program invocation in progress. Its accounts are the states its own
+/// changes are checked against, moved forward across each CPI it makes.
+struct Frame {
+    program_id: Pubkey,
+    accounts: Vec<FrameAccount>,
+}
+
+thread_local! {
+    static CLOCK: RefCell<Clock> = RefCell::new(Clock::default());
+    static EVENTS: RefCell<Vec<Vec<u8>>> = const { RefCell::new(Vec::new()) };
+    static FRAMES: RefCell<Vec<Frame>> = const { RefCell::new(Vec::new()) };
+    static RETURN_DATA: RefCell<Option<(Pubkey, Vec<u8>)>> = const { RefCell::new(None) };
+}
+
+pub struct Bank {
+    accounts: RefCell<HashMap<Pubkey, StoredAccount>>,
+    /// Addresses the bank holds the keys of, and so can sign for.
+    keypairs: RefCell<HashSet<Pubkey>>,
+}
+
+impl Bank {
+    pub fn new() -> Self {
+        static STUBS: Once = Once::new();
+        STUBS.call_once(|| {
+            program_stubs::set_syscall_stubs(Box::new(BankStubs));
+        });
+
+        CLOCK.with(|clock| {
+            *clock.borrow_mut() = Clock {
+                unix_timestamp: GENESIS_TIME,
+                ..Clock::default()
+            }
+        });
+        EVENTS.with(|events| events.borrow_mut().clear());
+
+        let mut accounts = HashMap::new();
+        for program_id in [
+            system_program::ID,
+            spl_token::ID,
+            associated_token::ID,
+            governance::ID,
+            yield_farming::ID,
+        ] {
+            accounts.insert(
+                program_id,
+                StoredAccount {
+                    lamports: 1,
+                    data: Vec::new(),
+                    owner: bpf_loader::ID,
+                    executable: true,
+                },
+            );
+        }
+        Self {
+            accounts: RefCell::new(accounts),
+            keypairs: RefCell::new(HashSet::new()),
+        }
+    }
+
+    pub fn now(&self) -> i64 {
+        CLOCK.with(|clock| clock.borrow().unix_timestamp)
+    }
+
+    /// Moves the clock to `unix_timestamp`; later instructions observe it.
+    pub fn warp_to(&self, unix_timestamp: i64) {
+        CLOCK.with(|clock| {
+            let mut clock = clock.borrow_mut();
+            clock.slot += 1;
+            clock.unix_timestamp = unix_timestamp;
+        });
+    }
+
+    pub fn warp_forward(&self, seconds: i64) {
+        self.warp_to(self.now() + seconds);
+    }
+
+    pub fn airdrop(&self, address: &Pubkey, lamports: u64) {
+        let mut accounts = self.accounts.borrow_mut();
+        let account = accounts
+            .entry(*address)
+            .or_insert_with(StoredAccount::empty);
+        account.lamports += lamports;
+    }
+
+    /// Returns a new address the bank can sign for.
+    pub fn create_keypair(&self) -> Pubkey {
+        let address = Pubkey::new_unique();
+        self.keypairs.borrow_mut().insert(address);
+        address
+    }
+
+    /// Returns a funded wallet the bank can sign for.
+    pub fn create_user(&self) -> Pubkey {
+        let user = self.create_keypair();
+        self.airdrop(&user, 10 * SOL);
+        user
+    }
+
+    pub fn create_mint(&self, authority: &Pubkey, decimals: u8) -> Pubkey {
+        let payer = self.create_user();
+        let mint = self.create_keypair();
+        self.process(&[
+            system_instruction::create_account(
+                &payer,
+                &mint,
+                Rent::default().minimum_balance(spl_token::state::Mint::LEN),
+                spl_token::state::Mint::LEN as u64,
+                &spl_token::ID,
+            ),
+            spl_token::instruction::initialize_mint2(
+                &spl_token::ID,
+                &mint,
+                authority,
+                None,
+                decimals,
+            )
+            .unwrap(),
+        ])
+        .expect("create mint");
+        mint
+    }
+
+    pub fn create_token_account(&self, mint: &Pubkey, owner: &Pubkey) -> Pubkey {
+        let payer = self.create_user();
+        let account = self.create_keypair();
+        self.process(&[
+            system_instruction::create_account(
+                &payer,
+                &account,
+                Rent::default().minimum_balance(spl_token::state::Account::LEN),
+                spl_token::state::Account::LEN as u64,
+                &spl_token::ID,
+            ),
+            spl_token::instruction::initialize_account3(&spl_token::ID, &account, mint, owner)
+                .unwrap(),
+        ])
+        .expect("create token account");
+        account
+    }
+
+    /// Creates `owner`'s associated token account through the associated
+    /// token account program.
+    pub fn create_associated_token_account(&self, mint: &Pubkey, owner: &Pubkey) -> Pubkey {
+        let payer = self.create_user();
+        self.process(&[
+            spl_associated_token_account::instruction::create_associated_token_account(
+                &payer,
+                owner,
+                mint,
+                &spl_token::ID,
+            ),
+        ])
+        .expect("create associated token account");
+        get_associated_token_address(owner, mint)
+    }
+
+    pub fn mint_to(&self, mint: &Pubkey, mint_authority: &Pubkey, account: &Pubkey, amount: u64) {
+        self.process(&[spl_token::instruction::mint_to(
+            &spl_token::ID,
+            mint,
+            account,
+            mint_authority,
+            &[],
+            amount,
+        )
+        .unwrap()])
+            .expect("mint to");
+    }
+
+    pub fn token_balance(&self, account: &Pubkey) -> u64 {
+        let data = self.account_data(account).expect("token account exists");
+        spl_token::state::Account::unpack(&data).unwrap().amount
+    }
+
+    pub fn account_data(&self, address: &Pubkey) -> Option<Vec<u8>> {
+        self.accounts
+            .borrow()
+            .get(address)
+            .map(|account| account.data.clone())
+    }
+
+    pub fn lamports(&self, address: &Pubkey) -> u64 {
+        self.accounts
+            .borrow()
+            .get(address)
+            .map_or(0, |account| account.lamports)
+    }
+
+    /// Decodes every `T` emitted since the bank was created.
+    pub fn events<T: Event + AnchorDeserialize>(&self) -> Vec<T> {
+        EVENTS.with(|events| {
+            events
+                .borrow()
+                .iter()
+                .filter(|data| data.starts_with(&T::DISCRIMINATOR))
+                .map(|data| T::try_from_slice(&data[8..]).expect("decode event"))
+                .collect()
+        })
+    }
+
+    /// Executes `instructions` atomically, rolling back on the first failure
+    /// or if they leave an account they touched below rent exemption.
+    pub fn process(&self, instructions: &[Instruction]) -> std::result::Result<(), ProgramError> {
+        let snapshot = self.accounts.borrow().clone();
+        let emitted = EVENTS.with(|events| events.borrow().len());
+        let result = instructions
+            .iter()
+            .try_for_each(|instruction| self.process_instruction(instruction))
+            .and_then(|()| self.check_rent(&snapshot));
+        if result.is_err() {
+            *self.accounts.borrow_mut() = snapshot;
+            EVENTS.with(|events| events.borrow_mut().truncate(emitted));
+        }
+        result
+    }
+
+    fn process_instruction(&self, instruction: &Instruction) -> ProgramResult {
+        // Only wallets the bank created can sign the transaction.
+        let keypairs = self.keypairs.borrow();
+        if instruction
+            .accounts
+            .iter()
+            .any(|meta| meta.is_signer && !keypairs.contains(&meta.pubkey))
+        {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let (keys, order) = dedup_keys(&instruction.accounts);
+        let accounts: Vec<FrameAccount> = {
+            let stored = self.accounts.borrow();
+            keys.iter()
+                .map(|key| {
+                    let (is_signer, is_writable) = privileges(&instruction.accounts, key);
+                    FrameAccount {
+                        key: *key,
+                        account: stored
+                            .get(key)
+                            .cloned()
+                            .unwrap_or_else(StoredAccount::empty),
+                        is_signer,
+                        is_writable,
+                    }
+                })
+                .collect()
+        };
+
+        let posts = invoke(
+            &instruction.program_id,
+            &accounts,
+            &order,
+            &instruction.data,
+        )?;
+
+        let mut stored = self.accounts.borrow_mut();
+        for (key, post) in keys.iter().zip(posts) {
+            if post.lamports == 0 {
+                stored.remove(key);
+            } else {
+                stored.insert(*key, post);
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails if an account changed since `snapshot` is left holding lamports
+    /// but not enough to be rent exempt, unless it already was not.
+    fn check_rent(&self, snapshot: &HashMap<Pubkey, StoredAccount>) -> ProgramResult {
+        let rent = Rent::default();
+        let is_exempt = |account: &StoredAccount| {
+            account.executable || rent.is_exempt(account.lamports, account.data.len())
+        };
+        for (key, post) in self.accounts.borrow().iter() {
+            let pre = snapshot.get(key);
+            if pre == Some(post) || is_exempt(post) {
+                continue;
+            }
+            if pre.is_none_or(is_exempt) {
+                return Err(ProgramError::AccountNotRentExempt);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RpcClient for Bank {
+    fn get_account_data(&self, address: &Pubkey) -> client::Result<Option<Vec<u8>>> {
+        Ok(self.account_data(address))
+    }
+
+    fn send_instructions(&self, instructions: &[Instruction]) -> client::Result<String> {
+        self.process(instructions)
+            .map_err(|err| ClientError::Rpc(format!("{err:?}")))?;
+        Ok(format!("slot-{}", CLOCK.with(|clock| clock.borrow().slot)))
+    }
+}
+
+/// Error a failing Anchor instruction surfaces as.
+pub fn anchor_error(code: impl Into<u32>) -> ProgramError {
+    ProgramError::Custom(code.into())
+}
+
+/// Splits `metas` into their distinct keys and, per meta, the index of its
+/// key.
+fn dedup_keys(metas: &[AccountMeta]) -> (Vec<Pubkey>, Vec<usize>) {
+    let mut keys: Vec<Pubkey> = Vec::new();
+    let order = metas
+        .iter()
+        .map(|meta| {
+            keys.iter()
+                .position(|key| *key == meta.pubkey)
+                .unwrap_or_else(|| {
+                    keys.push(meta.pubkey);
+                    keys.len() - 1
+                })
+        })
+        .collect();
+    (keys, order)
+}
+
+/// Signer and writable privileges of `key`, merged across its metas.
+fn privileges(metas: &[AccountMeta], key: &Pubkey) -> (bool, bool) {
+    metas.iter().filter(|meta| meta.pubkey == *key).fold(
+        (false, false),
+        |(is_signer, is_writable), meta| {
+            (is_signer || meta.is_signer, is_writable || meta.is_writable)
+        },
+    )
+}
+
+/// Runs `program_id` over `accounts`, passed in `order`, and returns their
+/// states afterwards once every change it made is checked to be allowed.
+fn invoke(
+    program_id: &Pubkey,
+    accounts: &[FrameAccount],
+    order: &[usize],
+    data: &[u8],
+) -> std::result::Result<Vec<StoredAccount>, ProgramError> {
+    RETURN_DATA.with(|return_data| *return_data.borrow_mut() = None);
+    FRAMES.with(|frames| {
+        frames.borrow_mut().push(Frame {
+            program_id: *program_id,
+            accounts: accounts.to_vec(),
+        })
+    });
+    let mut input = serialize(program_id, accounts, order, data);
+    let result = execute(&mut input, accounts.len(), order);
+    let frame = FRAMES.with(|frames| frames.borrow_mut().pop().expect("frame pushed above"));
+    let posts = result?;
+
+    for (pre, post) in frame.accounts.iter().zip(&posts) {
+        verify_account(program_id, pre, post)?;
+    }
+    let before: u128 = accounts
+        .iter()
+        .map(|pre| u128::from(pre.account.lamports))
+        .sum();
+    let after: u128 = posts.iter().map(|post| u128::from(post.lamports)).sum();
+    if before != after {
+        // InstructionError::UnbalancedInstruction
+        return Err(ProgramError::InvalidArgument);
+    }
+    Ok(posts)
+}
+
+/// Lays out the program input the way the BPF loader does, so that
+/// `entrypoint::deserialize` can hand it to the program.
+fn serialize(
+    program_id: &Pubkey,
+    accounts: &[FrameAccount],
+    order: &[usize],
+    data: &[u8],
+) -> Vec<u64> {
+    let mut bytes: Vec<u8> = Vec::new();
+    bytes.extend_from_slice(&(order.len() as u64).to_le_bytes());
+    for (position, index) in order.iter().enumerate() {
+        if let Some(first) = order[..position].iter().position(|other| other == index) {
+            bytes.push(first as u8);
+            bytes.extend_from_slice(&[0; 7]);
+            continue;
+        }
+        let FrameAccount {
+            key,
+            account,
+            is_signer,
+            is_writable,
+        } = &accounts[*index];
+        bytes.extend_from_slice(&[
+            NON_DUP_MARKER,
+            u8::from(*is_signer),
+            u8::from(*is_writable),
+            u8::from(account.executable),
+        ]);
+        // Original data length, filled in by `deserialize`.
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(key.as_ref());
+        bytes.extend_from_slice(account.owner.as_ref());
+        bytes.extend_from_slice(&account.lamports.to_le_bytes());
+        bytes.extend_from_slice(&(account.data.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&account.data);
+        bytes.resize(
+            (bytes.len() + MAX_PERMITTED_DATA_INCREASE).next_multiple_of(8),
+            0,
+        );
+        bytes.extend_from_slice(&u64::MAX.to_le_bytes());
+    }
+    bytes.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(data);
+    bytes.extend_from_slice(program_id.as_ref());
+
+    // Copied into words so the input is as aligned as the loader's.
+    let mut input = vec![0u64; bytes.len().div_ceil(8)];
+    for (word, chunk) in input.iter_mut().zip(bytes.chunks(8)) {
+        let mut buffer = [0; 8];
+        buffer[..chunk.len()].copy_from_slice(chunk);
+        *word = u64::from_le_bytes(buffer);
+    }
+    input
+}
+
+/// Runs the program `input` is laid out for and reads back each of its
+/// `unique` accounts.
+fn execute(
+    input: &mut [u64],
+    unique: usize,
+    order: &[usize],
+) -> std::result::Result<Vec<StoredAccount>, ProgramError> {
+    // SAFETY: `input` was laid out by `serialize`, and every `AccountInfo`
+    // borrowing it is dropped before this function returns.
+    let (program_id, infos, data) = unsafe { entrypoint::deserialize(input.as_mut_ptr().cast()) };
+    let program_id = *program_id;
+    dispatch(&program_id, &infos, data)?;
+    (0..unique)
+        .map(|index| {
+            let position = order.iter().position(|other| *other == index).unwrap();
+            StoredAccount::read(&infos[position])
+        })
+        .collect()
+}
+
+fn dispatch<'a>(
+    program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'a>],
+    data: &[u8],
+) -> ProgramResult {
+    if *program_id == system_program::ID {
+        process_system(accounts, data)
+    } else if *program_id == spl_token::ID {
+        spl_token::processor::Processor::process(program_id, accounts, data)
+    } else if *program_id == associated_token::ID {
+        spl_associated_token_account::processor::process_instruction(program_id, accounts, data)
+    } else if *program_id == governance::ID {
+        governance::entry(program_id, accounts, data)
+    } else if *program_id == yield_farming::ID {
+        yield_farming::entry(program_id, accounts, data)
+    } else {
+        Err(ProgramError::IncorrectProgramId)
+    }
+}
+
+/// Checks a change `program_id` made to an account against what the runtime
+/// allows.
+fn verify_account(program_id: &Pubkey, pre: &FrameAccount, post: &StoredAccount) -> ProgramResult {
+    let before = &pre.account;
+    if before == post {
+        return Ok(());
+    }
+    if !pre.is_writable || before.executable || post.executable {
+        // InstructionError::ReadonlyDataModified and friends
+        return Err(ProgramError::InvalidArgument);
+    }
+    let is_owner = before.owner == *program_id;
+    if post.lamports < before.lamports && !is_owner {
+        // InstructionError::ExternalAccountLamportSpend
+        return Err(ProgramError::InvalidArgument);
+    }
+    if post.data != before.data && !is_owner {
+        // InstructionError::ExternalAccountDataModified
+        return Err(ProgramError::InvalidAccountData);
+    }
+    if post.owner != before.owner && (!is_owner || post.data.iter().any(|byte| *byte != 0)) {
+        // InstructionError::ModifiedProgramId
+        return Err(ProgramError::IllegalOwner);
+    }
+    Ok(())
+}
+
+fn process_system(accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let instruction: SystemInstruction =
+        bincode::deserialize(data).map_err(|_| ProgramError::InvalidInstructionData)?;
+    match instruction {
+        SystemInstruction::CreateAccount {
+            lamports,
+            space,
+            owner,
+        } => {
+            let (from, to) = (&accounts[0], &accounts[1]);
+            if !from.is_signer || !to.is_signer {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            if to.lamports() > 0 || !to.data_is_empty() {
+                // SystemError::AccountAlreadyInUse
+                return Err(ProgramError::Custom(0));
+            }
+            move_lamports(from, to, lamports)?;
+            to.realloc(space as usize, true)?;
+            to.assign(&owner);
+        }
+        SystemInstruction::Transfer { lamports } => {
+            if !accounts[0].is_signer {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            if !accounts[0].data_is_empty() {
+                return Err(ProgramError::InvalidArgument);
+            }
+            move_lamports(&accounts[0], &accounts[1], lamports)?;
+        }
+        SystemInstruction::Allocate { space } => {
+            check_unused(&accounts[0])?;
+            accounts[0].realloc(space as usize, true)?;
+        }
+        SystemInstruction::Assign { owner } => {
+            if !accounts[0].is_signer {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            accounts[0].assign(&owner);
+        }
+        _ => return Err(ProgramError::InvalidInstructionData),
+    }
+    Ok(())
+}
+
+fn check_unused(account: &AccountInfo) -> ProgramResult {
+    if !account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if !account.data_is_empty() {
+        // SystemError::AccountAlreadyInUse
+        return Err(ProgramError::Custom(0));
+    }
+    Ok(())
+}
+
+fn move_lamports(from: &AccountInfo, to: &AccountInfo, lamports: u64) -> ProgramResult {
+    let mut from_lamports = from.try_borrow_mut_lamports()?;
+    **from_lamports = from_lamports
+        .checked_sub(lamports)
+        .ok_or(ProgramError::InsufficientFunds)?;
+    **to.try_borrow_mut_lamports()? += lamports;
+    Ok(())
+}
+
+struct BankStubs;
+
+impl SyscallStubs for BankStubs {
+    fn sol_log(&self, _message: &str) {}
+
+    fn sol_log_data(&self, fields: &[&[u8]]) {
+        EVENTS.with(|events| events.borrow_mut().push(fields.concat()));
+    }
+
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        let clock = CLOCK.with(|clock| clock.borrow().clone());
+        unsafe { *(var_addr as *mut Clock) = clock };
+        0
+    }
+
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+        unsafe { *(var_addr as *mut Rent) = Rent::default() };
+        0
+    }
+
+    fn sol_set_return_data(&self, data: &[u8]) {
+        let program_id = FRAMES.with(|frames| {
+            frames
+                .borrow()
+                .last()
+                .expect("return data set outside a program")
+                .program_id
+        });
+        RETURN_DATA.with(|return_data| {
+            *return_data.borrow_mut() = (!data.is_empty()).then(|| (program_id, data.to_vec()))
+        });
+    }
+
+    fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+        RETURN_DATA.with(|return_data| return_data.borrow().clone())
+    }
+
+    fn sol_invoke_signed(
+        &self,
+        instruction: &Instruction,
+        account_infos: &[AccountInfo],
+        signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        let caller = FRAMES.with(|frames| {
+            frames
+                .borrow()
+                .last()
+                .expect("invoked outside a program")
+                .program_id
+        });
+        let signers: Vec<Pubkey> = signers_seeds
+            .iter()
+            .map(|seeds| Pubkey::create_program_address(seeds, &caller))
+            .collect::<std::result::Result<_, _>>()
+            .map_err(|_| ProgramError::InvalidSeeds)?;
+
+        let (keys, order) = dedup_keys(&instruction.accounts);
+        let mut infos = Vec::with_capacity(keys.len());
+        let mut accounts = Vec::with_capacity(keys.len());
+        for key in &keys {
+            let info = account_infos
+                .iter()
+                .find(|info| info.key == key)
+                .ok_or(ProgramError::NotEnoughAccountKeys)?;
+            let (is_signer, is_writable) = privileges(&instruction.accounts, key);
+            if is_signer && !info.is_signer && !signers.contains(key) {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            if is_writable && !info.is_writable {
+                return Err(ProgramError::InvalidArgument);
+            }
+            infos.push(info);
+            accounts.push(FrameAccount {
+                key: *key,
+                account: StoredAccount::read(info)?,
+                is_signer,
+                is_writable,
+            });
+        }
+
+        // Check what the caller did to the accounts so far, as the runtime
+        // does before handing them on.
+        update_caller(&caller, &accounts, true)?;
+        let posts = invoke(
+            &instruction.program_id,
+            &accounts,
+            &order,
+            &instruction.data,
+        )?;
+
+        for (info, post) in infos.iter().zip(&posts) {
+            **info.try_borrow_mut_lamports()? = post.lamports;
+            if info.data_len() != post.data.len() {
+                info.realloc(post.data.len(), false)?;
+            }
+            info.try_borrow_mut_data()?.copy_from_slice(&post.data);
+            if *info.owner != post.owner {
+                info.assign(&post.owner);
+            }
+        }
+        let callee_accounts: Vec<FrameAccount> = accounts
+            .into_iter()
+            .zip(posts)
+            .map(|(account, post)| FrameAccount {
+                account: post,
+                ..account
+            })
+            .collect();
+        update_caller(&caller, &callee_accounts, false)
+    }
+}
+
+/// Moves the calling frame's view of `accounts` forward to their states in
+/// `accounts`, first checking the change if `verify` is set.
+fn update_caller(caller: &Pubkey, accounts: &[FrameAccount], verify: bool) -> ProgramResult {
+    FRAMES.with(|frames| {
+        let mut frames = frames.borrow_mut();
+        let frame = frames.last_mut().expect("invoked outside a program");
+        for account in accounts {
+            let pre = frame
+                .accounts
+                .iter_mut()
+                .find(|pre| pre.key == account.key)
+                .expect("caller holds every account it passes on");
+            if verify {
+                verify_account(caller, pre, &account.account)?;
+            }
+            pre.account = account.account.clone();
+        }
+        Ok(())
+    })
+}
@@ -0,0 +1,2213 @@
+mod common;
+
+use anchor_lang::prelude::{ProgramError, Pubkey};
+use anchor_lang::solana_program::instruction::Instruction;
+use anchor_spl::token::spl_token;
+use client::{farm as ix, governance as gov, EmissionSegment, ProposalType, VoteType};
+use common::{anchor_error, Bank};
+use governance::ErrorCode as GovernanceError;
+use yield_farming::{
+    AuthorityTransferred, AutoCompoundUpdated, CooldownUpdated, DepositCapsUpdated,
+    EmergencyWithdrawn, EmissionsDistributed, ErrorCode, Farm, FarmCreated, FarmStatusChanged,
+    FeesCollected, GaugeApplied, GaugeVoted, LockExpired, ReceiptsCheckpointed, RewardAdded,
+    RewardRateUpdated, RewardsClaimed, RewardsCompounded, RewardsVested, StakeTransferred,
+    StakedFor, StreamRewardsClaimed, TokensLocked, TokensStaked, UnstakeRequested, UserStake,
+    UserStakeClosed, VestingExited, MAX_COOLDOWN_SECONDS, MAX_EXTRA_REWARDS, SECONDS_PER_WEEK,
+};
+
+const REWARD_RATE: u64 = 1_000;
+const FARM_DURATION: i64 = 30 * 24 * 60 * 60;
+const CONTROLLER_FUNDING: u64 = 1_000_000_000;
+const EPOCH: i64 = 7 * 24 * 60 * 60;
+/// Emission of `schedule`: 100_000 + 50_000 + (8_000 + 4_000 + 2_000 + 1_000).
+const SCHEDULE_TOTAL: u64 = 165_000;
+const VOTING_PERIOD: i64 = 60;
+const EXECUTION_DELAY: i64 = 10;
+
+struct Pool {
+    bank: Bank,
+    authority: Pubkey,
+    farm: Pubkey,
+    staking_mint: Pubkey,
+    reward_mint: Pubkey,
+    vault: Pubkey,
+    reward_vault: Pubkey,
+}
+
+impl Pool {
+    /// A farm funded for its whole reward period.
+    fn new() -> Self {
+        Self::with(REWARD_RATE, REWARD_RATE * FARM_DURATION as u64)
+    }
+
+    fn with(reward_rate: u64, reward_funding: u64) -> Self {
+        Self::build(reward_rate, reward_funding, false)
+    }
+
+    /// A funded farm that pays rewards in its staking token.
+    fn single_asset() -> Self {
+        Self::build(REWARD_RATE, REWARD_RATE * FARM_DURATION as u64, true)
+    }
+
+    fn build(reward_rate: u64, reward_funding: u64, single_asset: bool) -> Self {
+        let bank = Bank::new();
+        let authority = bank.create_user();
+        let staking_mint = bank.create_mint(&authority, 6);
+        let reward_mint = if single_asset {
+            staking_mint
+        } else {
+            bank.create_mint(&authority, 6)
+        };
+        bank.process(&[
+            ix::initialize_registry(&authority),
+            ix::initialize_farm(
+                &authority,
+                0,
+                &reward_mint,
+                &staking_mint,
+                reward_rate,
+                FARM_DURATION,
+            ),
+        ])
+        .unwrap();
+
+        let farm = client::pda::farm(&authority, 0).0;
+        let vault = client::pda::stake_vault(&farm).0;
+        let reward_vault = client::pda::reward_vault(&farm).0;
+        bank.mint_to(&reward_mint, &authority, &reward_vault, reward_funding);
+        Self {
+            bank,
+            authority,
+            farm,
+            staking_mint,
+            reward_mint,
+            vault,
+            reward_vault,
+        }
+    }
+
+    /// Returns a reward token account owned by the farm authority.
+    fn reward_source(&self, amount: u64) -> Pubkey {
+        let source = self
+            .bank
+            .create_token_account(&self.reward_mint, &self.authority);
+        self.bank
+            .mint_to(&self.reward_mint, &self.authority, &source, amount);
+        source
+    }
+
+    fn notify(&self, source: &Pubkey, amount: u64) -> Result<(), ProgramError> {
+        self.bank.process(&[ix::notify_reward_amount(
+            &self.authority,
+            &self.farm,
+            source,
+            amount,
+        )])
+    }
+
+    /// Adds an extra reward stream funded for a full period at `reward_rate`
+    /// and returns its mint.
+    fn add_stream(&self, reward_rate: u64) -> Pubkey {
+        let mint = self.bank.create_mint(&self.authority, 6);
+        let source = self.bank.create_token_account(&mint, &self.authority);
+        let amount = reward_rate * FARM_DURATION as u64;
+        self.bank.mint_to(&mint, &self.authority, &source, amount);
+
+        let index = self
+            .farm_state()
+            .extra_rewards
+            .iter()
+            .position(|stream| !stream.is_active())
+            .unwrap();
+        self.bank
+            .process(&[
+                ix::add_reward_stream(&self.authority, &self.farm, &mint),
+                ix::fund_reward_stream(
+                    &self.authority,
+                    &self.farm,
+                    &source,
+                    &mint,
+                    index as u8,
+                    amount,
+                ),
+            ])
+            .unwrap();
+        mint
+    }
+
+    /// Creates another farm with the same mints and no rewards of its own.
+    fn add_farm(&self) -> Pubkey {
+        let index = client::fetch_registry(&self.bank).unwrap().farms.len() as u64;
+        self.bank
+            .process(&[ix::initialize_farm(
+                &self.authority,
+                index,
+                &self.reward_mint,
+                &self.staking_mint,
+                0,
+                FARM_DURATION,
+            )])
+            .unwrap();
+        client::pda::farm(&self.authority, index).0
+    }
+
+    /// Creates the emission controller with a well-funded vault.
+    fn start_controller(&self, emission_rate: u64) {
+        self.bank
+            .process(&[ix::initialize_controller(
+                &self.authority,
+                &self.reward_mint,
+                emission_rate,
+            )])
+            .unwrap();
+        self.bank.mint_to(
+            &self.reward_mint,
+            &self.authority,
+            &client::pda::controller_vault().0,
+            CONTROLLER_FUNDING,
+        );
+    }
+
+    /// Sets up governance over a new token and returns its mint.
+    fn start_governance(&self) -> Pubkey {
+        let gov_mint = self.bank.create_mint(&self.authority, 6);
+        self.bank
+            .process(&[gov::initialize_governance(
+                &self.authority,
+                &gov_mint,
+                VOTING_PERIOD,
+                EXECUTION_DELAY,
+                1,
+                1,
+            )])
+            .unwrap();
+        gov_mint
+    }
+
+    /// Returns a wallet with `amount` staking tokens and an empty reward account.
+    fn staker(&self, amount: u64) -> Staker {
+        let wallet = self.bank.create_user();
+        let tokens = self.bank.create_token_account(&self.staking_mint, &wallet);
+        let rewards = self.bank.create_token_account(&self.reward_mint, &wallet);
+        self.bank
+            .process(&[ix::create_receipt_account(&wallet, &self.farm, &wallet)])
+            .unwrap();
+        self.bank
+            .mint_to(&self.staking_mint, &self.authority, &tokens, amount);
+        Staker {
+            wallet,
+            tokens,
+            rewards,
+        }
+    }
+
+    /// Returns a staker with `amount` tokens, all of them staked.
+    fn staked(&self, amount: u64) -> Staker {
+        let staker = self.staker(amount);
+        self.stake(&staker, amount);
+        staker
+    }
+
+    fn farm_state(&self) -> Farm {
+        client::fetch_farm(&self.bank, &self.farm).unwrap()
+    }
+
+    fn user_stake(&self, staker: &Staker) -> UserStake {
+        client::fetch_user_stake(&self.bank, &self.farm, &staker.wallet).unwrap()
+    }
+
+    fn stake(&self, staker: &Staker, amount: u64) {
+        self.try_stake(staker, amount).unwrap();
+    }
+
+    fn try_stake(&self, staker: &Staker, amount: u64) -> Result<(), ProgramError> {
+        self.bank.process(&[ix::stake_tokens(
+            &self.farm,
+            &staker.wallet,
+            &staker.tokens,
+            amount,
+        )])
+    }
+
+    fn lock(&self, staker: &Staker, amount: u64, lock_weeks: u8) -> Result<(), ProgramError> {
+        self.bank.process(&[ix::stake_locked(
+            &self.farm,
+            &staker.wallet,
+            &staker.tokens,
+            amount,
+            lock_weeks,
+        )])
+    }
+
+    fn unstake(&self, staker: &Staker, amount: u64) -> Result<(), ProgramError> {
+        self.bank.process(&[ix::unstake_tokens(
+            &self.farm,
+            &staker.wallet,
+            &staker.tokens,
+            amount,
+        )])
+    }
+
+    /// End of a `lock_weeks` lock taken out now, at the next week start.
+    fn lock_end(&self, lock_weeks: i64) -> i64 {
+        let end = self.bank.now() + lock_weeks * SECONDS_PER_WEEK;
+        end + (-end).rem_euclid(SECONDS_PER_WEEK)
+    }
+
+    fn receipts(&self, staker: &Staker) -> u64 {
+        self.bank
+            .token_balance(&ix::receipt_account(&self.farm, &staker.wallet))
+    }
+
+    /// Moves receipt tokens with a plain SPL transfer, outside the farm.
+    fn transfer_receipts(
+        &self,
+        from: &Staker,
+        to: &Staker,
+        amount: u64,
+    ) -> Result<(), ProgramError> {
+        self.bank.process(&[spl_token::instruction::transfer(
+            &spl_token::ID,
+            &ix::receipt_account(&self.farm, &from.wallet),
+            &ix::receipt_account(&self.farm, &to.wallet),
+            &from.wallet,
+            &[],
+            amount,
+        )
+        .unwrap()])
+    }
+
+    fn claim(&self, staker: &Staker) -> Result<(), ProgramError> {
+        self.bank.process(&[ix::claim_rewards(
+            &self.farm,
+            &staker.wallet,
+            &staker.rewards,
+        )])
+    }
+}
+
+struct Staker {
+    wallet: Pubkey,
+    tokens: Pubkey,
+    rewards: Pubkey,
+}
+
+#[test]
+fn stake_claim_unstake() {
+    let pool = Pool::new();
+    let alice = pool.staked(1_000);
+    assert_eq!(pool.bank.token_balance(&pool.vault), 1_000);
+    assert_eq!(pool.bank.events::<TokensStaked>()[0].total_staked, 1_000);
+
+    pool.bank.warp_forward(100);
+    pool.claim(&alice).unwrap();
+    let claimed = pool.bank.events::<RewardsClaimed>()[0].rewards_paid;
+    assert_eq!(claimed, 100 * REWARD_RATE);
+    assert_eq!(pool.bank.token_balance(&alice.rewards), claimed);
+
+    pool.unstake(&alice, 1_000).unwrap();
+    assert_eq!(pool.bank.token_balance(&alice.tokens), 1_000);
+    assert_eq!(pool.bank.token_balance(&pool.vault), 0);
+    let farm = pool.farm_state();
+    assert_eq!(farm.total_staked, 0);
+}
+
+#[test]
+fn rewards_split_between_stakers() {
+    let pool = Pool::new();
+    let alice = pool.staked(3_000);
+    let bob = pool.staked(1_000);
+    pool.bank.warp_forward(400);
+    pool.claim(&alice).unwrap();
+    pool.claim(&bob).unwrap();
+
+    assert_eq!(pool.bank.token_balance(&alice.rewards), 300 * REWARD_RATE);
+    assert_eq!(pool.bank.token_balance(&bob.rewards), 100 * REWARD_RATE);
+}
+
+#[test]
+fn low_rate_accrues_in_large_pool() {
+    let pool = Pool::with(1, 1_000);
+    let alice = pool.staked(1_000_000_000_000);
+    let bob = pool.staked(1_000_000_000_000);
+
+    // One token per second over 2e12 staked tokens; the remainder of every
+    // update has to be carried rather than truncated to zero.
+    for _ in 0..5 {
+        pool.bank.warp_forward(1);
+        pool.stake(&bob, 0);
+    }
+    pool.claim(&alice).unwrap();
+    pool.claim(&bob).unwrap();
+    let paid = pool.bank.token_balance(&alice.rewards) + pool.bank.token_balance(&bob.rewards);
+    assert_eq!(pool.bank.token_balance(&bob.rewards), 2);
+    assert_eq!(paid, 4);
+}
+
+#[test]
+fn cannot_unstake_more_than_staked() {
+    let pool = Pool::new();
+    let alice = pool.staker(1_000);
+    pool.stake(&alice, 500);
+
+    assert_eq!(
+        pool.unstake(&alice, 501),
+        Err(anchor_error(ErrorCode::InsufficientStake))
+    );
+}
+
+#[test]
+fn claim_without_rewards_fails() {
+    let pool = Pool::new();
+    let alice = pool.staked(1_000);
+
+    assert_eq!(pool.claim(&alice), Err(anchor_error(ErrorCode::NoRewards)));
+}
+
+#[test]
+fn rewards_stop_at_period_finish() {
+    let pool = Pool::new();
+    let alice = pool.staked(1_000);
+
+    let farm = pool.farm_state();
+    assert_eq!(farm.period_finish, common::GENESIS_TIME + FARM_DURATION);
+
+    pool.bank.warp_to(farm.period_finish + 7 * 24 * 60 * 60);
+    pool.claim(&alice).unwrap();
+    assert_eq!(
+        pool.bank.token_balance(&alice.rewards),
+        REWARD_RATE * FARM_DURATION as u64
+    );
+    assert_eq!(pool.bank.token_balance(&pool.reward_vault), 0);
+
+    pool.bank.warp_forward(60);
+    assert_eq!(pool.claim(&alice), Err(anchor_error(ErrorCode::NoRewards)));
+}
+
+#[test]
+fn claim_fails_when_vault_is_short() {
+    let pool = Pool::with(REWARD_RATE, 50 * REWARD_RATE);
+    let alice = pool.staked(1_000);
+
+    pool.bank.warp_forward(100);
+    assert_eq!(
+        pool.claim(&alice),
+        Err(anchor_error(ErrorCode::InsufficientRewardFunds))
+    );
+    assert_eq!(pool.bank.token_balance(&alice.rewards), 0);
+}
+
+#[test]
+fn notify_rolls_leftover_into_new_period() {
+    let pool = Pool::new();
+    let alice = pool.staked(1_000);
+
+    pool.bank.warp_forward(FARM_DURATION / 2);
+    let top_up = REWARD_RATE * FARM_DURATION as u64;
+    let source = pool.reward_source(top_up);
+    pool.notify(&source, top_up).unwrap();
+
+    // Half a period left at the old rate plus a full period's top-up.
+    let farm = pool.farm_state();
+    assert_eq!(farm.reward_rate, REWARD_RATE * 3 / 2);
+    assert_eq!(farm.period_finish, pool.bank.now() + FARM_DURATION);
+    let added = &pool.bank.events::<RewardAdded>()[0];
+    assert_eq!(
+        (added.amount, added.reward_rate),
+        (top_up, farm.reward_rate)
+    );
+
+    pool.bank.warp_to(farm.period_finish);
+    pool.claim(&alice).unwrap();
+    assert_eq!(pool.bank.token_balance(&alice.rewards), 2 * top_up);
+    assert_eq!(pool.bank.token_balance(&pool.reward_vault), 0);
+}
+
+#[test]
+fn notify_requires_farm_authority() {
+    let pool = Pool::new();
+    let stranger = pool.bank.create_user();
+    let source = pool.reward_source(1_000);
+
+    let mut instruction = ix::notify_reward_amount(&pool.authority, &pool.farm, &source, 1_000);
+    instruction.accounts[1].pubkey = stranger;
+    assert_eq!(
+        pool.bank.process(&[instruction]),
+        Err(anchor_error(
+            anchor_lang::error::ErrorCode::ConstraintHasOne
+        ))
+    );
+}
+
+#[test]
+fn notify_rejects_rate_the_vault_cannot_cover() {
+    let pool = Pool::with(REWARD_RATE, 0);
+    let source = pool.reward_source(1_000);
+
+    assert_eq!(
+        pool.notify(&source, 1_000),
+        Err(anchor_error(ErrorCode::InsufficientRewardBalance))
+    );
+}
+
+#[test]
+fn notify_keeps_unclaimed_rewards_covered() {
+    let half_period = REWARD_RATE * FARM_DURATION as u64 / 2;
+    let pool = Pool::with(REWARD_RATE, half_period);
+    let alice = pool.staked(1_000);
+    pool.bank.warp_forward(FARM_DURATION / 2);
+
+    // Alice has not claimed, so what the vault held before is hers already
+    // and only the top-up can back the rest of the period.
+    let top_up = REWARD_RATE * FARM_DURATION as u64;
+    let source = pool.reward_source(top_up);
+    assert_eq!(
+        pool.notify(&source, top_up),
+        Err(anchor_error(ErrorCode::InsufficientRewardBalance))
+    );
+
+    // Once the rest of the old period is funded every promise can be paid.
+    pool.bank.mint_to(
+        &pool.reward_mint,
+        &pool.authority,
+        &pool.reward_vault,
+        half_period,
+    );
+    pool.notify(&source, top_up).unwrap();
+    let farm = pool.farm_state();
+    pool.bank.warp_to(farm.period_finish);
+    pool.claim(&alice).unwrap();
+    assert_eq!(pool.bank.token_balance(&alice.rewards), 2 * top_up);
+    assert_eq!(pool.bank.token_balance(&pool.reward_vault), 0);
+    let farm = pool.farm_state();
+    assert_eq!(farm.reward_owed, 0);
+}
+
+#[test]
+fn extra_streams_pay_alongside_primary_reward() {
+    let pool = Pool::new();
+    let first_mint = pool.add_stream(10);
+    let second_mint = pool.add_stream(20);
+    let alice = pool.staker(1_000);
+    let alice_first = pool.bank.create_token_account(&first_mint, &alice.wallet);
+    let alice_second = pool.bank.create_token_account(&second_mint, &alice.wallet);
+    pool.stake(&alice, 1_000);
+
+    pool.bank.warp_forward(100);
+    pool.bank
+        .process(&[ix::claim_all_rewards(
+            &pool.farm,
+            &alice.wallet,
+            &alice.rewards,
+            &[(first_mint, alice_first), (second_mint, alice_second)],
+        )])
+        .unwrap();
+
+    assert_eq!(pool.bank.token_balance(&alice.rewards), 100 * REWARD_RATE);
+    assert_eq!(pool.bank.token_balance(&alice_first), 100 * 10);
+    assert_eq!(pool.bank.token_balance(&alice_second), 100 * 20);
+    let claimed = pool.bank.events::<StreamRewardsClaimed>();
+    assert_eq!(
+        claimed.iter().map(|event| event.index).collect::<Vec<_>>(),
+        [0, 1]
+    );
+
+    // The plain claim still only pays the primary reward.
+    pool.bank.warp_forward(100);
+    pool.claim(&alice).unwrap();
+    assert_eq!(pool.bank.token_balance(&alice_first), 100 * 10);
+}
+
+#[test]
+fn reward_streams_are_capped() {
+    let pool = Pool::new();
+    for _ in 0..MAX_EXTRA_REWARDS {
+        pool.add_stream(1);
+    }
+
+    let mint = pool.bank.create_mint(&pool.authority, 6);
+    assert_eq!(
+        pool.bank
+            .process(&[ix::add_reward_stream(&pool.authority, &pool.farm, &mint)]),
+        Err(anchor_error(ErrorCode::RewardStreamsFull))
+    );
+}
+
+#[test]
+fn claim_all_rejects_foreign_stream_vault() {
+    let pool = Pool::new();
+    let mint = pool.add_stream(10);
+    let alice = pool.staker(1_000);
+    let alice_stream = pool.bank.create_token_account(&mint, &alice.wallet);
+    pool.stake(&alice, 1_000);
+    pool.bank.warp_forward(100);
+
+    let impostor = pool.bank.create_token_account(&mint, &alice.wallet);
+    pool.bank.mint_to(&mint, &pool.authority, &impostor, 1_000);
+    let mut instruction = ix::claim_all_rewards(
+        &pool.farm,
+        &alice.wallet,
+        &alice.rewards,
+        &[(mint, alice_stream)],
+    );
+    let stream_vault = instruction.accounts.len() - 2;
+    instruction.accounts[stream_vault].pubkey = impostor;
+    assert_eq!(
+        pool.bank.process(&[instruction]),
+        Err(anchor_error(ErrorCode::InvalidRewardVault))
+    );
+    assert_eq!(
+        pool.bank.process(&[ix::claim_all_rewards(
+            &pool.farm,
+            &alice.wallet,
+            &alice.rewards,
+            &[],
+        )]),
+        Err(anchor_error(ErrorCode::InvalidRewardStream))
+    );
+}
+
+#[test]
+fn refunding_a_stream_keeps_unclaimed_rewards_covered() {
+    let pool = Pool::new();
+    let mint = pool.add_stream(10);
+    let alice = pool.staker(1_000);
+    let alice_stream = pool.bank.create_token_account(&mint, &alice.wallet);
+    pool.stake(&alice, 1_000);
+    pool.bank.warp_forward(FARM_DURATION / 2);
+
+    // Half the first funding is owed to alice and only the rest rolls over.
+    let top_up = 10 * FARM_DURATION as u64;
+    let source = pool.bank.create_token_account(&mint, &pool.authority);
+    pool.bank.mint_to(&mint, &pool.authority, &source, top_up);
+    pool.bank
+        .process(&[ix::fund_reward_stream(
+            &pool.authority,
+            &pool.farm,
+            &source,
+            &mint,
+            0,
+            top_up,
+        )])
+        .unwrap();
+    let farm = pool.farm_state();
+    assert_eq!(farm.extra_rewards[0].reward_owed, top_up / 2);
+    assert_eq!(farm.extra_rewards[0].reward_rate, 15);
+
+    pool.bank.warp_to(farm.extra_rewards[0].period_finish);
+    pool.bank
+        .process(&[ix::claim_all_rewards(
+            &pool.farm,
+            &alice.wallet,
+            &alice.rewards,
+            &[(mint, alice_stream)],
+        )])
+        .unwrap();
+    assert_eq!(pool.bank.token_balance(&alice_stream), 2 * top_up);
+    let stream_vault = client::pda::stream_vault(&pool.farm, &mint).0;
+    assert_eq!(pool.bank.token_balance(&stream_vault), 0);
+    let farm = pool.farm_state();
+    assert_eq!(farm.extra_rewards[0].reward_owed, 0);
+}
+
+#[test]
+fn authority_runs_several_farms() {
+    let pool = Pool::new();
+    let other_mint = pool.bank.create_mint(&pool.authority, 6);
+    pool.bank
+        .process(&[ix::initialize_farm(
+            &pool.authority,
+            1,
+            &pool.reward_mint,
+            &other_mint,
+            REWARD_RATE,
+            FARM_DURATION,
+        )])
+        .unwrap();
+
+    let second = client::pda::farm(&pool.authority, 1).0;
+    let registry = client::fetch_registry(&pool.bank).unwrap();
+    assert_eq!(registry.farms, [pool.farm, second]);
+    let created = pool.bank.events::<FarmCreated>();
+    assert_eq!((created[1].farm, created[1].index), (second, 1));
+    assert_eq!(
+        client::fetch_farm(&pool.bank, &second)
+            .unwrap()
+            .staking_token_mint,
+        other_mint
+    );
+
+    // Indices are handed out by the registry and cannot be skipped.
+    assert!(pool
+        .bank
+        .process(&[ix::initialize_farm(
+            &pool.authority,
+            3,
+            &pool.reward_mint,
+            &other_mint,
+            REWARD_RATE,
+            FARM_DURATION,
+        )])
+        .is_err());
+}
+
+#[test]
+fn stake_requires_farm_vault() {
+    let pool = Pool::new();
+    let alice = pool.staker(1_000);
+    // A token account alice controls instead of the farm's vault.
+    let own_vault = pool
+        .bank
+        .create_token_account(&pool.staking_mint, &alice.wallet);
+
+    let mut instruction = ix::stake_tokens(&pool.farm, &alice.wallet, &alice.tokens, 1_000);
+    instruction.accounts[4].pubkey = own_vault;
+    assert_eq!(
+        pool.bank.process(&[instruction]),
+        Err(anchor_error(ErrorCode::InvalidStakeVault))
+    );
+
+    let farm = pool.farm_state();
+    assert_eq!(
+        (farm.staking_vault, farm.reward_vault),
+        (pool.vault, pool.reward_vault)
+    );
+}
+
+#[test]
+fn token_accounts_must_match_farm_mints() {
+    let pool = Pool::new();
+    let alice = pool.staked(1_000);
+    pool.bank.warp_forward(100);
+
+    // Reward tokens offered as stake, and staking tokens as reward account.
+    let reward_tokens = pool.reward_source(1_000);
+    assert_eq!(
+        pool.bank.process(&[ix::stake_tokens(
+            &pool.farm,
+            &alice.wallet,
+            &reward_tokens,
+            1
+        )]),
+        Err(anchor_error(ErrorCode::InvalidTokenMint))
+    );
+    assert_eq!(
+        pool.bank
+            .process(&[ix::claim_rewards(&pool.farm, &alice.wallet, &alice.tokens)]),
+        Err(anchor_error(ErrorCode::InvalidTokenMint))
+    );
+}
+
+#[test]
+fn paused_farm_blocks_staking() {
+    let pool = Pool::new();
+    let alice = pool.staker(1_000);
+    pool.stake(&alice, 500);
+
+    let stranger = pool.bank.create_user();
+    assert_eq!(
+        pool.bank.process(&[ix::pause_farm(&stranger, &pool.farm)]),
+        Err(anchor_error(
+            anchor_lang::error::ErrorCode::ConstraintHasOne
+        ))
+    );
+
+    pool.bank
+        .process(&[ix::pause_farm(&pool.authority, &pool.farm)])
+        .unwrap();
+    assert_eq!(
+        pool.try_stake(&alice, 500),
+        Err(anchor_error(ErrorCode::FarmNotActive))
+    );
+    // Exits and claims keep working while paused.
+    pool.bank.warp_forward(10);
+    pool.claim(&alice).unwrap();
+    pool.unstake(&alice, 100).unwrap();
+
+    pool.bank
+        .process(&[ix::unpause_farm(&pool.authority, &pool.farm)])
+        .unwrap();
+    pool.stake(&alice, 500);
+    let changes = pool.bank.events::<FarmStatusChanged>();
+    assert_eq!(
+        changes
+            .iter()
+            .map(|event| event.is_active)
+            .collect::<Vec<_>>(),
+        [false, true]
+    );
+}
+
+#[test]
+fn emergency_withdraw_forfeits_rewards() {
+    let pool = Pool::new();
+    let treasury = pool.bank.create_user();
+    let alice = pool.staked(1_000);
+    let bob = pool.staked(1_000);
+    pool.bank
+        .process(&[ix::set_fee_config(
+            &pool.authority,
+            &pool.farm,
+            0,
+            50,
+            0,
+            &treasury,
+        )])
+        .unwrap();
+    pool.bank.warp_forward(100);
+
+    // The exit skips the withdraw fee and forfeits rewards not yet settled.
+    pool.bank
+        .process(&[ix::emergency_withdraw(
+            &pool.farm,
+            &alice.wallet,
+            &alice.tokens,
+        )])
+        .unwrap();
+    assert_eq!(pool.bank.token_balance(&alice.tokens), 1_000);
+    let withdrawn = &pool.bank.events::<EmergencyWithdrawn>()[0];
+    assert_eq!(
+        (withdrawn.amount, withdrawn.rewards_forfeited),
+        (1_000, 50 * REWARD_RATE)
+    );
+    assert_eq!(pool.claim(&alice), Err(anchor_error(ErrorCode::NoRewards)));
+
+    // Bob is unaffected and earns the whole rate from here on.
+    pool.bank.warp_forward(100);
+    pool.claim(&bob).unwrap();
+    assert_eq!(pool.bank.token_balance(&bob.rewards), 150 * REWARD_RATE);
+}
+
+#[test]
+fn locked_stake_earns_boosted_share() {
+    let pool = Pool::new();
+    let alice = pool.staker(1_000);
+    let bob = pool.staker(1_000);
+
+    // A 52-week lock doubles alice's weight.
+    pool.lock(&alice, 1_000, 52).unwrap();
+    pool.stake(&bob, 1_000);
+    let locked = &pool.bank.events::<TokensLocked>()[0];
+    assert_eq!(locked.effective_amount, 2_000);
+    assert_eq!(locked.lock_end, pool.lock_end(52));
+    let farm = pool.farm_state();
+    assert_eq!(
+        (farm.total_staked, farm.total_effective_staked),
+        (2_000, 3_000)
+    );
+
+    pool.bank.warp_forward(300);
+    pool.claim(&alice).unwrap();
+    pool.claim(&bob).unwrap();
+    assert_eq!(pool.bank.token_balance(&alice.rewards), 200 * REWARD_RATE);
+    assert_eq!(pool.bank.token_balance(&bob.rewards), 100 * REWARD_RATE);
+}
+
+#[test]
+fn locked_stake_cannot_unstake_until_unlock() {
+    let pool = Pool::new();
+    let alice = pool.staker(1_000);
+    assert_eq!(
+        pool.lock(&alice, 1_000, 53),
+        Err(anchor_error(ErrorCode::InvalidLockDuration))
+    );
+    let lock_end = pool.lock_end(4);
+    pool.lock(&alice, 1_000, 4).unwrap();
+    // A shorter relock does not shorten the lock.
+    pool.lock(&alice, 0, 1).unwrap();
+
+    pool.bank.warp_to(lock_end - 1);
+    assert_eq!(
+        pool.unstake(&alice, 1),
+        Err(anchor_error(ErrorCode::StakeLocked))
+    );
+    assert_eq!(
+        pool.bank.process(&[ix::emergency_withdraw(
+            &pool.farm,
+            &alice.wallet,
+            &alice.tokens
+        )]),
+        Err(anchor_error(ErrorCode::StakeLocked))
+    );
+
+    pool.bank.warp_forward(1);
+    pool.unstake(&alice, 1_000).unwrap();
+    let farm = pool.farm_state();
+    assert_eq!(farm.total_effective_staked, 0);
+}
+
+#[test]
+fn topping_up_a_lock_restarts_it() {
+    let pool = Pool::new();
+    let alice = pool.staker(1_000_001);
+    let bob = pool.staker(1_000);
+    pool.lock(&alice, 1, 52).unwrap();
+
+    // A late top-up cannot ride out the old lock with a doubled weight.
+    pool.bank.warp_forward(52 * SECONDS_PER_WEEK - 3_600);
+    pool.stake(&alice, 1_000_000);
+    let stake = pool.user_stake(&alice);
+    assert_eq!(stake.lock_end, pool.lock_end(52));
+    pool.bank.warp_forward(3_600);
+    assert_eq!(
+        pool.unstake(&alice, 1_000_000),
+        Err(anchor_error(ErrorCode::StakeLocked))
+    );
+
+    // Nobody else can restart it.
+    assert_eq!(
+        pool.bank.process(&[ix::stake_for(
+            &pool.farm,
+            &bob.wallet,
+            &bob.tokens,
+            &alice.wallet,
+            1_000
+        )]),
+        Err(anchor_error(ErrorCode::StakeLocked))
+    );
+}
+
+#[test]
+fn anyone_can_expire_a_lapsed_lock() {
+    let pool = Pool::new();
+    let alice = pool.staker(1_000);
+    let bob = pool.staker(1_000);
+    let lock_end = pool.lock_end(1);
+    pool.lock(&alice, 1_000, 1).unwrap();
+    pool.stake(&bob, 1_000);
+
+    assert_eq!(
+        pool.bank
+            .process(&[ix::expire_lock(&pool.farm, &alice.wallet)]),
+        Err(anchor_error(ErrorCode::LockNotExpired))
+    );
+
+    pool.bank.warp_to(lock_end);
+    pool.bank
+        .process(&[ix::expire_lock(&pool.farm, &alice.wallet)])
+        .unwrap();
+    let expired = &pool.bank.events::<LockExpired>()[0];
+    assert_eq!(
+        (expired.effective_amount, expired.total_effective_staked),
+        (1_000, 2_000)
+    );
+    let stake = pool.user_stake(&alice);
+    assert_eq!((stake.user, stake.lock_weeks), (alice.wallet, 0));
+}
+
+#[test]
+fn expired_boost_is_not_paid_past_the_lock() {
+    // A 20-week reward period at the usual rate.
+    let pool = Pool::with(0, 0);
+    pool.bank.warp_forward(FARM_DURATION);
+    let duration = 20 * SECONDS_PER_WEEK;
+    let source = pool.reward_source(REWARD_RATE * duration as u64);
+    pool.bank
+        .process(&[ix::set_duration(&pool.authority, &pool.farm, duration)])
+        .unwrap();
+    pool.notify(&source, REWARD_RATE * duration as u64).unwrap();
+
+    // A 13-week lock weighs 1.25x, as much as Bob's larger plain stake.
+    let alice = pool.staker(1_000);
+    let bob = pool.staker(1_250);
+    let start = pool.bank.now();
+    let lock_end = pool.lock_end(13);
+    pool.lock(&alice, 1_000, 13).unwrap();
+    pool.stake(&bob, 1_250);
+
+    // Nobody touches the farm for two weeks after the lock ends.
+    let after = 2 * SECONDS_PER_WEEK;
+    pool.bank.warp_to(lock_end + after);
+    pool.claim(&alice).unwrap();
+    let locked = (lock_end - start) as u64 * REWARD_RATE;
+    let unlocked = after as u64 * REWARD_RATE;
+    assert_eq!(
+        pool.bank.token_balance(&alice.rewards),
+        locked / 2 + unlocked * 1_000 / 2_500
+    );
+
+    // The boost still counted after the lock went back to both of them.
+    pool.claim(&bob).unwrap();
+    pool.claim(&alice).unwrap();
+    let paid = pool.bank.token_balance(&alice.rewards) + pool.bank.token_balance(&bob.rewards);
+    assert!(locked + unlocked - paid <= 2);
+    assert!(pool.bank.token_balance(&bob.rewards) > locked / 2 + unlocked / 2);
+}
+
+#[test]
+fn cooldown_delays_withdrawal() {
+    const COOLDOWN: i64 = 7 * 24 * 60 * 60;
+    let pool = Pool::new();
+    let alice = pool.staker(1_000);
+    let bob = pool.staker(1_000);
+    pool.bank
+        .process(&[ix::set_cooldown(&pool.authority, &pool.farm, COOLDOWN)])
+        .unwrap();
+    assert_eq!(
+        pool.bank.events::<CooldownUpdated>()[0].cooldown_seconds,
+        COOLDOWN
+    );
+    for cooldown_seconds in [-1, MAX_COOLDOWN_SECONDS + 1, i64::MAX] {
+        assert_eq!(
+            pool.bank.process(&[ix::set_cooldown(
+                &pool.authority,
+                &pool.farm,
+                cooldown_seconds
+            )]),
+            Err(anchor_error(ErrorCode::InvalidCooldown))
+        );
+    }
+    pool.stake(&alice, 1_000);
+    pool.stake(&bob, 1_000);
+
+    assert_eq!(
+        pool.unstake(&alice, 1_000),
+        Err(anchor_error(ErrorCode::CooldownRequired))
+    );
+    pool.bank
+        .process(&[ix::request_unstake(
+            &pool.farm,
+            &alice.wallet,
+            &alice.tokens,
+            1_000,
+        )])
+        .unwrap();
+    let requested = &pool.bank.events::<UnstakeRequested>()[0];
+    assert_eq!(requested.unbonding_end, pool.bank.now() + COOLDOWN);
+    assert_eq!(requested.total_staked, 1_000);
+
+    // Unbonding tokens earn nothing; bob takes the whole rate.
+    pool.bank.warp_forward(COOLDOWN - 1);
+    let withdraw = ix::withdraw_unstaked(&pool.farm, &alice.wallet, &alice.tokens);
+    assert_eq!(
+        pool.bank.process(std::slice::from_ref(&withdraw)),
+        Err(anchor_error(ErrorCode::CooldownNotElapsed))
+    );
+    assert_eq!(pool.claim(&alice), Err(anchor_error(ErrorCode::NoRewards)));
+
+    pool.bank.warp_forward(1);
+    pool.bank.process(std::slice::from_ref(&withdraw)).unwrap();
+    assert_eq!(pool.bank.token_balance(&alice.tokens), 1_000);
+    assert_eq!(
+        pool.bank.process(&[withdraw]),
+        Err(anchor_error(ErrorCode::NothingUnbonding))
+    );
+    pool.claim(&bob).unwrap();
+    assert_eq!(
+        pool.bank.token_balance(&bob.rewards),
+        COOLDOWN as u64 * REWARD_RATE
+    );
+}
+
+#[test]
+fn fees_accrue_and_are_collected() {
+    let pool = Pool::new();
+    let treasury = pool.bank.create_user();
+    let stake_fees = pool
+        .bank
+        .create_token_account(&pool.staking_mint, &treasury);
+    let reward_fees = pool.bank.create_token_account(&pool.reward_mint, &treasury);
+    // 1% in, 0.5% out, 10% of rewards.
+    pool.bank
+        .process(&[ix::set_fee_config(
+            &pool.authority,
+            &pool.farm,
+            100,
+            50,
+            1_000,
+            &treasury,
+        )])
+        .unwrap();
+
+    let alice = pool.staked(10_101);
+    let staked = &pool.bank.events::<TokensStaked>()[0];
+    assert_eq!((staked.fee, staked.user_staked), (101, 10_000));
+
+    pool.bank.warp_forward(100);
+    pool.claim(&alice).unwrap();
+    assert_eq!(pool.bank.token_balance(&alice.rewards), 90 * REWARD_RATE);
+    pool.unstake(&alice, 10_000).unwrap();
+    assert_eq!(pool.bank.token_balance(&alice.tokens), 10_000 - 50);
+
+    let collect = ix::collect_fees(&pool.farm, &stake_fees, &reward_fees);
+    pool.bank.process(std::slice::from_ref(&collect)).unwrap();
+    assert_eq!(pool.bank.token_balance(&stake_fees), 151);
+    assert_eq!(pool.bank.token_balance(&reward_fees), 10 * REWARD_RATE);
+    assert_eq!(pool.bank.token_balance(&pool.vault), 0);
+    let collected = &pool.bank.events::<FeesCollected>()[0];
+    assert_eq!(
+        (collected.stake_fees, collected.reward_fees),
+        (151, 10 * REWARD_RATE)
+    );
+
+    // Fee accounts must belong to the recipient.
+    let elsewhere = pool
+        .bank
+        .create_token_account(&pool.staking_mint, &alice.wallet);
+    assert_eq!(
+        pool.bank
+            .process(&[ix::collect_fees(&pool.farm, &elsewhere, &reward_fees)]),
+        Err(anchor_error(ErrorCode::InvalidFeeAccount))
+    );
+}
+
+#[test]
+fn fees_are_capped() {
+    let pool = Pool::new();
+    let treasury = pool.bank.create_user();
+    for (deposit, withdraw, performance) in [(201, 0, 0), (0, 201, 0), (0, 0, 2_001)] {
+        assert_eq!(
+            pool.bank.process(&[ix::set_fee_config(
+                &pool.authority,
+                &pool.farm,
+                deposit,
+                withdraw,
+                performance,
+                &treasury,
+            )]),
+            Err(anchor_error(ErrorCode::FeeTooHigh))
+        );
+    }
+}
+
+#[test]
+fn deposit_caps_limit_stakes() {
+    let pool = Pool::new();
+    pool.bank
+        .process(&[ix::set_deposit_caps(
+            &pool.authority,
+            &pool.farm,
+            15_000,
+            10_000,
+        )])
+        .unwrap();
+    let caps = &pool.bank.events::<DepositCapsUpdated>()[0];
+    assert_eq!(
+        (caps.max_total_staked, caps.max_stake_per_user),
+        (15_000, 10_000)
+    );
+
+    let alice = pool.staker(20_000);
+    let bob = pool.staker(20_000);
+    pool.stake(&alice, 10_000);
+    assert_eq!(
+        pool.try_stake(&alice, 1),
+        Err(anchor_error(ErrorCode::UserStakeCapExceeded))
+    );
+    pool.stake(&bob, 5_000);
+    assert_eq!(
+        pool.try_stake(&bob, 1),
+        Err(anchor_error(ErrorCode::TotalStakeCapExceeded))
+    );
+
+    // Lifting the caps reopens deposits.
+    pool.bank
+        .process(&[ix::set_deposit_caps(&pool.authority, &pool.farm, 0, 0)])
+        .unwrap();
+    pool.stake(&alice, 10_000);
+    let farm = pool.farm_state();
+    assert_eq!(farm.total_staked, 25_000);
+}
+
+#[test]
+fn deposit_caps_cover_compounding_and_transfers() {
+    let pool = Pool::single_asset();
+    let alice = pool.staked(1_000);
+    let bob = pool.staked(500);
+
+    pool.bank
+        .process(&[ix::set_deposit_caps(&pool.authority, &pool.farm, 1_500, 0)])
+        .unwrap();
+    pool.bank.warp_forward(100);
+    assert_eq!(
+        pool.bank
+            .process(&[ix::compound(&pool.farm, &alice.wallet)]),
+        Err(anchor_error(ErrorCode::TotalStakeCapExceeded))
+    );
+
+    pool.bank
+        .process(&[ix::set_deposit_caps(&pool.authority, &pool.farm, 0, 1_000)])
+        .unwrap();
+    assert_eq!(
+        pool.bank
+            .process(&[ix::transfer_stake(&pool.farm, &alice.wallet, &bob.wallet)]),
+        Err(anchor_error(ErrorCode::UserStakeCapExceeded))
+    );
+
+    // Moved receipts are only credited up to the cap.
+    pool.claim(&alice).unwrap();
+    pool.transfer_receipts(&alice, &bob, 1_000).unwrap();
+    pool.bank
+        .process(&[
+            ix::checkpoint(&pool.farm, &alice.wallet, &bob.wallet),
+            ix::checkpoint(&pool.farm, &bob.wallet, &bob.wallet),
+        ])
+        .unwrap();
+    let checkpoint = &pool.bank.events::<ReceiptsCheckpointed>()[1];
+    assert_eq!(
+        (checkpoint.user_staked, checkpoint.total_staked),
+        (1_000, 1_000)
+    );
+}
+
+#[test]
+fn compound_restakes_rewards() {
+    let pool = Pool::single_asset();
+    let alice = pool.staked(1_000);
+
+    pool.bank.warp_forward(100);
+    pool.bank
+        .process(&[ix::compound(&pool.farm, &alice.wallet)])
+        .unwrap();
+    let compounded = &pool.bank.events::<RewardsCompounded>()[0];
+    assert_eq!(compounded.amount, 100 * REWARD_RATE);
+    assert_eq!(compounded.user_staked, 1_000 + 100 * REWARD_RATE);
+    assert_eq!(
+        pool.bank.token_balance(&pool.vault),
+        1_000 + 100 * REWARD_RATE
+    );
+    let stake = pool.user_stake(&alice);
+    assert_eq!(stake.rewards_earned, 0);
+
+    // Only farms paying their staking token can compound.
+    let pool = Pool::new();
+    let bob = pool.staked(1_000);
+    pool.bank.warp_forward(100);
+    assert_eq!(
+        pool.bank.process(&[ix::compound(&pool.farm, &bob.wallet)]),
+        Err(anchor_error(ErrorCode::NotSingleAsset))
+    );
+}
+
+#[test]
+fn keepers_compound_opted_in_stakes() {
+    let pool = Pool::single_asset();
+    let alice = pool.staked(1_000);
+    let keeper = pool.staker(0);
+    pool.bank.warp_forward(100);
+
+    let crank = ix::compound_for(&pool.farm, &alice.wallet, &keeper.wallet, &keeper.rewards);
+    assert_eq!(
+        pool.bank.process(std::slice::from_ref(&crank)),
+        Err(anchor_error(ErrorCode::AutoCompoundDisabled))
+    );
+
+    pool.bank
+        .process(&[ix::set_auto_compound(&pool.farm, &alice.wallet, true)])
+        .unwrap();
+    assert!(pool.bank.events::<AutoCompoundUpdated>()[0].enabled);
+    pool.bank.process(&[crank]).unwrap();
+
+    // The keeper earns 0.5% of the compounded rewards.
+    let compounded = &pool.bank.events::<RewardsCompounded>()[0];
+    assert_eq!((compounded.amount, compounded.tip), (99_500, 500));
+    assert_eq!(compounded.keeper, keeper.wallet);
+    assert_eq!(pool.bank.token_balance(&keeper.rewards), 500);
+    assert_eq!(pool.bank.token_balance(&pool.vault), 1_000 + 99_500);
+}
+
+#[test]
+fn vested_rewards_release_linearly() {
+    let pool = Pool::new();
+    pool.bank
+        .process(&[ix::set_vesting(
+            &pool.authority,
+            &pool.farm,
+            &pool.reward_mint,
+            1_000,
+            0,
+        )])
+        .unwrap();
+    let alice = pool.staked(1_000);
+    pool.bank.warp_forward(100);
+
+    assert_eq!(
+        pool.claim(&alice),
+        Err(anchor_error(ErrorCode::RewardsMustVest))
+    );
+    pool.bank
+        .process(&[ix::vest_rewards(&pool.farm, &alice.wallet)])
+        .unwrap();
+    let vested = &pool.bank.events::<RewardsVested>()[0];
+    assert_eq!(vested.amount, 100 * REWARD_RATE);
+    assert_eq!(vested.vesting_end, pool.bank.now() + 1_000);
+
+    let withdraw = ix::withdraw_vested(&pool.farm, &alice.wallet, &alice.rewards);
+    pool.bank.warp_forward(500);
+    pool.bank.process(std::slice::from_ref(&withdraw)).unwrap();
+    assert_eq!(pool.bank.token_balance(&alice.rewards), 50 * REWARD_RATE);
+
+    pool.bank.warp_forward(500);
+    pool.bank.process(std::slice::from_ref(&withdraw)).unwrap();
+    assert_eq!(pool.bank.token_balance(&alice.rewards), 100 * REWARD_RATE);
+    assert_eq!(
+        pool.bank.process(&[withdraw]),
+        Err(anchor_error(ErrorCode::NothingVested))
+    );
+}
+
+#[test]
+fn early_exit_penalty_goes_to_stakers() {
+    let pool = Pool::new();
+    assert_eq!(
+        pool.bank.process(&[ix::set_vesting(
+            &pool.authority,
+            &pool.farm,
+            &pool.reward_mint,
+            1_000,
+            5_001,
+        )]),
+        Err(anchor_error(ErrorCode::PenaltyTooHigh))
+    );
+    pool.bank
+        .process(&[ix::set_vesting(
+            &pool.authority,
+            &pool.farm,
+            &pool.reward_mint,
+            1_000,
+            5_000,
+        )])
+        .unwrap();
+    let alice = pool.staked(1_000);
+    let bob = pool.staked(1_000);
+    pool.bank.warp_forward(100);
+
+    // Alice leaves the farm and exits her schedule right away, losing half.
+    pool.bank
+        .process(&[
+            ix::vest_rewards(&pool.farm, &alice.wallet),
+            ix::unstake_tokens(&pool.farm, &alice.wallet, &alice.tokens, 1_000),
+            ix::exit_vesting(&pool.farm, &alice.wallet, &alice.rewards),
+        ])
+        .unwrap();
+    let exited = &pool.bank.events::<VestingExited>()[0];
+    assert_eq!((exited.amount, exited.penalty), (25_000, 25_000));
+    assert_eq!(pool.bank.token_balance(&alice.rewards), 25_000);
+
+    // Bob is the only staker left and receives the whole penalty.
+    pool.bank
+        .process(&[ix::vest_rewards(&pool.farm, &bob.wallet)])
+        .unwrap();
+    assert_eq!(pool.bank.events::<RewardsVested>()[1].amount, 75_000);
+}
+
+#[test]
+fn receipts_are_minted_and_burned_with_stake() {
+    let pool = Pool::new();
+    let alice = pool.staked(1_000);
+    assert_eq!(pool.receipts(&alice), 1_000);
+
+    pool.unstake(&alice, 400).unwrap();
+    assert_eq!(pool.receipts(&alice), 600);
+    assert_eq!(pool.bank.token_balance(&alice.tokens), 400);
+}
+
+#[test]
+fn rewards_follow_transferred_receipts() {
+    let pool = Pool::new();
+    let alice = pool.staked(1_000);
+    let bob = pool.staker(0);
+    pool.bank.warp_forward(100);
+
+    // Alice claims before moving her receipts, then Bob checkpoints both
+    // sides of the transfer.
+    pool.claim(&alice).unwrap();
+    pool.transfer_receipts(&alice, &bob, 1_000).unwrap();
+    pool.bank
+        .process(&[
+            ix::checkpoint(&pool.farm, &alice.wallet, &bob.wallet),
+            ix::checkpoint(&pool.farm, &bob.wallet, &bob.wallet),
+        ])
+        .unwrap();
+    let checkpoints = pool.bank.events::<ReceiptsCheckpointed>();
+    assert_eq!(checkpoints[0].user_staked, 0);
+    assert_eq!(checkpoints[1].user_staked, 1_000);
+    assert_eq!(checkpoints[1].total_staked, 1_000);
+
+    pool.bank.warp_forward(100);
+    pool.claim(&bob).unwrap();
+    assert_eq!(pool.bank.token_balance(&alice.rewards), 100 * REWARD_RATE);
+    assert_eq!(pool.bank.token_balance(&bob.rewards), 100 * REWARD_RATE);
+
+    // The stake now belongs to Bob.
+    assert_eq!(
+        pool.unstake(&alice, 1),
+        Err(anchor_error(ErrorCode::InsufficientStake))
+    );
+    pool.unstake(&bob, 1_000).unwrap();
+    assert_eq!(pool.bank.token_balance(&bob.tokens), 1_000);
+}
+
+#[test]
+fn moved_receipts_never_earn_twice() {
+    let pool = Pool::new();
+    let alice = pool.staked(1_000);
+    let bob = pool.staker(0);
+    let carol = pool.staked(1_000);
+
+    // Only the receiving side is checkpointed, so Alice's position still
+    // counts the receipts and Bob is not credited yet.
+    pool.transfer_receipts(&alice, &bob, 1_000).unwrap();
+    pool.bank
+        .process(&[ix::checkpoint(&pool.farm, &bob.wallet, &bob.wallet)])
+        .unwrap();
+    let checkpoint = &pool.bank.events::<ReceiptsCheckpointed>()[0];
+    assert_eq!(
+        (checkpoint.user_staked, checkpoint.total_staked),
+        (0, 2_000)
+    );
+
+    // Settling Alice pays her nothing for the receipts she gave away. What
+    // they earned goes back to the pool, which leaves only Carol, and Bob
+    // can now be credited.
+    pool.bank.warp_forward(300);
+    assert_eq!(pool.claim(&alice), Err(anchor_error(ErrorCode::NoRewards)));
+    pool.bank
+        .process(&[
+            ix::checkpoint(&pool.farm, &alice.wallet, &carol.wallet),
+            ix::checkpoint(&pool.farm, &bob.wallet, &carol.wallet),
+        ])
+        .unwrap();
+    let checkpoints = pool.bank.events::<ReceiptsCheckpointed>();
+    assert_eq!(checkpoints[1].user_staked, 0);
+    assert_eq!(
+        (checkpoints[2].user_staked, checkpoints[2].total_staked),
+        (1_000, 2_000)
+    );
+    pool.claim(&carol).unwrap();
+    assert_eq!(pool.bank.token_balance(&carol.rewards), 300 * REWARD_RATE);
+
+    pool.bank.warp_forward(100);
+    pool.claim(&bob).unwrap();
+    pool.claim(&carol).unwrap();
+    assert_eq!(pool.bank.token_balance(&bob.rewards), 50 * REWARD_RATE);
+    assert_eq!(pool.bank.token_balance(&carol.rewards), 350 * REWARD_RATE);
+    let farm = pool.farm_state();
+    assert_eq!(farm.total_staked, pool.bank.token_balance(&pool.vault));
+}
+
+#[test]
+fn unsettled_transfers_hand_rewards_back_to_the_pool() {
+    let pool = Pool::new();
+    let alice = pool.staked(1_000);
+    let bob = pool.staker(0);
+    let carol = pool.staked(1_000);
+    pool.bank.warp_forward(100);
+
+    // Nobody checkpoints the transfer, so Alice keeps earning on the moved
+    // receipts until her next claim.
+    pool.transfer_receipts(&alice, &bob, 500).unwrap();
+    pool.bank.warp_forward(100);
+
+    // Her claim settles her old balance and forfeits the moved half of it,
+    // 50_000, to the stakers still counted: a third to her and two to Carol.
+    pool.claim(&alice).unwrap();
+    assert_eq!(pool.bank.token_balance(&alice.rewards), 66_666);
+    pool.bank
+        .process(&[ix::checkpoint(&pool.farm, &bob.wallet, &bob.wallet)])
+        .unwrap();
+    let checkpoint = &pool.bank.events::<ReceiptsCheckpointed>()[0];
+    assert_eq!(
+        (checkpoint.user_staked, checkpoint.total_staked),
+        (500, 2_000)
+    );
+
+    pool.bank.warp_forward(100);
+    pool.claim(&alice).unwrap();
+    pool.claim(&bob).unwrap();
+    pool.claim(&carol).unwrap();
+    assert_eq!(pool.bank.token_balance(&alice.rewards), 91_666);
+    assert_eq!(pool.bank.token_balance(&bob.rewards), 25 * REWARD_RATE);
+    assert_eq!(pool.bank.token_balance(&carol.rewards), 183_333);
+    let farm = pool.farm_state();
+    assert!(farm.reward_owed <= 1);
+}
+
+#[test]
+fn locked_receipts_cannot_move() {
+    let pool = Pool::new();
+    let alice = pool.staker(1_000);
+    let bob = pool.staker(0);
+    let lock_end = pool.lock_end(1);
+    pool.lock(&alice, 1_000, 1).unwrap();
+    assert_eq!(
+        pool.transfer_receipts(&alice, &bob, 1_000),
+        Err(ProgramError::Custom(
+            spl_token::error::TokenError::AccountFrozen as u32
+        ))
+    );
+
+    pool.bank.warp_to(lock_end);
+    pool.bank
+        .process(&[ix::checkpoint(&pool.farm, &alice.wallet, &bob.wallet)])
+        .unwrap();
+    pool.transfer_receipts(&alice, &bob, 1_000).unwrap();
+    assert_eq!(pool.receipts(&bob), 1_000);
+}
+
+#[test]
+fn stake_for_credits_the_beneficiary() {
+    let pool = Pool::new();
+    let treasury = pool.staker(1_000);
+    let bob = pool.staker(0);
+    pool.bank
+        .process(&[ix::stake_for(
+            &pool.farm,
+            &treasury.wallet,
+            &treasury.tokens,
+            &bob.wallet,
+            1_000,
+        )])
+        .unwrap();
+    let staked = &pool.bank.events::<StakedFor>()[0];
+    assert_eq!((staked.funder, staked.user), (treasury.wallet, bob.wallet));
+    assert_eq!(pool.bank.token_balance(&treasury.tokens), 0);
+    assert_eq!(pool.receipts(&bob), 1_000);
+
+    pool.bank.warp_forward(100);
+    pool.claim(&bob).unwrap();
+    assert_eq!(pool.bank.token_balance(&bob.rewards), 100 * REWARD_RATE);
+    pool.unstake(&bob, 1_000).unwrap();
+    assert_eq!(pool.bank.token_balance(&bob.tokens), 1_000);
+}
+
+#[test]
+fn transfer_stake_moves_position_and_lock() {
+    let pool = Pool::new();
+    let alice = pool.staker(1_000);
+    let bob = pool.staker(0);
+    let lock_end = pool.lock_end(52);
+    pool.lock(&alice, 1_000, 52).unwrap();
+    pool.bank.warp_forward(100);
+
+    assert_eq!(
+        pool.bank
+            .process(&[ix::transfer_stake(&pool.farm, &alice.wallet, &alice.wallet)]),
+        Err(anchor_error(ErrorCode::SameOwner))
+    );
+    pool.bank
+        .process(&[ix::transfer_stake(&pool.farm, &alice.wallet, &bob.wallet)])
+        .unwrap();
+    let transferred = &pool.bank.events::<StakeTransferred>()[0];
+    assert_eq!(
+        (transferred.amount, transferred.lock_end),
+        (1_000, lock_end)
+    );
+    assert_eq!(pool.receipts(&alice), 0);
+    assert_eq!(pool.receipts(&bob), 1_000);
+
+    // Rewards earned before the transfer stay with Alice.
+    pool.bank.warp_forward(100);
+    pool.claim(&alice).unwrap();
+    pool.claim(&bob).unwrap();
+    assert_eq!(pool.bank.token_balance(&alice.rewards), 100 * REWARD_RATE);
+    assert_eq!(pool.bank.token_balance(&bob.rewards), 100 * REWARD_RATE);
+    assert_eq!(
+        pool.unstake(&bob, 1_000),
+        Err(anchor_error(ErrorCode::StakeLocked))
+    );
+
+    pool.bank.warp_to(lock_end);
+    pool.unstake(&bob, 1_000).unwrap();
+    assert_eq!(pool.bank.token_balance(&bob.tokens), 1_000);
+}
+
+#[test]
+fn transfer_stake_respects_the_recipients_lock() {
+    let pool = Pool::new();
+    let alice = pool.staker(1);
+    let bob = pool.staked(1_000_000);
+    let carol = pool.staker(1_000);
+    pool.lock(&alice, 1, 52).unwrap();
+    pool.stake(&carol, 1_000);
+
+    // Alice's lock cannot reach Bob's unlocked stake, nor can Carol's
+    // unlocked stake pick up Alice's boost.
+    for (from, to) in [(&alice, &bob), (&carol, &alice)] {
+        assert_eq!(
+            pool.bank
+                .process(&[ix::transfer_stake(&pool.farm, &from.wallet, &to.wallet)]),
+            Err(anchor_error(ErrorCode::IncompatibleLock))
+        );
+    }
+    pool.unstake(&bob, 1_000_000).unwrap();
+
+    // A longer lock on the same terms can take the stake.
+    pool.bank.warp_forward(100);
+    pool.lock(&carol, 0, 52).unwrap();
+    pool.bank
+        .process(&[ix::transfer_stake(&pool.farm, &alice.wallet, &carol.wallet)])
+        .unwrap();
+    let stake = pool.user_stake(&carol);
+    assert_eq!((stake.amount, stake.effective_amount), (1_001, 2_002));
+}
+
+#[test]
+fn empty_position_can_be_closed() {
+    let pool = Pool::new();
+    let alice = pool.staked(1_000);
+    let position = client::pda::user_stake(&pool.farm, &alice.wallet).0;
+    let rent = pool.bank.lamports(&position);
+    let close = ix::close_user_stake(&pool.farm, &alice.wallet);
+
+    pool.bank.warp_forward(100);
+    pool.unstake(&alice, 1_000).unwrap();
+    // Rewards are still waiting to be claimed.
+    assert_eq!(
+        pool.bank.process(std::slice::from_ref(&close)),
+        Err(anchor_error(ErrorCode::PositionNotEmpty))
+    );
+
+    pool.claim(&alice).unwrap();
+    let before = pool.bank.lamports(&alice.wallet);
+    pool.bank.process(&[close]).unwrap();
+    assert_eq!(pool.bank.lamports(&position), 0);
+    assert_eq!(pool.bank.lamports(&alice.wallet), before + rent);
+    assert_eq!(pool.bank.events::<UserStakeClosed>()[0].user, alice.wallet);
+}
+
+#[test]
+fn full_unstake_can_close_the_position() {
+    let pool = Pool::new();
+    let alice = pool.staked(1_000);
+    let position = client::pda::user_stake(&pool.farm, &alice.wallet).0;
+    pool.bank.warp_forward(100);
+
+    // Unclaimed rewards keep the position open without failing the exit.
+    pool.bank
+        .process(&[ix::unstake_and_close(
+            &pool.farm,
+            &alice.wallet,
+            &alice.tokens,
+            500,
+        )])
+        .unwrap();
+    pool.bank
+        .process(&[ix::unstake_and_close(
+            &pool.farm,
+            &alice.wallet,
+            &alice.tokens,
+            500,
+        )])
+        .unwrap();
+    assert_eq!(pool.bank.token_balance(&alice.tokens), 1_000);
+    assert!(pool.bank.lamports(&position) > 0);
+    pool.claim(&alice).unwrap();
+    assert_eq!(pool.bank.token_balance(&alice.rewards), 100 * REWARD_RATE);
+
+    // With nothing left to claim, the full exit closes it.
+    pool.stake(&alice, 1_000);
+    pool.bank
+        .process(&[ix::unstake_and_close(
+            &pool.farm,
+            &alice.wallet,
+            &alice.tokens,
+            1_000,
+        )])
+        .unwrap();
+    assert_eq!(pool.bank.lamports(&position), 0);
+
+    // Staking again opens a fresh position.
+    pool.stake(&alice, 1_000);
+    let stake = pool.user_stake(&alice);
+    assert_eq!(stake.amount, 1_000);
+}
+
+#[test]
+fn authority_transfer_takes_two_steps() {
+    let pool = Pool::new();
+    let successor = pool.bank.create_user();
+    let stranger = pool.bank.create_user();
+    let has_one = anchor_error(anchor_lang::error::ErrorCode::ConstraintHasOne);
+
+    pool.bank
+        .process(&[ix::set_authority(&pool.authority, &pool.farm, &successor)])
+        .unwrap();
+    // Nothing changes until the successor accepts.
+    assert_eq!(
+        pool.bank
+            .process(&[ix::set_cooldown(&successor, &pool.farm, 60)]),
+        Err(has_one.clone())
+    );
+    assert_eq!(
+        pool.bank
+            .process(&[ix::accept_authority(&pool.farm, &stranger)]),
+        Err(anchor_error(ErrorCode::NotPendingAuthority))
+    );
+
+    pool.bank
+        .process(&[ix::accept_authority(&pool.farm, &successor)])
+        .unwrap();
+    let farm = pool.farm_state();
+    assert_eq!(farm.authority, successor);
+    assert_eq!(farm.pending_authority, Pubkey::default());
+    assert_eq!(farm.creator, pool.authority);
+    assert_eq!(
+        pool.bank.events::<AuthorityTransferred>()[0].previous_authority,
+        pool.authority
+    );
+    assert_eq!(
+        pool.bank
+            .process(&[ix::set_cooldown(&pool.authority, &pool.farm, 60)]),
+        Err(has_one)
+    );
+    pool.bank
+        .process(&[ix::set_cooldown(&successor, &pool.farm, 60)])
+        .unwrap();
+
+    // The farm keeps its address, so positions are unaffected.
+    pool.staked(1_000);
+    assert_eq!(pool.bank.token_balance(&pool.vault), 1_000);
+}
+
+#[test]
+fn reward_rate_and_duration_can_change() {
+    let pool = Pool::new();
+    let alice = pool.staked(1_000);
+    pool.bank.warp_forward(100);
+
+    // Twice the rate would outrun the funding.
+    assert_eq!(
+        pool.bank.process(&[ix::set_reward_rate(
+            &pool.authority,
+            &pool.farm,
+            2 * REWARD_RATE
+        )]),
+        Err(anchor_error(ErrorCode::InsufficientRewardBalance))
+    );
+    pool.bank
+        .process(&[ix::set_reward_rate(
+            &pool.authority,
+            &pool.farm,
+            REWARD_RATE / 2,
+        )])
+        .unwrap();
+    assert_eq!(
+        pool.bank.events::<RewardRateUpdated>()[0].reward_rate,
+        REWARD_RATE / 2
+    );
+
+    // Time before the change accrued at the old rate.
+    pool.bank.warp_forward(100);
+    pool.claim(&alice).unwrap();
+    assert_eq!(
+        pool.bank.token_balance(&alice.rewards),
+        100 * REWARD_RATE + 100 * (REWARD_RATE / 2)
+    );
+
+    let set_duration = ix::set_duration(&pool.authority, &pool.farm, 7 * 24 * 60 * 60);
+    assert_eq!(
+        pool.bank.process(std::slice::from_ref(&set_duration)),
+        Err(anchor_error(ErrorCode::RewardPeriodActive))
+    );
+    let period_finish = client::fetch_farm(&pool.bank, &pool.farm)
+        .unwrap()
+        .period_finish;
+    pool.bank.warp_to(period_finish);
+    pool.bank.process(&[set_duration]).unwrap();
+    let source = pool.reward_source(1_000);
+    pool.notify(&source, 1_000).unwrap();
+    let farm = pool.farm_state();
+    assert_eq!(farm.period_finish, pool.bank.now() + 7 * 24 * 60 * 60);
+}
+
+#[test]
+fn governance_controls_emissions_after_handover() {
+    let pool = Pool::new();
+    let governance = client::pda::governance().0;
+    let gov_mint = pool.start_governance();
+    let voter = pool.bank.create_user();
+    let votes = pool.bank.create_token_account(&gov_mint, &voter);
+    pool.bank.mint_to(&gov_mint, &pool.authority, &votes, 1_000);
+
+    // Passes and queues a proposal running `instruction`.
+    let pass = |instruction: &Instruction| {
+        let proposal_id = client::fetch_governance(&pool.bank).unwrap().proposal_count;
+        pool.bank
+            .process(&[
+                gov::create_proposal(
+                    &voter,
+                    &votes,
+                    proposal_id,
+                    "Farm change".to_string(),
+                    String::new(),
+                    ProposalType::ParameterChange,
+                    gov::proposal_instruction(instruction).unwrap(),
+                ),
+                gov::cast_vote(&voter, &votes, proposal_id, VoteType::For, 1_000),
+            ])
+            .unwrap();
+        pool.bank.warp_forward(VOTING_PERIOD + 1);
+        pool.bank
+            .process(&[gov::queue_proposal(proposal_id)])
+            .unwrap();
+        pool.bank.warp_forward(EXECUTION_DELAY);
+        proposal_id
+    };
+
+    pool.bank
+        .process(&[ix::set_authority(&pool.authority, &pool.farm, &governance)])
+        .unwrap();
+    let accept = ix::accept_authority(&pool.farm, &governance);
+    let proposal_id = pass(&accept);
+    // The executor must supply the accounts the DAO voted on.
+    assert_eq!(
+        pool.bank
+            .process(&[gov::execute_proposal(&voter, proposal_id)]),
+        Err(anchor_error(GovernanceError::InvalidExecutionAccounts))
+    );
+    pool.bank
+        .process(&[gov::execute_proposal_instruction(
+            &voter,
+            proposal_id,
+            &accept,
+        )])
+        .unwrap();
+    let farm = pool.farm_state();
+    assert_eq!(farm.authority, governance);
+
+    // Emissions now only change through a vote.
+    assert_eq!(
+        pool.bank.process(&[ix::set_reward_rate(
+            &pool.authority,
+            &pool.farm,
+            REWARD_RATE / 2
+        )]),
+        Err(anchor_error(
+            anchor_lang::error::ErrorCode::ConstraintHasOne
+        ))
+    );
+    let set_rate = ix::set_reward_rate(&governance, &pool.farm, REWARD_RATE / 2);
+    let proposal_id = pass(&set_rate);
+    pool.bank
+        .process(&[gov::execute_proposal_instruction(
+            &voter,
+            proposal_id,
+            &set_rate,
+        )])
+        .unwrap();
+    let farm = pool.farm_state();
+    assert_eq!(farm.reward_rate, REWARD_RATE / 2);
+}
+
+#[test]
+fn controller_splits_emissions_by_alloc_point() {
+    let pool = Pool::with(0, 0);
+    let second = pool.add_farm();
+    let controller_vault = client::pda::controller_vault().0;
+    pool.start_controller(300);
+
+    let alice = pool.staker(1_000);
+    let bob = pool.staker(1_000);
+    pool.bank
+        .create_associated_token_account(&client::pda::receipt_mint(&second).0, &bob.wallet);
+    pool.stake(&alice, 1_000);
+    pool.bank
+        .process(&[
+            ix::stake_tokens(&second, &bob.wallet, &bob.tokens, 1_000),
+            ix::set_alloc_point(&pool.authority, &pool.farm, 1),
+            ix::set_alloc_point(&pool.authority, &second, 2),
+        ])
+        .unwrap();
+    let update = ix::mass_update_pools(&[pool.farm, second]);
+
+    pool.bank.warp_forward(100);
+    pool.bank.process(std::slice::from_ref(&update)).unwrap();
+    let distributed = pool.bank.events::<EmissionsDistributed>();
+    assert_eq!(
+        (distributed[0].farm, distributed[0].amount),
+        (pool.farm, 10_000)
+    );
+    assert_eq!(
+        (distributed[1].farm, distributed[1].amount),
+        (second, 20_000)
+    );
+    assert_eq!(pool.bank.token_balance(&pool.reward_vault), 10_000);
+
+    // Rebalancing pays the second pool under its old share first.
+    pool.bank.warp_forward(100);
+    pool.bank
+        .process(&[ix::set_alloc_point(&pool.authority, &second, 1)])
+        .unwrap();
+    assert_eq!(pool.bank.events::<EmissionsDistributed>()[2].amount, 20_000);
+    assert_eq!(
+        client::fetch_controller(&pool.bank)
+            .unwrap()
+            .total_alloc_point,
+        2
+    );
+
+    pool.bank.warp_forward(100);
+    pool.bank.process(&[update]).unwrap();
+    let distributed = pool.bank.events::<EmissionsDistributed>();
+    assert_eq!(distributed[3].amount, 10_000 + 15_000);
+    assert_eq!(distributed[4].amount, 15_000);
+
+    pool.claim(&alice).unwrap();
+    assert_eq!(pool.bank.token_balance(&alice.rewards), 35_000);
+    assert_eq!(
+        pool.bank.token_balance(&controller_vault),
+        CONTROLLER_FUNDING - 90_000
+    );
+}
+
+#[test]
+fn underfunded_controller_carries_emissions_forward() {
+    let pool = Pool::with(0, 0);
+    let controller_vault = client::pda::controller_vault().0;
+    pool.bank
+        .process(&[ix::initialize_controller(
+            &pool.authority,
+            &pool.reward_mint,
+            100,
+        )])
+        .unwrap();
+    pool.bank
+        .mint_to(&pool.reward_mint, &pool.authority, &controller_vault, 5_000);
+    let alice = pool.staked(1_000);
+    pool.bank
+        .process(&[ix::set_alloc_point(&pool.authority, &pool.farm, 1)])
+        .unwrap();
+
+    // The authority can still rebalance with the vault short.
+    pool.bank.warp_forward(100);
+    pool.bank
+        .process(&[ix::set_alloc_point(&pool.authority, &pool.farm, 0)])
+        .unwrap();
+    assert_eq!(pool.bank.events::<EmissionsDistributed>()[0].amount, 5_000);
+    let farm = pool.farm_state();
+    assert_eq!(farm.unpaid_emissions, 5_000);
+
+    pool.bank
+        .mint_to(&pool.reward_mint, &pool.authority, &controller_vault, 5_000);
+    pool.bank
+        .process(&[ix::mass_update_pools(&[pool.farm])])
+        .unwrap();
+    assert_eq!(pool.bank.events::<EmissionsDistributed>()[1].amount, 5_000);
+    pool.claim(&alice).unwrap();
+    assert_eq!(pool.bank.token_balance(&alice.rewards), 10_000);
+    assert_eq!(pool.bank.token_balance(&controller_vault), 0);
+}
+
+#[test]
+fn emissions_to_an_empty_farm_wait_for_stakers() {
+    let pool = Pool::with(0, 0);
+    let controller_vault = client::pda::controller_vault().0;
+    pool.start_controller(100);
+    pool.bank
+        .process(&[ix::set_alloc_point(&pool.authority, &pool.farm, 1)])
+        .unwrap();
+
+    pool.bank.warp_forward(100);
+    let alice = pool.staked(1_000);
+    let farm = pool.farm_state();
+    assert_eq!(farm.unpaid_emissions, 0);
+    pool.bank.warp_forward(100);
+    pool.bank
+        .process(&[ix::mass_update_pools(&[pool.farm])])
+        .unwrap();
+
+    pool.claim(&alice).unwrap();
+    assert_eq!(pool.bank.token_balance(&alice.rewards), 20_000);
+    assert_eq!(
+        pool.bank.token_balance(&controller_vault),
+        CONTROLLER_FUNDING - 20_000
+    );
+}
+
+#[test]
+fn only_the_controller_authority_sets_alloc_points() {
+    let pool = Pool::new();
+    let stranger = pool.bank.create_user();
+    pool.start_controller(300);
+    assert_eq!(
+        pool.bank
+            .process(&[ix::set_alloc_point(&stranger, &pool.farm, 1)]),
+        Err(anchor_error(
+            anchor_lang::error::ErrorCode::ConstraintHasOne
+        ))
+    );
+
+    // Farms must pay out the controller's token.
+    let other_mint = pool.bank.create_mint(&pool.authority, 6);
+    pool.bank
+        .process(&[ix::initialize_farm(
+            &pool.authority,
+            1,
+            &other_mint,
+            &pool.staking_mint,
+            0,
+            FARM_DURATION,
+        )])
+        .unwrap();
+    let second = client::pda::farm(&pool.authority, 1).0;
+    assert_eq!(
+        pool.bank
+            .process(&[ix::set_alloc_point(&pool.authority, &second, 1)]),
+        Err(anchor_error(ErrorCode::InvalidTokenMint))
+    );
+}
+
+#[test]
+fn gauge_votes_set_emission_shares_each_epoch() {
+    let pool = Pool::with(0, 0);
+    let second = pool.add_farm();
+    pool.start_controller(300);
+    let gov_mint = pool.start_governance();
+    // Gauges are voted with the governance token only.
+    let other_mint = pool.bank.create_mint(&pool.authority, 6);
+    assert_eq!(
+        pool.bank
+            .process(&[ix::initialize_gauges(&pool.authority, &other_mint, EPOCH)]),
+        Err(anchor_error(ErrorCode::InvalidTokenMint))
+    );
+    pool.bank
+        .process(&[
+            ix::initialize_gauges(&pool.authority, &gov_mint, EPOCH),
+            ix::create_gauge(&pool.authority, &pool.farm),
+            ix::create_gauge(&pool.authority, &second),
+        ])
+        .unwrap();
+    let genesis = pool.bank.now();
+
+    let alice = pool.staker(1_000);
+    let bob = pool.staker(1_000);
+    pool.bank
+        .create_associated_token_account(&client::pda::receipt_mint(&second).0, &bob.wallet);
+    pool.stake(&alice, 1_000);
+    pool.bank
+        .process(&[ix::stake_tokens(&second, &bob.wallet, &bob.tokens, 1_000)])
+        .unwrap();
+
+    let voter = |amount: u64, unlock_time: i64| {
+        let wallet = pool.bank.create_user();
+        let tokens = pool.bank.create_token_account(&gov_mint, &wallet);
+        pool.bank
+            .mint_to(&gov_mint, &pool.authority, &tokens, amount);
+        pool.bank
+            .process(&[ix::lock_governance_tokens(
+                &wallet,
+                &tokens,
+                amount,
+                unlock_time,
+            )])
+            .unwrap();
+        (wallet, tokens)
+    };
+    let (carol, carol_tokens) = voter(300, genesis + EPOCH);
+    let (dave, _) = voter(100, genesis + 3 * EPOCH);
+    let (erin, _) = voter(100, genesis + EPOCH - 1);
+
+    pool.bank
+        .process(&[
+            ix::vote_gauges(&carol, &[(pool.farm, 5_000), (second, 5_000)]),
+            ix::vote_gauges(&dave, &[(second, 10_000)]),
+        ])
+        .unwrap();
+    assert_eq!(pool.bank.events::<GaugeVoted>()[2].total_votes, 250);
+    assert_eq!(
+        pool.bank
+            .process(&[ix::vote_gauges(&dave, &[(pool.farm, 10_000)])]),
+        Err(anchor_error(ErrorCode::AlreadyVotedThisEpoch))
+    );
+    // Tokens unlocking mid-epoch cannot vote in it.
+    assert_eq!(
+        pool.bank
+            .process(&[ix::vote_gauges(&erin, &[(pool.farm, 10_000)])]),
+        Err(anchor_error(ErrorCode::LockEndsBeforeEpoch))
+    );
+    assert_eq!(
+        pool.bank.process(&[ix::update_gauge(&pool.farm)]),
+        Err(anchor_error(ErrorCode::EpochNotEnded))
+    );
+
+    // The crank applies the tallies once the epoch is over.
+    pool.bank.warp_to(genesis + EPOCH);
+    pool.bank
+        .process(&[ix::update_gauge(&pool.farm), ix::update_gauge(&second)])
+        .unwrap();
+    let applied = pool.bank.events::<GaugeApplied>();
+    assert_eq!((applied[0].epoch, applied[0].votes), (1, 150));
+    assert_eq!((applied[1].epoch, applied[1].votes), (1, 250));
+    assert_eq!(
+        client::fetch_controller(&pool.bank)
+            .unwrap()
+            .total_alloc_point,
+        400
+    );
+    // Votes, not the authority, now set both farms' shares.
+    assert_eq!(
+        pool.bank
+            .process(&[ix::set_alloc_point(&pool.authority, &pool.farm, 1_000)]),
+        Err(anchor_error(ErrorCode::GaugeControlsAllocation))
+    );
+
+    pool.bank.warp_forward(100);
+    pool.bank
+        .process(&[ix::mass_update_pools(&[pool.farm, second])])
+        .unwrap();
+    let distributed = pool.bank.events::<EmissionsDistributed>();
+    assert_eq!(distributed[0].amount, 100 * 300 * 150 / 400);
+    assert_eq!(distributed[1].amount, 100 * 300 * 250 / 400);
+
+    // Carol's lock has ended; dave's weight alone directs the next epoch.
+    pool.bank
+        .process(&[
+            ix::withdraw_governance_tokens(&carol, &carol_tokens),
+            ix::vote_gauges(&dave, &[(pool.farm, 10_000)]),
+        ])
+        .unwrap();
+    assert_eq!(pool.bank.token_balance(&carol_tokens), 300);
+    pool.bank.warp_to(genesis + 2 * EPOCH);
+    pool.bank
+        .process(&[ix::update_gauge(&pool.farm), ix::update_gauge(&second)])
+        .unwrap();
+    assert_eq!(
+        client::fetch_farm(&pool.bank, &pool.farm)
+            .unwrap()
+            .alloc_point,
+        100
+    );
+    assert_eq!(
+        client::fetch_farm(&pool.bank, &second).unwrap().alloc_point,
+        0
+    );
+}
+
+#[test]
+fn locked_governance_tokens_cannot_be_withdrawn_early() {
+    let pool = Pool::new();
+    pool.start_controller(300);
+    let gov_mint = pool.start_governance();
+    pool.bank
+        .process(&[ix::initialize_gauges(&pool.authority, &gov_mint, EPOCH)])
+        .unwrap();
+    let wallet = pool.bank.create_user();
+    let tokens = pool.bank.create_token_account(&gov_mint, &wallet);
+    pool.bank.mint_to(&gov_mint, &pool.authority, &tokens, 100);
+    let unlock_time = pool.bank.now() + EPOCH;
+    pool.bank
+        .process(&[ix::lock_governance_tokens(
+            &wallet,
+            &tokens,
+            100,
+            unlock_time,
+        )])
+        .unwrap();
+
+    assert_eq!(
+        pool.bank.process(&[ix::lock_governance_tokens(
+            &wallet,
+            &tokens,
+            0,
+            unlock_time - 1
+        )]),
+        Err(anchor_error(ErrorCode::InvalidUnlockTime))
+    );
+    let withdraw = ix::withdraw_governance_tokens(&wallet, &tokens);
+    assert_eq!(
+        pool.bank.process(std::slice::from_ref(&withdraw)),
+        Err(anchor_error(ErrorCode::GovernanceTokensStillLocked))
+    );
+    pool.bank.warp_to(unlock_time);
+    pool.bank.process(&[withdraw]).unwrap();
+    assert_eq!(pool.bank.token_balance(&tokens), 100);
+}
+
+/// A flat stretch, a linear decay to zero, a gap, then a halving tail,
+/// emitting `SCHEDULE_TOTAL` tokens.
+fn schedule(start: i64) -> Vec<EmissionSegment> {
+    let segment =
+        |offset: i64, length: i64, rate: u64, end_rate: u64, halving_period: i64| EmissionSegment {
+            start_time: start + offset,
+            end_time: start + offset + length,
+            rate,
+            end_rate,
+            halving_period,
+        };
+    vec![
+        segment(100, 100, 1_000, 1_000, 0),
+        segment(200, 100, 1_000, 0, 0),
+        segment(400, 40, 800, 800, 10),
+    ]
+}
+
+#[test]
+fn emission_schedule_integrates_across_segments() {
+    let pool = Pool::with(REWARD_RATE, SCHEDULE_TOTAL);
+    let start = pool.bank.now();
+    pool.bank
+        .process(&[ix::set_emission_schedule(
+            &pool.authority,
+            &pool.farm,
+            schedule(start),
+        )])
+        .unwrap();
+    let alice = pool.staked(1_000);
+
+    // Nothing is emitted before the first segment.
+    pool.bank.warp_to(start + 100);
+    assert_eq!(pool.claim(&alice), Err(anchor_error(ErrorCode::NoRewards)));
+
+    // Halfway down the decay: the flat segment plus three quarters of half.
+    pool.bank.warp_to(start + 250);
+    pool.claim(&alice).unwrap();
+    assert_eq!(pool.bank.token_balance(&alice.rewards), 100_000 + 37_500);
+
+    // Mid-halving, with the gap in between emitting nothing.
+    pool.bank.warp_to(start + 415);
+    pool.claim(&alice).unwrap();
+    assert_eq!(
+        pool.bank.token_balance(&alice.rewards),
+        150_000 + 8_000 + 5 * 400
+    );
+
+    pool.bank.warp_to(start + 1_000);
+    pool.claim(&alice).unwrap();
+    assert_eq!(pool.bank.token_balance(&alice.rewards), SCHEDULE_TOTAL);
+    assert_eq!(pool.bank.token_balance(&pool.reward_vault), 0);
+}
+
+#[test]
+fn emission_schedule_is_validated_and_funded() {
+    let pool = Pool::with(REWARD_RATE, SCHEDULE_TOTAL - 1);
+    let start = pool.bank.now();
+    let set = |segments| {
+        pool.bank.process(&[ix::set_emission_schedule(
+            &pool.authority,
+            &pool.farm,
+            segments,
+        )])
+    };
+
+    assert_eq!(
+        set(schedule(start)),
+        Err(anchor_error(ErrorCode::InsufficientRewardBalance))
+    );
+    let mut overlapping = schedule(start);
+    overlapping[1].start_time -= 1;
+    assert_eq!(
+        set(overlapping),
+        Err(anchor_error(ErrorCode::InvalidEmissionSchedule))
+    );
+    let mut decaying_halving = schedule(start);
+    decaying_halving[2].end_rate = 0;
+    assert_eq!(
+        set(decaying_halving),
+        Err(anchor_error(ErrorCode::InvalidEmissionSchedule))
+    );
+
+    // Dropping the tail fits the funding; the flat rate is then off limits.
+    let mut shorter = schedule(start);
+    shorter.pop();
+    set(shorter).unwrap();
+    let source = pool.reward_source(1_000);
+    assert_eq!(
+        pool.notify(&source, 1_000),
+        Err(anchor_error(ErrorCode::EmissionScheduleActive))
+    );
+
+    // Clearing the schedule restores the flat rate.
+    set(Vec::new()).unwrap();
+    pool.notify(&source, 1_000).unwrap();
+}
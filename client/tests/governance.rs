@@ -0,0 +1,245 @@
+mod common;
+
+use anchor_lang::prelude::Pubkey;
+use client::{governance as ix, ProposalStatus, ProposalType, VoteType};
+use common::{anchor_error, Bank};
+use governance::{ErrorCode, ProposalCancelled, ProposalExecuted, ProposalQueued, VoteCast};
+
+const VOTING_PERIOD: i64 = 3 * 24 * 60 * 60;
+const EXECUTION_DELAY: i64 = 24 * 60 * 60;
+const PROPOSAL_THRESHOLD: u64 = 100;
+const QUORUM_THRESHOLD: u64 = 500;
+
+struct Dao {
+    bank: Bank,
+    authority: Pubkey,
+    mint: Pubkey,
+}
+
+impl Dao {
+    fn new() -> Self {
+        let bank = Bank::new();
+        let authority = bank.create_user();
+        let mint = bank.create_mint(&authority, 6);
+        bank.process(&[ix::initialize_governance(
+            &authority,
+            &mint,
+            VOTING_PERIOD,
+            EXECUTION_DELAY,
+            PROPOSAL_THRESHOLD,
+            QUORUM_THRESHOLD,
+        )])
+        .unwrap();
+        Self {
+            bank,
+            authority,
+            mint,
+        }
+    }
+
+    /// Returns a wallet and its governance token account holding `amount`.
+    fn holder(&self, amount: u64) -> (Pubkey, Pubkey) {
+        let wallet = self.bank.create_user();
+        let tokens = self.bank.create_token_account(&self.mint, &wallet);
+        self.bank
+            .mint_to(&self.mint, &self.authority, &tokens, amount);
+        (wallet, tokens)
+    }
+
+    fn propose(&self, proposer: &Pubkey, tokens: &Pubkey) -> u64 {
+        let proposal_id = client::fetch_governance(&self.bank).unwrap().proposal_count;
+        self.bank
+            .process(&[ix::create_proposal(
+                proposer,
+                tokens,
+                proposal_id,
+                "Raise farm emissions".to_string(),
+                "Double the reward rate of the main pool".to_string(),
+                ProposalType::ParameterChange,
+                Vec::new(),
+            )])
+            .unwrap();
+        proposal_id
+    }
+
+    fn status(&self, proposal_id: u64) -> ProposalStatus {
+        client::fetch_proposal(&self.bank, proposal_id)
+            .unwrap()
+            .status
+    }
+}
+
+#[test]
+fn proposal_lifecycle() {
+    let dao = Dao::new();
+    let (proposer, proposer_tokens) = dao.holder(1_000);
+    let (opponent, opponent_tokens) = dao.holder(200);
+    let proposal_id = dao.propose(&proposer, &proposer_tokens);
+    let proposal = client::fetch_proposal(&dao.bank, proposal_id).unwrap();
+    assert_eq!(proposal.voting_ends_at, dao.bank.now() + VOTING_PERIOD);
+
+    dao.bank
+        .process(&[
+            ix::cast_vote(&proposer, &proposer_tokens, proposal_id, VoteType::For, 600),
+            ix::cast_vote(
+                &opponent,
+                &opponent_tokens,
+                proposal_id,
+                VoteType::Against,
+                200,
+            ),
+        ])
+        .unwrap();
+    let votes = dao.bank.events::<VoteCast>();
+    assert_eq!(votes.len(), 2);
+    assert_eq!((votes[1].votes_for, votes[1].votes_against), (600, 200));
+
+    // Voting is still open.
+    assert_eq!(
+        dao.bank.process(&[ix::queue_proposal(proposal_id)]),
+        Err(anchor_error(ErrorCode::VotingPeriodNotEnded))
+    );
+
+    dao.bank.warp_to(proposal.voting_ends_at + 1);
+    dao.bank
+        .process(&[ix::queue_proposal(proposal_id)])
+        .unwrap();
+    assert!(dao.status(proposal_id) == ProposalStatus::Queued);
+    let eta = dao.bank.events::<ProposalQueued>()[0].execution_eta;
+    assert_eq!(eta, proposal.voting_ends_at + 1 + EXECUTION_DELAY);
+
+    dao.bank.warp_to(eta - 1);
+    assert_eq!(
+        dao.bank
+            .process(&[ix::execute_proposal(&proposer, proposal_id)]),
+        Err(anchor_error(ErrorCode::ExecutionDelayNotMet))
+    );
+
+    dao.bank.warp_to(eta);
+    dao.bank
+        .process(&[ix::execute_proposal(&proposer, proposal_id)])
+        .unwrap();
+    assert!(dao.status(proposal_id) == ProposalStatus::Executed);
+    assert_eq!(dao.bank.events::<ProposalExecuted>()[0].executed_at, eta);
+}
+
+#[test]
+fn voting_closes_at_voting_ends_at() {
+    let dao = Dao::new();
+    let (proposer, proposer_tokens) = dao.holder(1_000);
+    let (late, late_tokens) = dao.holder(1_000);
+    let proposal_id = dao.propose(&proposer, &proposer_tokens);
+    let voting_ends_at = client::fetch_proposal(&dao.bank, proposal_id)
+        .unwrap()
+        .voting_ends_at;
+
+    dao.bank.warp_to(voting_ends_at);
+    dao.bank
+        .process(&[ix::cast_vote(
+            &proposer,
+            &proposer_tokens,
+            proposal_id,
+            VoteType::For,
+            500,
+        )])
+        .unwrap();
+
+    dao.bank.warp_to(voting_ends_at + 1);
+    assert_eq!(
+        dao.bank.process(&[ix::cast_vote(
+            &late,
+            &late_tokens,
+            proposal_id,
+            VoteType::For,
+            500
+        )]),
+        Err(anchor_error(ErrorCode::VotingPeriodEnded))
+    );
+}
+
+#[test]
+fn proposal_without_quorum_cannot_be_queued() {
+    let dao = Dao::new();
+    let (proposer, proposer_tokens) = dao.holder(1_000);
+    let proposal_id = dao.propose(&proposer, &proposer_tokens);
+    dao.bank
+        .process(&[ix::cast_vote(
+            &proposer,
+            &proposer_tokens,
+            proposal_id,
+            VoteType::For,
+            QUORUM_THRESHOLD - 1,
+        )])
+        .unwrap();
+
+    dao.bank.warp_forward(VOTING_PERIOD + 1);
+    assert_eq!(
+        dao.bank.process(&[ix::queue_proposal(proposal_id)]),
+        Err(anchor_error(ErrorCode::QuorumNotMet))
+    );
+}
+
+#[test]
+fn proposal_threshold_is_enforced() {
+    let dao = Dao::new();
+    let (proposer, proposer_tokens) = dao.holder(PROPOSAL_THRESHOLD - 1);
+    let result = dao.bank.process(&[ix::create_proposal(
+        &proposer,
+        &proposer_tokens,
+        0,
+        "Spam".to_string(),
+        String::new(),
+        ProposalType::TreasurySpend,
+        Vec::new(),
+    )]);
+    assert_eq!(
+        result,
+        Err(anchor_error(ErrorCode::InsufficientTokensForProposal))
+    );
+}
+
+#[test]
+fn voter_cannot_vote_twice() {
+    let dao = Dao::new();
+    let (proposer, proposer_tokens) = dao.holder(1_000);
+    let proposal_id = dao.propose(&proposer, &proposer_tokens);
+    let vote = ix::cast_vote(&proposer, &proposer_tokens, proposal_id, VoteType::For, 100);
+
+    dao.bank.process(std::slice::from_ref(&vote)).unwrap();
+    // The vote record PDA already exists, so `init` fails in the system program.
+    assert!(dao.bank.process(&[vote]).is_err());
+    let proposal = client::fetch_proposal(&dao.bank, proposal_id).unwrap();
+    assert_eq!(proposal.votes_for, 100);
+}
+
+#[test]
+fn only_proposer_or_authority_can_cancel() {
+    let dao = Dao::new();
+    let (proposer, proposer_tokens) = dao.holder(1_000);
+    let (stranger, _) = dao.holder(0);
+    let first = dao.propose(&proposer, &proposer_tokens);
+    let second = dao.propose(&proposer, &proposer_tokens);
+
+    assert_eq!(
+        dao.bank.process(&[ix::cancel_proposal(&stranger, first)]),
+        Err(anchor_error(ErrorCode::Unauthorized))
+    );
+
+    dao.bank
+        .process(&[
+            ix::cancel_proposal(&proposer, first),
+            ix::cancel_proposal(&dao.authority, second),
+        ])
+        .unwrap();
+    assert!(dao.status(first) == ProposalStatus::Cancelled);
+    assert!(dao.status(second) == ProposalStatus::Cancelled);
+    assert_eq!(
+        dao.bank.events::<ProposalCancelled>()[1].cancelled_by,
+        dao.authority
+    );
+
+    assert_eq!(
+        dao.bank.process(&[ix::cancel_proposal(&proposer, first)]),
+        Err(anchor_error(ErrorCode::ProposalNotCancellable))
+    );
+}
@@ -0,0 +1,87 @@
+use anchor_lang::prelude::Pubkey;
+use anchor_lang::solana_program::instruction::Instruction;
+use client::{ClientError, RpcClient};
+use solana_client::rpc_client::RpcClient as SolanaRpcClient;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::signature::{Keypair, Signer};
+use solana_sdk::transaction::Transaction;
+
+/// [`RpcClient`] backed by a JSON-RPC endpoint, signing with a single keypair.
+pub struct Cluster {
+    rpc: SolanaRpcClient,
+    payer: Keypair,
+}
+
+impl Cluster {
+    pub fn new(url: String, payer: Keypair) -> Self {
+        Self {
+            rpc: SolanaRpcClient::new_with_commitment(url, CommitmentConfig::confirmed()),
+            payer,
+        }
+    }
+
+    pub fn payer(&self) -> Pubkey {
+        self.payer.pubkey()
+    }
+}
+
+impl RpcClient for Cluster {
+    fn get_account_data(&self, address: &Pubkey) -> client::Result<Option<Vec<u8>>> {
+        let response = self
+            .rpc
+            .get_account_with_commitment(address, self.rpc.commitment())
+            .map_err(|err| ClientError::Rpc(err.to_string()))?;
+        Ok(response.value.map(|account| account.data))
+    }
+
+    fn send_instructions(&self, instructions: &[Instruction]) -> client::Result<String> {
+        let blockhash = self
+            .rpc
+            .get_latest_blockhash()
+            .map_err(|err| ClientError::Rpc(err.to_string()))?;
+        let transaction = Transaction::new_signed_with_payer(
+            instructions,
+            Some(&self.payer.pubkey()),
+            &[&self.payer],
+            blockhash,
+        );
+        let signature = self
+            .rpc
+            .send_and_confirm_transaction(&transaction)
+            .map_err(|err| ClientError::Rpc(err.to_string()))?;
+        Ok(signature.to_string())
+    }
+}
+
+/// Describes the transaction that would be sent, without signing it.
+pub fn describe_transaction(payer: &Pubkey, instructions: &[Instruction]) -> serde_json::Value {
+    let instructions: Vec<_> = instructions
+        .iter()
+        .map(|ix| {
+            let accounts: Vec<_> = ix
+                .accounts
+                .iter()
+                .map(|meta| {
+                    serde_json::json!({
+                        "pubkey": meta.pubkey.to_string(),
+                        "is_signer": meta.is_signer,
+                        "is_writable": meta.is_writable,
+                    })
+                })
+                .collect();
+            serde_json::json!({
+                "program_id": ix.program_id.to_string(),
+                "accounts": accounts,
+                "data": hex(&ix.data),
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "fee_payer": payer.to_string(),
+        "instructions": instructions,
+    })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
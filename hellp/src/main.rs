@@ -1,3 +1,1231 @@
+mod cluster;
+
+use std::error::Error;
+
+use anchor_lang::prelude::Pubkey;
+use anchor_lang::solana_program::instruction::Instruction;
+use clap::{Parser, Subcommand, ValueEnum};
+use client::{ClientError, RpcClient};
+use serde_json::{Value, json};
+use solana_sdk::signature::read_keypair_file;
+
+use crate::cluster::Cluster;
+
+/// Operator CLI for the governance and yield-farming programs.
+#[derive(Parser)]
+#[command(name = "hellp", version)]
+struct Cli {
+    /// JSON-RPC endpoint of the cluster
+    #[arg(long, global = true, default_value = "http://127.0.0.1:8899")]
+    url: String,
+
+    /// Keypair file used to sign and pay for transactions
+    #[arg(long, global = true, default_value = "~/.config/solana/id.json")]
+    keypair: String,
+
+    /// Print machine-readable JSON instead of text
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Print the transaction that would be sent instead of sending it
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[command(subcommand)]
+    Governance(GovernanceCommand),
+    #[command(subcommand)]
+    Farm(FarmCommand),
+    #[command(subcommand)]
+    Controller(ControllerCommand),
+}
+
+#[derive(Subcommand)]
+enum GovernanceCommand {
+    /// Create the governance account
+    Init {
+        #[arg(long)]
+        token_mint: Pubkey,
+        #[arg(long)]
+        voting_period: i64,
+        #[arg(long)]
+        execution_delay: i64,
+        #[arg(long)]
+        proposal_threshold: u64,
+        #[arg(long)]
+        quorum_threshold: u64,
+    },
+    /// Create a proposal
+    Propose {
+        #[arg(long)]
+        token_account: Pubkey,
+        #[arg(long)]
+        title: String,
+        #[arg(long, default_value = "")]
+        description: String,
+        #[arg(long, value_enum)]
+        kind: ProposalKind,
+        /// Hex-encoded execution data
+        #[arg(long, default_value = "")]
+        data: String,
+    },
+    /// Vote on an active proposal
+    Vote {
+        #[arg(long)]
+        proposal_id: u64,
+        #[arg(long)]
+        token_account: Pubkey,
+        #[arg(long, value_enum)]
+        vote: Vote,
+        #[arg(long)]
+        power: u64,
+    },
+    /// Queue a proposal that passed
+    Queue {
+        #[arg(long)]
+        proposal_id: u64,
+    },
+    /// Execute a queued proposal
+    Execute {
+        #[arg(long)]
+        proposal_id: u64,
+    },
+    /// Cancel an active or queued proposal
+    Cancel {
+        #[arg(long)]
+        proposal_id: u64,
+    },
+    /// Delegate voting power to another wallet
+    Delegate {
+        #[arg(long)]
+        token_account: Pubkey,
+        #[arg(long)]
+        to: Pubkey,
+        #[arg(long)]
+        amount: u64,
+    },
+    /// Show the governance account, or a proposal
+    Show {
+        #[arg(long)]
+        proposal_id: Option<u64>,
+    },
+}
+
+#[derive(Subcommand)]
+enum FarmCommand {
+    /// Create a farm owned by the signer
+    Init {
+        #[arg(long)]
+        reward_mint: Pubkey,
+        #[arg(long)]
+        staking_mint: Pubkey,
+        #[arg(long)]
+        reward_rate: u64,
+        #[arg(long)]
+        duration: i64,
+    },
+    /// Deposit rewards into a farm run by the signer and restart its reward period
+    Fund {
+        #[arg(long)]
+        farm: Pubkey,
+        #[arg(long)]
+        source: Pubkey,
+        #[arg(long)]
+        amount: u64,
+    },
+    /// Add an extra reward token to a farm run by the signer
+    AddReward {
+        #[arg(long)]
+        farm: Pubkey,
+        #[arg(long)]
+        reward_mint: Pubkey,
+    },
+    /// Deposit rewards into one of the farm's extra reward streams
+    FundReward {
+        #[arg(long)]
+        farm: Pubkey,
+        #[arg(long)]
+        index: u8,
+        #[arg(long)]
+        source: Pubkey,
+        #[arg(long)]
+        amount: u64,
+    },
+    /// Stake tokens into a farm
+    Stake {
+        #[arg(long)]
+        farm: Pubkey,
+        #[arg(long)]
+        token_account: Pubkey,
+        #[arg(long)]
+        amount: u64,
+    },
+    /// Stake tokens and lock the whole position for 1-52 weeks
+    Lock {
+        #[arg(long)]
+        farm: Pubkey,
+        #[arg(long)]
+        token_account: Pubkey,
+        #[arg(long, default_value_t = 0)]
+        amount: u64,
+        #[arg(long)]
+        weeks: u8,
+    },
+    /// Stake the signer's tokens into another wallet's position
+    StakeFor {
+        #[arg(long)]
+        farm: Pubkey,
+        #[arg(long)]
+        token_account: Pubkey,
+        #[arg(long)]
+        beneficiary: Pubkey,
+        #[arg(long)]
+        amount: u64,
+    },
+    /// Hand the signer's whole stake to another wallet
+    TransferStake {
+        #[arg(long)]
+        farm: Pubkey,
+        #[arg(long)]
+        new_owner: Pubkey,
+    },
+    /// Sync positions with their receipt balances after receipts moved
+    Checkpoint {
+        #[arg(long)]
+        farm: Pubkey,
+        /// Wallet whose position to sync; repeat for sender and recipient
+        #[arg(long = "owner", required = true)]
+        owners: Vec<Pubkey>,
+    },
+    /// Drop the boost of a user's expired lock
+    ExpireLock {
+        #[arg(long)]
+        farm: Pubkey,
+        #[arg(long)]
+        user: Pubkey,
+    },
+    /// Unstake tokens from a farm
+    Unstake {
+        #[arg(long)]
+        farm: Pubkey,
+        #[arg(long)]
+        token_account: Pubkey,
+        #[arg(long)]
+        amount: u64,
+        /// Close the position and reclaim its rent if this empties it
+        #[arg(long)]
+        close: bool,
+    },
+    /// Close the signer's empty position and reclaim its rent
+    CloseStake {
+        #[arg(long)]
+        farm: Pubkey,
+    },
+    /// Start the farm's cooldown on part of the signer's stake
+    RequestUnstake {
+        #[arg(long)]
+        farm: Pubkey,
+        #[arg(long)]
+        token_account: Pubkey,
+        #[arg(long)]
+        amount: u64,
+    },
+    /// Withdraw tokens whose cooldown has elapsed
+    WithdrawUnstaked {
+        #[arg(long)]
+        farm: Pubkey,
+        #[arg(long)]
+        token_account: Pubkey,
+    },
+    /// Set the unbonding cooldown of a farm run by the signer
+    SetCooldown {
+        #[arg(long)]
+        farm: Pubkey,
+        #[arg(long)]
+        seconds: i64,
+    },
+    /// Cap deposits into a farm run by the signer; zero leaves a cap off
+    SetCaps {
+        #[arg(long)]
+        farm: Pubkey,
+        #[arg(long, default_value_t = 0)]
+        max_total: u64,
+        #[arg(long, default_value_t = 0)]
+        max_per_user: u64,
+    },
+    /// Configure the fees of a farm run by the signer, in basis points
+    SetFees {
+        #[arg(long)]
+        farm: Pubkey,
+        #[arg(long, default_value_t = 0)]
+        deposit_bps: u16,
+        #[arg(long, default_value_t = 0)]
+        withdraw_bps: u16,
+        #[arg(long, default_value_t = 0)]
+        performance_bps: u16,
+        /// Owner of the token accounts fees are collected into
+        #[arg(long)]
+        recipient: Pubkey,
+    },
+    /// Change the emission rate for the rest of the current period
+    SetRate {
+        #[arg(long)]
+        farm: Pubkey,
+        #[arg(long)]
+        reward_rate: u64,
+    },
+    /// Set the length of the next reward period
+    SetDuration {
+        #[arg(long)]
+        farm: Pubkey,
+        #[arg(long)]
+        seconds: i64,
+    },
+    /// Replace the flat reward rate with emission segments; pass none to clear
+    SetSchedule {
+        #[arg(long)]
+        farm: Pubkey,
+        /// START:END:RATE[:END_RATE[:HALVING_SECONDS]], in schedule order
+        #[arg(long = "segment", value_parser = parse_segment)]
+        segments: Vec<client::EmissionSegment>,
+    },
+    /// Propose a new authority for a farm run by the signer
+    SetAuthority {
+        #[arg(long)]
+        farm: Pubkey,
+        #[arg(long)]
+        new_authority: Pubkey,
+    },
+    /// Take over a farm the signer was proposed as authority of
+    AcceptAuthority {
+        #[arg(long)]
+        farm: Pubkey,
+    },
+    /// Send accrued fees to the fee recipient
+    CollectFees {
+        #[arg(long)]
+        farm: Pubkey,
+        #[arg(long)]
+        stake_fee_account: Pubkey,
+        #[arg(long)]
+        reward_fee_account: Pubkey,
+    },
+    /// Restake accrued rewards in a farm that pays its staking token
+    Compound {
+        #[arg(long)]
+        farm: Pubkey,
+    },
+    /// Let keepers compound the signer's rewards for a tip
+    AutoCompound {
+        #[arg(long)]
+        farm: Pubkey,
+        #[arg(long, action = clap::ArgAction::Set)]
+        enabled: bool,
+    },
+    /// Compound an opted-in user's rewards and collect the keeper tip
+    CompoundFor {
+        #[arg(long)]
+        farm: Pubkey,
+        #[arg(long)]
+        user: Pubkey,
+        #[arg(long)]
+        reward_account: Pubkey,
+    },
+    /// Vest claimed rewards of a farm run by the signer; zero seconds pays
+    /// claims out directly
+    SetVesting {
+        #[arg(long)]
+        farm: Pubkey,
+        #[arg(long)]
+        seconds: i64,
+        #[arg(long, default_value_t = 0)]
+        penalty_bps: u16,
+    },
+    /// Move accrued rewards into the signer's vesting schedule
+    Vest {
+        #[arg(long)]
+        farm: Pubkey,
+    },
+    /// Withdraw rewards released by the signer's vesting schedule
+    WithdrawVested {
+        #[arg(long)]
+        farm: Pubkey,
+        #[arg(long)]
+        reward_account: Pubkey,
+    },
+    /// Withdraw the whole vesting schedule, paying the early-exit penalty
+    ExitVesting {
+        #[arg(long)]
+        farm: Pubkey,
+        #[arg(long)]
+        reward_account: Pubkey,
+    },
+    /// Claim accrued rewards
+    Claim {
+        #[arg(long)]
+        farm: Pubkey,
+        #[arg(long)]
+        reward_account: Pubkey,
+        /// Token account receiving each extra reward stream, in stream order;
+        /// claims every stream when given
+        #[arg(long = "stream-account")]
+        stream_accounts: Vec<Pubkey>,
+    },
+    /// Block new stakes into a farm run by the signer
+    Pause {
+        #[arg(long)]
+        farm: Pubkey,
+    },
+    /// Allow staking into a paused farm again
+    Unpause {
+        #[arg(long)]
+        farm: Pubkey,
+    },
+    /// Withdraw the whole stake, forfeiting pending rewards
+    EmergencyWithdraw {
+        #[arg(long)]
+        farm: Pubkey,
+        #[arg(long)]
+        token_account: Pubkey,
+    },
+    /// List every farm in the registry
+    List,
+    /// Show a farm, and optionally a user's stake in it
+    Show {
+        #[arg(long)]
+        farm: Pubkey,
+        #[arg(long)]
+        user: Option<Pubkey>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ControllerCommand {
+    /// Create the emission controller, run by the signer
+    Init {
+        #[arg(long)]
+        reward_mint: Pubkey,
+        /// Tokens emitted per second across every pool
+        #[arg(long)]
+        emission_rate: u64,
+    },
+    /// Change the emission shared by every pool
+    SetRate {
+        #[arg(long)]
+        emission_rate: u64,
+    },
+    /// Set a farm's share of the emission
+    SetAlloc {
+        #[arg(long)]
+        farm: Pubkey,
+        #[arg(long)]
+        alloc_point: u64,
+    },
+    /// Pay pools the emissions they have earned; defaults to every farm with a share
+    Update {
+        #[arg(long = "farm")]
+        farms: Vec<Pubkey>,
+    },
+    /// Let governance token lockers direct the emission through gauges
+    InitGauges {
+        #[arg(long)]
+        governance_mint: Pubkey,
+        #[arg(long)]
+        epoch_seconds: i64,
+    },
+    /// Make a farm eligible for gauge votes
+    AddGauge {
+        #[arg(long)]
+        farm: Pubkey,
+    },
+    /// Lock governance tokens as gauge voting weight
+    LockVotes {
+        #[arg(long)]
+        token_account: Pubkey,
+        #[arg(long, default_value_t = 0)]
+        amount: u64,
+        /// Unix timestamp the tokens unlock at
+        #[arg(long)]
+        unlock_time: i64,
+    },
+    /// Withdraw governance tokens whose lock has ended
+    WithdrawVotes {
+        #[arg(long)]
+        token_account: Pubkey,
+    },
+    /// Split the signer's locked weight across gauges for this epoch
+    Vote {
+        #[arg(long = "farm", required = true)]
+        farms: Vec<Pubkey>,
+        /// Share of the weight for each `--farm`, in the same order
+        #[arg(long = "weight-bps", required = true)]
+        weights_bps: Vec<u16>,
+    },
+    /// Apply the last epoch's votes; defaults to every farm with a gauge
+    UpdateGauges {
+        #[arg(long = "farm")]
+        farms: Vec<Pubkey>,
+    },
+    /// Show the controller and each pool's share
+    Show,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ProposalKind {
+    ParameterChange,
+    TreasurySpend,
+    UpgradeContract,
+    AddFarm,
+}
+
+impl From<ProposalKind> for client::ProposalType {
+    fn from(kind: ProposalKind) -> Self {
+        match kind {
+            ProposalKind::ParameterChange => Self::ParameterChange,
+            ProposalKind::TreasurySpend => Self::TreasurySpend,
+            ProposalKind::UpgradeContract => Self::UpgradeContract,
+            ProposalKind::AddFarm => Self::AddFarm,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Vote {
+    For,
+    Against,
+}
+
+impl From<Vote> for client::VoteType {
+    fn from(vote: Vote) -> Self {
+        match vote {
+            Vote::For => Self::For,
+            Vote::Against => Self::Against,
+        }
+    }
+}
+
+/// What a subcommand wants done once its inputs are resolved.
+enum Action {
+    Send(Vec<Instruction>),
+    Show(Value),
+}
+
 fn main() {
-    println!("Hello, world!");
+    let cli = Cli::parse();
+    if let Err(err) = run(cli) {
+        eprintln!("error: {err}");
+        std::process::exit(1);
+    }
+}
+
+fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    let payer = read_keypair_file(expand_home(&cli.keypair))
+        .map_err(|err| format!("failed to read keypair {}: {err}", cli.keypair))?;
+    let cluster = Cluster::new(cli.url, payer);
+
+    let action = match cli.command {
+        Command::Governance(command) => governance(&cluster, command)?,
+        Command::Farm(command) => farm(&cluster, command)?,
+        Command::Controller(command) => controller(&cluster, command)?,
+    };
+
+    let output = match action {
+        Action::Show(value) => value,
+        Action::Send(instructions) if cli.dry_run => {
+            cluster::describe_transaction(&cluster.payer(), &instructions)
+        }
+        Action::Send(instructions) => {
+            json!({ "signature": cluster.send_instructions(&instructions)? })
+        }
+    };
+    print(&output, cli.json);
+    Ok(())
+}
+
+fn governance(cluster: &Cluster, command: GovernanceCommand) -> Result<Action, Box<dyn Error>> {
+    let signer = cluster.payer();
+    let instruction = match command {
+        GovernanceCommand::Init {
+            token_mint,
+            voting_period,
+            execution_delay,
+            proposal_threshold,
+            quorum_threshold,
+        } => client::governance::initialize_governance(
+            &signer,
+            &token_mint,
+            voting_period,
+            execution_delay,
+            proposal_threshold,
+            quorum_threshold,
+        ),
+        GovernanceCommand::Propose {
+            token_account,
+            title,
+            description,
+            kind,
+            data,
+        } => {
+            let proposal_id = client::fetch_governance(cluster)?.proposal_count;
+            client::governance::create_proposal(
+                &signer,
+                &token_account,
+                proposal_id,
+                title,
+                description,
+                kind.into(),
+                parse_hex(&data)?,
+            )
+        }
+        GovernanceCommand::Vote {
+            proposal_id,
+            token_account,
+            vote,
+            power,
+        } => {
+            client::governance::cast_vote(&signer, &token_account, proposal_id, vote.into(), power)
+        }
+        GovernanceCommand::Queue { proposal_id } => client::governance::queue_proposal(proposal_id),
+        GovernanceCommand::Execute { proposal_id } => {
+            let proposal = client::fetch_proposal(cluster, proposal_id)?;
+            match client::governance::decode_proposal_instruction(&proposal) {
+                Some(instruction) => client::governance::execute_proposal_instruction(
+                    &signer,
+                    proposal_id,
+                    &instruction,
+                ),
+                None => client::governance::execute_proposal(&signer, proposal_id),
+            }
+        }
+        GovernanceCommand::Cancel { proposal_id } => {
+            client::governance::cancel_proposal(&signer, proposal_id)
+        }
+        GovernanceCommand::Delegate {
+            token_account,
+            to,
+            amount,
+        } => client::governance::delegate_voting_power(&signer, &token_account, &to, amount),
+        GovernanceCommand::Show { proposal_id: None } => {
+            let governance = client::fetch_governance(cluster)?;
+            return Ok(Action::Show(json!({
+                "address": client::pda::governance().0.to_string(),
+                "authority": governance.authority.to_string(),
+                "governance_token_mint": governance.governance_token_mint.to_string(),
+                "voting_period": governance.voting_period,
+                "execution_delay": governance.execution_delay,
+                "proposal_threshold": governance.proposal_threshold,
+                "quorum_threshold": governance.quorum_threshold,
+                "proposal_count": governance.proposal_count,
+                "is_active": governance.is_active,
+            })));
+        }
+        GovernanceCommand::Show {
+            proposal_id: Some(proposal_id),
+        } => {
+            let proposal = client::fetch_proposal(cluster, proposal_id)?;
+            return Ok(Action::Show(json!({
+                "id": proposal.id,
+                "proposer": proposal.proposer.to_string(),
+                "title": proposal.title,
+                "description": proposal.description,
+                "proposal_type": proposal_type_name(&proposal.proposal_type),
+                "votes_for": proposal.votes_for,
+                "votes_against": proposal.votes_against,
+                "status": proposal_status_name(&proposal.status),
+                "created_at": proposal.created_at,
+                "voting_ends_at": proposal.voting_ends_at,
+                "execution_eta": proposal.execution_eta,
+            })));
+        }
+    };
+    Ok(Action::Send(vec![instruction]))
+}
+
+fn farm(cluster: &Cluster, command: FarmCommand) -> Result<Action, Box<dyn Error>> {
+    let signer = cluster.payer();
+    let instruction = match command {
+        FarmCommand::Init {
+            reward_mint,
+            staking_mint,
+            reward_rate,
+            duration,
+        } => {
+            // The first farm on a cluster creates the registry
+            let mut instructions = Vec::new();
+            let index = match client::fetch_registry(cluster) {
+                Ok(registry) => registry.farms.len() as u64,
+                Err(ClientError::AccountNotFound(_)) => {
+                    instructions.push(client::farm::initialize_registry(&signer));
+                    0
+                }
+                Err(err) => return Err(err.into()),
+            };
+            instructions.push(client::farm::initialize_farm(
+                &signer,
+                index,
+                &reward_mint,
+                &staking_mint,
+                reward_rate,
+                duration,
+            ));
+            return Ok(Action::Send(instructions));
+        }
+        FarmCommand::Fund {
+            farm,
+            source,
+            amount,
+        } => client::farm::notify_reward_amount(&signer, &farm, &source, amount),
+        FarmCommand::AddReward { farm, reward_mint } => {
+            client::farm::add_reward_stream(&signer, &farm, &reward_mint)
+        }
+        FarmCommand::FundReward {
+            farm,
+            index,
+            source,
+            amount,
+        } => {
+            let stream = client::fetch_farm(cluster, &farm)?
+                .extra_rewards
+                .get(index as usize)
+                .copied()
+                .filter(|stream| stream.is_active())
+                .ok_or_else(|| format!("farm has no reward stream {index}"))?;
+            client::farm::fund_reward_stream(
+                &signer,
+                &farm,
+                &source,
+                &stream.reward_token_mint,
+                index,
+                amount,
+            )
+        }
+        FarmCommand::Stake {
+            farm,
+            token_account,
+            amount,
+        } => {
+            return Ok(Action::Send(vec![
+                client::farm::create_receipt_account(&signer, &farm, &signer),
+                client::farm::stake_tokens(&farm, &signer, &token_account, amount),
+            ]));
+        }
+        FarmCommand::Lock {
+            farm,
+            token_account,
+            amount,
+            weeks,
+        } => {
+            return Ok(Action::Send(vec![
+                client::farm::create_receipt_account(&signer, &farm, &signer),
+                client::farm::stake_locked(&farm, &signer, &token_account, amount, weeks),
+            ]));
+        }
+        FarmCommand::StakeFor {
+            farm,
+            token_account,
+            beneficiary,
+            amount,
+        } => {
+            return Ok(Action::Send(vec![
+                client::farm::create_receipt_account(&signer, &farm, &beneficiary),
+                client::farm::stake_for(&farm, &signer, &token_account, &beneficiary, amount),
+            ]));
+        }
+        FarmCommand::TransferStake { farm, new_owner } => {
+            return Ok(Action::Send(vec![
+                client::farm::create_receipt_account(&signer, &farm, &new_owner),
+                client::farm::transfer_stake(&farm, &signer, &new_owner),
+            ]));
+        }
+        FarmCommand::Checkpoint { farm, owners } => {
+            return Ok(Action::Send(
+                owners
+                    .iter()
+                    .map(|owner| client::farm::checkpoint(&farm, owner, &signer))
+                    .collect(),
+            ));
+        }
+        FarmCommand::ExpireLock { farm, user } => client::farm::expire_lock(&farm, &user),
+        FarmCommand::Unstake {
+            farm,
+            token_account,
+            amount,
+            close: false,
+        } => client::farm::unstake_tokens(&farm, &signer, &token_account, amount),
+        FarmCommand::Unstake {
+            farm,
+            token_account,
+            amount,
+            close: true,
+        } => client::farm::unstake_and_close(&farm, &signer, &token_account, amount),
+        FarmCommand::CloseStake { farm } => client::farm::close_user_stake(&farm, &signer),
+        FarmCommand::RequestUnstake {
+            farm,
+            token_account,
+            amount,
+        } => client::farm::request_unstake(&farm, &signer, &token_account, amount),
+        FarmCommand::WithdrawUnstaked {
+            farm,
+            token_account,
+        } => client::farm::withdraw_unstaked(&farm, &signer, &token_account),
+        FarmCommand::SetCooldown { farm, seconds } => {
+            client::farm::set_cooldown(&signer, &farm, seconds)
+        }
+        FarmCommand::SetCaps {
+            farm,
+            max_total,
+            max_per_user,
+        } => client::farm::set_deposit_caps(&signer, &farm, max_total, max_per_user),
+        FarmCommand::SetFees {
+            farm,
+            deposit_bps,
+            withdraw_bps,
+            performance_bps,
+            recipient,
+        } => client::farm::set_fee_config(
+            &signer,
+            &farm,
+            deposit_bps,
+            withdraw_bps,
+            performance_bps,
+            &recipient,
+        ),
+        FarmCommand::SetRate { farm, reward_rate } => {
+            client::farm::set_reward_rate(&signer, &farm, reward_rate)
+        }
+        FarmCommand::SetDuration { farm, seconds } => {
+            client::farm::set_duration(&signer, &farm, seconds)
+        }
+        FarmCommand::SetSchedule { farm, segments } => {
+            client::farm::set_emission_schedule(&signer, &farm, segments)
+        }
+        FarmCommand::SetAuthority {
+            farm,
+            new_authority,
+        } => client::farm::set_authority(&signer, &farm, &new_authority),
+        FarmCommand::AcceptAuthority { farm } => client::farm::accept_authority(&farm, &signer),
+        FarmCommand::CollectFees {
+            farm,
+            stake_fee_account,
+            reward_fee_account,
+        } => client::farm::collect_fees(&farm, &stake_fee_account, &reward_fee_account),
+        FarmCommand::SetVesting {
+            farm,
+            seconds,
+            penalty_bps,
+        } => {
+            let reward_mint = client::fetch_farm(cluster, &farm)?.reward_token_mint;
+            client::farm::set_vesting(&signer, &farm, &reward_mint, seconds, penalty_bps)
+        }
+        FarmCommand::Vest { farm } => client::farm::vest_rewards(&farm, &signer),
+        FarmCommand::WithdrawVested {
+            farm,
+            reward_account,
+        } => client::farm::withdraw_vested(&farm, &signer, &reward_account),
+        FarmCommand::ExitVesting {
+            farm,
+            reward_account,
+        } => client::farm::exit_vesting(&farm, &signer, &reward_account),
+        FarmCommand::Compound { farm } => client::farm::compound(&farm, &signer),
+        FarmCommand::AutoCompound { farm, enabled } => {
+            client::farm::set_auto_compound(&farm, &signer, enabled)
+        }
+        FarmCommand::CompoundFor {
+            farm,
+            user,
+            reward_account,
+        } => client::farm::compound_for(&farm, &user, &signer, &reward_account),
+        FarmCommand::Claim {
+            farm,
+            reward_account,
+            stream_accounts,
+        } if stream_accounts.is_empty() => {
+            client::farm::claim_rewards(&farm, &signer, &reward_account)
+        }
+        FarmCommand::Claim {
+            farm,
+            reward_account,
+            stream_accounts,
+        } => {
+            let mints: Vec<Pubkey> = client::fetch_farm(cluster, &farm)?
+                .extra_rewards
+                .iter()
+                .filter(|stream| stream.is_active())
+                .map(|stream| stream.reward_token_mint)
+                .collect();
+            if mints.len() != stream_accounts.len() {
+                return Err(format!(
+                    "farm has {} extra reward streams but {} --stream-account given",
+                    mints.len(),
+                    stream_accounts.len()
+                )
+                .into());
+            }
+            let streams: Vec<(Pubkey, Pubkey)> = mints.into_iter().zip(stream_accounts).collect();
+            client::farm::claim_all_rewards(&farm, &signer, &reward_account, &streams)
+        }
+        FarmCommand::Pause { farm } => client::farm::pause_farm(&signer, &farm),
+        FarmCommand::Unpause { farm } => client::farm::unpause_farm(&signer, &farm),
+        FarmCommand::EmergencyWithdraw {
+            farm,
+            token_account,
+        } => client::farm::emergency_withdraw(&farm, &signer, &token_account),
+        FarmCommand::List => {
+            let registry = client::fetch_registry(cluster)?;
+            let farms: Vec<Value> = registry
+                .farms
+                .iter()
+                .map(|address| {
+                    let farm = client::fetch_farm(cluster, address)?;
+                    Ok(json!({
+                        "address": address.to_string(),
+                        "authority": farm.authority.to_string(),
+                        "index": farm.index,
+                        "staking_token_mint": farm.staking_token_mint.to_string(),
+                        "reward_token_mint": farm.reward_token_mint.to_string(),
+                        "total_staked": farm.total_staked,
+                    }))
+                })
+                .collect::<client::Result<_>>()?;
+            return Ok(Action::Show(json!({ "farms": farms })));
+        }
+        FarmCommand::Show { farm, user } => {
+            let address = farm;
+            let farm = client::fetch_farm(cluster, &address)?;
+            let mut value = json!({
+                "address": address.to_string(),
+                "authority": farm.authority.to_string(),
+                "pending_authority": farm.pending_authority.to_string(),
+                "alloc_point": farm.alloc_point,
+                "unpaid_emissions": farm.unpaid_emissions,
+                "creator": farm.creator.to_string(),
+                "index": farm.index,
+                "reward_token_mint": farm.reward_token_mint.to_string(),
+                "staking_token_mint": farm.staking_token_mint.to_string(),
+                "staking_vault": farm.staking_vault.to_string(),
+                "reward_vault": farm.reward_vault.to_string(),
+                "receipt_mint": farm.receipt_mint.to_string(),
+                "reward_rate": farm.reward_rate,
+                "farm_duration": farm.farm_duration,
+                "cooldown_seconds": farm.cooldown_seconds,
+                "max_total_staked": farm.max_total_staked,
+                "max_stake_per_user": farm.max_stake_per_user,
+                "total_staked": farm.total_staked,
+                "total_effective_staked": farm.total_effective_staked,
+                "last_update_time": farm.last_update_time,
+                "period_finish": farm.period_finish,
+                "reward_per_token_stored": farm.reward_per_token_stored.to_string(),
+                "is_active": farm.is_active,
+                "fee_recipient": farm.fee_recipient.to_string(),
+                "deposit_fee_bps": farm.deposit_fee_bps,
+                "withdraw_fee_bps": farm.withdraw_fee_bps,
+                "performance_fee_bps": farm.performance_fee_bps,
+                "accrued_stake_fees": farm.accrued_stake_fees,
+                "accrued_reward_fees": farm.accrued_reward_fees,
+                "reward_owed": farm.reward_owed,
+                "vesting_period": farm.vesting_period,
+                "early_exit_penalty_bps": farm.early_exit_penalty_bps,
+                "extra_rewards": farm
+                    .extra_rewards
+                    .iter()
+                    .filter(|stream| stream.is_active())
+                    .map(|stream| json!({
+                        "reward_token_mint": stream.reward_token_mint.to_string(),
+                        "reward_token_account": stream.reward_token_account.to_string(),
+                        "reward_rate": stream.reward_rate,
+                        "period_finish": stream.period_finish,
+                        "reward_per_token_stored": stream.reward_per_token_stored.to_string(),
+                        "reward_owed": stream.reward_owed,
+                    }))
+                    .collect::<Vec<_>>(),
+                "emission_schedule": farm
+                    .emission_schedule
+                    .iter()
+                    .filter(|segment| segment.is_active())
+                    .map(|segment| json!({
+                        "start_time": segment.start_time,
+                        "end_time": segment.end_time,
+                        "rate": segment.rate,
+                        "end_rate": segment.end_rate,
+                        "halving_period": segment.halving_period,
+                    }))
+                    .collect::<Vec<_>>(),
+            });
+            if let Some(user) = user {
+                let stake = client::fetch_user_stake(cluster, &address, &user)?;
+                value["user_stake"] = json!({
+                    "user": stake.user.to_string(),
+                    "amount": stake.amount,
+                    "effective_amount": stake.effective_amount,
+                    "lock_weeks": stake.lock_weeks,
+                    "lock_end": stake.lock_end,
+                    "unbonding_amount": stake.unbonding_amount,
+                    "unbonding_end": stake.unbonding_end,
+                    "auto_compound": stake.auto_compound,
+                    "rewards_earned": stake.rewards_earned,
+                    "reward_per_token_paid": stake.reward_per_token_paid.to_string(),
+                    "extra_rewards_earned": farm
+                        .extra_rewards
+                        .iter()
+                        .zip(&stake.extra_rewards)
+                        .filter(|(stream, _)| stream.is_active())
+                        .map(|(_, user_stream)| user_stream.rewards_earned)
+                        .collect::<Vec<_>>(),
+                });
+                match client::fetch_vesting(cluster, &address, &user) {
+                    Ok(vesting) => {
+                        value["vesting"] = json!({
+                            "amount": vesting.amount,
+                            "released": vesting.released,
+                            "unlocked": vesting.unlocked,
+                            "start_time": vesting.start_time,
+                            "end_time": vesting.end_time,
+                        });
+                    }
+                    Err(ClientError::AccountNotFound(_)) => {}
+                    Err(err) => return Err(err.into()),
+                }
+            }
+            return Ok(Action::Show(value));
+        }
+    };
+    Ok(Action::Send(vec![instruction]))
+}
+
+fn controller(cluster: &Cluster, command: ControllerCommand) -> Result<Action, Box<dyn Error>> {
+    let signer = cluster.payer();
+    let instruction = match command {
+        ControllerCommand::Init {
+            reward_mint,
+            emission_rate,
+        } => client::farm::initialize_controller(&signer, &reward_mint, emission_rate),
+        ControllerCommand::SetRate { emission_rate } => {
+            client::farm::set_emission_rate(&signer, emission_rate)
+        }
+        ControllerCommand::SetAlloc { farm, alloc_point } => {
+            client::farm::set_alloc_point(&signer, &farm, alloc_point)
+        }
+        ControllerCommand::Update { farms } => {
+            let farms = if farms.is_empty() {
+                pools(cluster)?
+                    .into_iter()
+                    .map(|(address, _)| address)
+                    .collect()
+            } else {
+                farms
+            };
+            client::farm::mass_update_pools(&farms)
+        }
+        ControllerCommand::InitGauges {
+            governance_mint,
+            epoch_seconds,
+        } => client::farm::initialize_gauges(&signer, &governance_mint, epoch_seconds),
+        ControllerCommand::AddGauge { farm } => client::farm::create_gauge(&signer, &farm),
+        ControllerCommand::LockVotes {
+            token_account,
+            amount,
+            unlock_time,
+        } => client::farm::lock_governance_tokens(&signer, &token_account, amount, unlock_time),
+        ControllerCommand::WithdrawVotes { token_account } => {
+            client::farm::withdraw_governance_tokens(&signer, &token_account)
+        }
+        ControllerCommand::Vote { farms, weights_bps } => {
+            if farms.len() != weights_bps.len() {
+                return Err("pass one --weight-bps per --farm".into());
+            }
+            let votes = farms.into_iter().zip(weights_bps).collect::<Vec<_>>();
+            client::farm::vote_gauges(&signer, &votes)
+        }
+        ControllerCommand::UpdateGauges { farms } => {
+            let farms = if farms.is_empty() {
+                gauges(cluster)?.into_iter().map(|(farm, _)| farm).collect()
+            } else {
+                farms
+            };
+            return Ok(Action::Send(
+                farms.iter().map(client::farm::update_gauge).collect(),
+            ));
+        }
+        ControllerCommand::Show => {
+            let controller = client::fetch_controller(cluster)?;
+            let pools = pools(cluster)?
+                .into_iter()
+                .map(|(address, farm)| {
+                    json!({
+                        "farm": address.to_string(),
+                        "alloc_point": farm.alloc_point,
+                    })
+                })
+                .collect::<Vec<_>>();
+            let mut value = json!({
+                "address": client::pda::controller().0.to_string(),
+                "authority": controller.authority.to_string(),
+                "reward_token_mint": controller.reward_token_mint.to_string(),
+                "reward_vault": controller.reward_vault.to_string(),
+                "emission_rate": controller.emission_rate,
+                "total_alloc_point": controller.total_alloc_point,
+                "last_update_time": controller.last_update_time,
+                "pools": pools,
+            });
+            match client::fetch_gauge_controller(cluster) {
+                Ok(gauge_controller) => {
+                    let epoch = gauge_controller.epoch_at(unix_now());
+                    value["gauges"] = json!({
+                        "governance_token_mint": gauge_controller.governance_token_mint.to_string(),
+                        "epoch": epoch,
+                        "epoch_end": gauge_controller.epoch_end(epoch),
+                        "farms": gauges(cluster)?
+                            .into_iter()
+                            .map(|(farm, gauge)| {
+                                json!({
+                                    "farm": farm.to_string(),
+                                    "votes": if gauge.vote_epoch == epoch { gauge.votes } else { 0 },
+                                    "last_epoch_votes": gauge.votes_before(epoch),
+                                    "applied_epoch": gauge.applied_epoch,
+                                })
+                            })
+                            .collect::<Vec<_>>(),
+                    });
+                }
+                Err(ClientError::AccountNotFound(_)) => {}
+                Err(err) => return Err(err.into()),
+            }
+            return Ok(Action::Show(value));
+        }
+    };
+    Ok(Action::Send(vec![instruction]))
+}
+
+/// Registered farms with a gauge, and their gauges.
+fn gauges(cluster: &Cluster) -> Result<Vec<(Pubkey, client::Gauge)>, Box<dyn Error>> {
+    let mut gauges = Vec::new();
+    for farm in client::fetch_registry(cluster)?.farms {
+        match client::fetch_gauge(cluster, &farm) {
+            Ok(gauge) => gauges.push((farm, gauge)),
+            Err(ClientError::AccountNotFound(_)) => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(gauges)
+}
+
+/// Registered farms holding a share of the controller emission.
+fn pools(cluster: &Cluster) -> Result<Vec<(Pubkey, client::Farm)>, Box<dyn Error>> {
+    let mut pools = Vec::new();
+    for address in client::fetch_registry(cluster)?.farms {
+        let farm = client::fetch_farm(cluster, &address)?;
+        if farm.alloc_point > 0 {
+            pools.push((address, farm));
+        }
+    }
+    Ok(pools)
+}
+
+fn proposal_type_name(proposal_type: &client::ProposalType) -> &'static str {
+    match proposal_type {
+        client::ProposalType::ParameterChange => "parameter-change",
+        client::ProposalType::TreasurySpend => "treasury-spend",
+        client::ProposalType::UpgradeContract => "upgrade-contract",
+        client::ProposalType::AddFarm => "add-farm",
+    }
+}
+
+fn proposal_status_name(status: &client::ProposalStatus) -> &'static str {
+    match status {
+        client::ProposalStatus::Active => "active",
+        client::ProposalStatus::Queued => "queued",
+        client::ProposalStatus::Executed => "executed",
+        client::ProposalStatus::Cancelled => "cancelled",
+        client::ProposalStatus::Failed => "failed",
+    }
+}
+
+/// Local wall-clock time, close enough to the cluster clock for display.
+fn unix_now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
+
+fn expand_home(path: &str) -> String {
+    match (path.strip_prefix("~/"), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) => format!("{home}/{rest}"),
+        _ => path.to_string(),
+    }
+}
+
+fn parse_hex(data: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    if !data.is_ascii() || !data.len().is_multiple_of(2) {
+        return Err("execution data must be an even number of hex digits".into());
+    }
+    (0..data.len())
+        .step_by(2)
+        .map(|i| Ok(u8::from_str_radix(&data[i..i + 2], 16)?))
+        .collect()
+}
+
+/// Parses `START:END:RATE[:END_RATE[:HALVING_SECONDS]]`.
+fn parse_segment(segment: &str) -> Result<client::EmissionSegment, String> {
+    let fields = segment
+        .split(':')
+        .map(|field| {
+            field
+                .parse::<i128>()
+                .map_err(|err| format!("{field}: {err}"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let [start_time, end_time, rate, rest @ ..] = fields.as_slice() else {
+        return Err("expected START:END:RATE[:END_RATE[:HALVING_SECONDS]]".into());
+    };
+    if rest.len() > 2 {
+        return Err("expected START:END:RATE[:END_RATE[:HALVING_SECONDS]]".into());
+    }
+    let field = |value: i128| i64::try_from(value).map_err(|err| err.to_string());
+    let amount = |value: i128| u64::try_from(value).map_err(|err| err.to_string());
+    Ok(client::EmissionSegment {
+        start_time: field(*start_time)?,
+        end_time: field(*end_time)?,
+        rate: amount(*rate)?,
+        end_rate: amount(*rest.first().unwrap_or(rate))?,
+        halving_period: field(*rest.get(1).unwrap_or(&0))?,
+    })
+}
+
+fn print(value: &Value, json: bool) {
+    if json {
+        println!("{value}");
+    } else {
+        print_text(value, 0);
+    }
+}
+
+fn print_text(value: &Value, indent: usize) {
+    let pad = " ".repeat(indent);
+    match value {
+        Value::Object(fields) => {
+            for (key, field) in fields {
+                match field {
+                    Value::Object(_) | Value::Array(_) => {
+                        println!("{pad}{key}:");
+                        print_text(field, indent + 2);
+                    }
+                    Value::String(text) => println!("{pad}{key}: {text}"),
+                    other => println!("{pad}{key}: {other}"),
+                }
+            }
+        }
+        Value::Array(items) => {
+            for (index, item) in items.iter().enumerate() {
+                println!("{pad}[{index}]");
+                print_text(item, indent + 2);
+            }
+        }
+        Value::String(text) => println!("{pad}{text}"),
+        other => println!("{pad}{other}"),
+    }
 }
@@ -1,7 +1,9 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Token, TokenAccount, Mint};
+use anchor_lang::solana_program::instruction::Instruction;
+use anchor_lang::solana_program::program::invoke_signed;
+use anchor_spl::token::{TokenAccount, Mint};
 
-declare_id!("GovernanceProtocol1111111111111111111111111");
+declare_id!("GovernanceProtocoL1111111111111111111111111");
 
 #[program]
 pub mod governance {
@@ -23,6 +25,16 @@ pub mod governance {
         governance.quorum_threshold = quorum_threshold;
         governance.proposal_count = 0;
         governance.is_active = true;
+
+        emit!(GovernanceInitialized {
+            governance: governance.key(),
+            authority: governance.authority,
+            governance_token_mint: governance.governance_token_mint,
+            voting_period,
+            execution_delay,
+            proposal_threshold,
+            quorum_threshold,
+        });
         
         Ok(())
     }
@@ -59,6 +71,17 @@ pub mod governance {
 
         governance.proposal_count += 1;
 
+        emit!(ProposalCreated {
+            governance: governance.key(),
+            proposal: proposal.key(),
+            proposal_id: proposal.id,
+            proposer: proposal.proposer,
+            title: proposal.title.clone(),
+            proposal_type: proposal.proposal_type.clone(),
+            created_at: proposal.created_at,
+            voting_ends_at: proposal.voting_ends_at,
+        });
+
         Ok(())
     }
 
@@ -93,14 +116,14 @@ pub mod governance {
 
         // Check if user hasn't voted already
         require!(
-            vote_record.has_voted == false,
+            !vote_record.has_voted,
             ErrorCode::AlreadyVoted
         );
 
         // Record the vote
         vote_record.voter = ctx.accounts.voter.key();
         vote_record.proposal_id = proposal_id;
-        vote_record.vote = vote;
+        vote_record.vote = vote.clone();
         vote_record.voting_power = voting_power;
         vote_record.has_voted = true;
 
@@ -110,6 +133,16 @@ pub mod governance {
             VoteType::Against => proposal.votes_against += voting_power,
         }
 
+        emit!(VoteCast {
+            proposal: proposal.key(),
+            proposal_id,
+            voter: vote_record.voter,
+            vote,
+            voting_power,
+            votes_for: proposal.votes_for,
+            votes_against: proposal.votes_against,
+        });
+
         Ok(())
     }
 
@@ -145,10 +178,24 @@ pub mod governance {
         proposal.status = ProposalStatus::Queued;
         proposal.execution_eta = current_time + governance.execution_delay;
 
+        emit!(ProposalQueued {
+            proposal: proposal.key(),
+            proposal_id,
+            votes_for: proposal.votes_for,
+            votes_against: proposal.votes_against,
+            execution_eta: proposal.execution_eta,
+        });
+
         Ok(())
     }
 
-    pub fn execute_proposal(ctx: Context<ExecuteProposal>, proposal_id: u64) -> Result<()> {
+    /// Parameter change proposals carrying a `ProposalInstruction` invoke it
+    /// signed by the governance PDA. `remaining_accounts` must hold the target
+    /// program followed by the instruction's accounts, in order.
+    pub fn execute_proposal<'info>(
+        ctx: Context<'_, '_, '_, 'info, ExecuteProposal<'info>>,
+        proposal_id: u64,
+    ) -> Result<()> {
         let proposal = &mut ctx.accounts.proposal;
 
         // Check if proposal is queued
@@ -169,6 +216,14 @@ pub mod governance {
             ProposalType::ParameterChange => {
                 // Handle parameter changes
                 msg!("Executing parameter change proposal");
+                if !proposal.execution_data.is_empty() {
+                    invoke_proposal_instruction(
+                        &proposal.execution_data,
+                        &ctx.accounts.governance.to_account_info(),
+                        ctx.remaining_accounts,
+                        ctx.bumps.governance,
+                    )?;
+                }
             },
             ProposalType::TreasurySpend => {
                 // Handle treasury spending
@@ -186,6 +241,43 @@ pub mod governance {
 
         proposal.status = ProposalStatus::Executed;
 
+        emit!(ProposalExecuted {
+            proposal: proposal.key(),
+            proposal_id,
+            proposal_type: proposal.proposal_type.clone(),
+            executor: ctx.accounts.executor.key(),
+            executed_at: current_time,
+        });
+
+        Ok(())
+    }
+
+    pub fn cancel_proposal(ctx: Context<CancelProposal>, proposal_id: u64) -> Result<()> {
+        let governance = &ctx.accounts.governance;
+        let proposal = &mut ctx.accounts.proposal;
+        let canceller = ctx.accounts.canceller.key();
+
+        // Only the proposer or the governance authority can cancel
+        require!(
+            canceller == proposal.proposer || canceller == governance.authority,
+            ErrorCode::Unauthorized
+        );
+
+        // Executed, failed or already cancelled proposals are final
+        require!(
+            proposal.status == ProposalStatus::Active || proposal.status == ProposalStatus::Queued,
+            ErrorCode::ProposalNotCancellable
+        );
+
+        proposal.status = ProposalStatus::Cancelled;
+
+        emit!(ProposalCancelled {
+            proposal: proposal.key(),
+            proposal_id,
+            cancelled_by: canceller,
+            cancelled_at: Clock::get()?.unix_timestamp,
+        });
+
         Ok(())
     }
 
@@ -207,10 +299,71 @@ pub mod governance {
         delegation.amount = amount;
         delegation.created_at = Clock::get()?.unix_timestamp;
 
+        emit!(DelegationChanged {
+            delegator: delegation.delegator,
+            delegate: delegation.delegate,
+            amount: delegation.amount,
+            created_at: delegation.created_at,
+        });
+
         Ok(())
     }
 }
 
+fn invoke_proposal_instruction<'info>(
+    execution_data: &[u8],
+    governance: &AccountInfo<'info>,
+    remaining_accounts: &[AccountInfo<'info>],
+    governance_bump: u8,
+) -> Result<()> {
+    let instruction = ProposalInstruction::try_from_slice(execution_data)
+        .map_err(|_| ErrorCode::InvalidExecutionData)?;
+
+    // Proposals cannot re-enter governance, which has not yet recorded the execution
+    require!(instruction.program_id != crate::ID, ErrorCode::InvalidExecutionData);
+
+    // The executor must pass exactly the accounts the DAO voted on
+    let (program, accounts) = remaining_accounts
+        .split_first()
+        .ok_or(ErrorCode::InvalidExecutionAccounts)?;
+    require!(
+        program.key() == instruction.program_id
+            && accounts.len() == instruction.accounts.len()
+            && accounts
+                .iter()
+                .zip(&instruction.accounts)
+                .all(|(account, meta)| account.key() == meta.pubkey),
+        ErrorCode::InvalidExecutionAccounts
+    );
+
+    let metas = instruction
+        .accounts
+        .iter()
+        .map(|meta| {
+            if meta.is_writable {
+                AccountMeta::new(meta.pubkey, meta.is_signer)
+            } else {
+                AccountMeta::new_readonly(meta.pubkey, meta.is_signer)
+            }
+        })
+        .collect();
+    let mut account_infos = accounts.to_vec();
+    account_infos.push(governance.clone());
+    account_infos.push(program.clone());
+
+    invoke_signed(
+        &Instruction {
+            program_id: instruction.program_id,
+            accounts: metas,
+            data: instruction.data,
+        },
+        &account_infos,
+        &[&[b"governance".as_ref(), &[governance_bump]]],
+    )?;
+
+    Ok(())
+}
+
 #[derive(Accounts)]
 pub struct InitializeGovernance<'info> {
     #[account(
@@ -231,7 +384,6 @@ pub struct InitializeGovernance<'info> {
 }
 
 #[derive(Accounts)]
-#[instruction(proposal_id: u64)]
 pub struct CreateProposal<'info> {
     #[account(mut)]
     pub governance: Account<'info, Governance>,
@@ -240,7 +392,7 @@ pub struct CreateProposal<'info> {
         init,
         payer = proposer,
         space = 8 + Proposal::LEN,
-        seeds = [b"proposal", governance.key().as_ref(), &proposal_id.to_le_bytes()],
+        seeds = [b"proposal", governance.key().as_ref(), &governance.proposal_count.to_le_bytes()],
         bump
     )]
     pub proposal: Account<'info, Proposal>,
@@ -286,12 +438,26 @@ pub struct QueueProposal<'info> {
 
 #[derive(Accounts)]
 pub struct ExecuteProposal<'info> {
+    #[account(seeds = [b"governance"], bump)]
+    pub governance: Account<'info, Governance>,
+    
     #[account(mut)]
     pub proposal: Account<'info, Proposal>,
     
     pub executor: Signer<'info>,
 }
 
+#[derive(Accounts)]
+pub struct CancelProposal<'info> {
+    #[account(seeds = [b"governance"], bump)]
+    pub governance: Account<'info, Governance>,
+    
+    #[account(mut)]
+    pub proposal: Account<'info, Proposal>,
+    
+    pub canceller: Signer<'info>,
+}
+
 #[derive(Accounts)]
 pub struct DelegateVotingPower<'info> {
     #[account(
@@ -380,6 +546,22 @@ pub enum ProposalType {
     AddFarm,
 }
 
+/// Instruction a parameter change proposal executes, signed by the governance
+/// PDA. Stored Borsh-encoded in `Proposal::execution_data`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct ProposalInstruction {
+    pub program_id: Pubkey,
+    pub accounts: Vec<ProposalAccountMeta>,
+    pub data: Vec<u8>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct ProposalAccountMeta {
+    pub pubkey: Pubkey,
+    pub is_signer: bool,
+    pub is_writable: bool,
+}
+
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
 pub enum ProposalStatus {
     Active,
@@ -395,6 +577,74 @@ pub enum VoteType {
     Against,
 }
 
+#[event]
+pub struct GovernanceInitialized {
+    pub governance: Pubkey,
+    pub authority: Pubkey,
+    pub governance_token_mint: Pubkey,
+    pub voting_period: i64,
+    pub execution_delay: i64,
+    pub proposal_threshold: u64,
+    pub quorum_threshold: u64,
+}
+
+#[event]
+pub struct ProposalCreated {
+    pub governance: Pubkey,
+    pub proposal: Pubkey,
+    pub proposal_id: u64,
+    pub proposer: Pubkey,
+    pub title: String,
+    pub proposal_type: ProposalType,
+    pub created_at: i64,
+    pub voting_ends_at: i64,
+}
+
+#[event]
+pub struct VoteCast {
+    pub proposal: Pubkey,
+    pub proposal_id: u64,
+    pub voter: Pubkey,
+    pub vote: VoteType,
+    pub voting_power: u64,
+    pub votes_for: u64,
+    pub votes_against: u64,
+}
+
+#[event]
+pub struct DelegationChanged {
+    pub delegator: Pubkey,
+    pub delegate: Pubkey,
+    pub amount: u64,
+    pub created_at: i64,
+}
+
+#[event]
+pub struct ProposalQueued {
+    pub proposal: Pubkey,
+    pub proposal_id: u64,
+    pub votes_for: u64,
+    pub votes_against: u64,
+    pub execution_eta: i64,
+}
+
+#[event]
+pub struct ProposalExecuted {
+    pub proposal: Pubkey,
+    pub proposal_id: u64,
+    pub proposal_type: ProposalType,
+    pub executor: Pubkey,
+    pub executed_at: i64,
+}
+
+#[event]
+pub struct ProposalCancelled {
+    pub proposal: Pubkey,
+    pub proposal_id: u64,
+    pub cancelled_by: Pubkey,
+    pub cancelled_at: i64,
+}
+
 #[error_code]
 pub enum ErrorCode {
     #[msg("Insufficient tokens to create proposal")]
@@ -419,4 +669,12 @@ pub enum ErrorCode {
     ExecutionDelayNotMet,
     #[msg("Insufficient tokens for delegation")]
     InsufficientTokensForDelegation,
+    #[msg("Signer is not authorized for this action")]
+    Unauthorized,
+    #[msg("Proposal can no longer be cancelled")]
+    ProposalNotCancellable,
+    #[msg("Execution data is not a valid instruction")]
+    InvalidExecutionData,
+    #[msg("Accounts do not match the proposal's instruction")]
+    InvalidExecutionAccounts,
 }
@@ -1,27 +1,57 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Token, TokenAccount, Transfer};
+use anchor_spl::associated_token::get_associated_token_address;
+use anchor_spl::token::{self, Burn, FreezeAccount, MintTo, ThawAccount, Token, TokenAccount, Transfer};
 
-declare_id!("YieldFarmingProtocol11111111111111111111111");
+pub mod math;
+
+declare_id!("YieLdFarmingProtocoL11111111111111111111111");
 
 #[program]
 pub mod yield_farming {
     use super::*;
 
+    pub fn initialize_registry(_ctx: Context<InitializeRegistry>) -> Result<()> {
+        Ok(())
+    }
+
     pub fn initialize_farm(
         ctx: Context<InitializeFarm>,
         reward_rate: u64,
         farm_duration: i64,
     ) -> Result<()> {
+        require!(farm_duration > 0, ErrorCode::InvalidDuration);
+        
+        let current_time = Clock::get()?.unix_timestamp;
         let farm = &mut ctx.accounts.farm;
+        let registry = &mut ctx.accounts.registry;
         farm.authority = ctx.accounts.authority.key();
+        farm.creator = farm.authority;
+        farm.index = registry.farms.len() as u64;
         farm.reward_token_mint = ctx.accounts.reward_token_mint.key();
         farm.staking_token_mint = ctx.accounts.staking_token_mint.key();
+        farm.staking_vault = ctx.accounts.staking_vault.key();
+        farm.reward_vault = ctx.accounts.reward_vault.key();
+        farm.receipt_mint = ctx.accounts.receipt_mint.key();
         farm.reward_rate = reward_rate;
         farm.farm_duration = farm_duration;
         farm.total_staked = 0;
-        farm.last_update_time = Clock::get()?.unix_timestamp;
+        farm.last_update_time = current_time;
+        farm.period_finish = current_time + farm_duration;
         farm.reward_per_token_stored = 0;
+        farm.reward_dust = 0;
         farm.is_active = true;
+        farm.snapshot_week = current_time.div_euclid(SECONDS_PER_WEEK);
+        
+        // List the farm so clients can discover it
+        registry.farms.push(farm.key());
+        
+        emit!(FarmCreated {
+            farm: farm.key(),
+            authority: farm.authority,
+            index: farm.index,
+            reward_token_mint: farm.reward_token_mint,
+            staking_token_mint: farm.staking_token_mint,
+        });
         
         Ok(())
     }
@@ -30,39 +60,83 @@ pub mod yield_farming {
         let farm = &mut ctx.accounts.farm;
         let user_stake = &mut ctx.accounts.user_stake;
         
-        // Update rewards before changing stake
-        update_reward(farm, user_stake)?;
+        require!(farm.is_active, ErrorCode::FarmNotActive);
         
-        // Transfer tokens from user to farm
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.user_token_account.to_account_info(),
-            to: ctx.accounts.farm_token_account.to_account_info(),
-            authority: ctx.accounts.user.to_account_info(),
-        };
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
-        token::transfer(cpi_ctx, amount)?;
+        let current_time = Clock::get()?.unix_timestamp;
+        let fee = deposit(
+            farm,
+            user_stake,
+            ctx.accounts.user.key(),
+            &ctx.accounts.user,
+            &ctx.accounts.user_token_account,
+            &ctx.accounts.farm_token_account,
+            &ctx.accounts.receipt_mint,
+            &ctx.accounts.user_receipt_account,
+            &ctx.accounts.token_program,
+            ctx.bumps.farm,
+            amount,
+            false,
+            current_time,
+        )?;
         
-        // Update stake info
-        user_stake.amount += amount;
-        farm.total_staked += amount;
+        emit!(TokensStaked {
+            user: ctx.accounts.user.key(),
+            farm: farm.key(),
+            amount,
+            fee,
+            user_staked: user_stake.amount,
+            total_staked: farm.total_staked,
+            reward_per_token_stored: farm.reward_per_token_stored,
+            timestamp: current_time,
+        });
         
         Ok(())
     }
 
-    pub fn unstake_tokens(ctx: Context<UnstakeTokens>, amount: u64) -> Result<()> {
+    /// With `close_if_empty`, a full exit also closes the position and
+    /// refunds its rent. A position still holding rewards or unbonding
+    /// tokens stays open.
+    pub fn unstake_tokens(
+        ctx: Context<UnstakeTokens>,
+        amount: u64,
+        close_if_empty: bool,
+    ) -> Result<()> {
         let farm = &mut ctx.accounts.farm;
         let user_stake = &mut ctx.accounts.user_stake;
         
+        // Update rewards before changing stake
+        let current_time = Clock::get()?.unix_timestamp;
+        checkpoint_receipts(
+            farm,
+            user_stake,
+            ctx.accounts.user_receipt_account.amount,
+            ctx.accounts.receipt_mint.supply,
+            current_time,
+        )?;
+        
         require!(user_stake.amount >= amount, ErrorCode::InsufficientStake);
+        require!(farm.cooldown_seconds == 0, ErrorCode::CooldownRequired);
+        require!(current_time >= user_stake.lock_end, ErrorCode::StakeLocked);
         
-        // Update rewards before changing stake
-        update_reward(farm, user_stake)?;
+        burn_receipts(
+            farm,
+            ctx.bumps.farm,
+            &ctx.accounts.receipt_mint,
+            &ctx.accounts.user_receipt_account,
+            &ctx.accounts.user,
+            &ctx.accounts.token_program,
+            amount,
+        )?;
+        
+        // Withdraw fees stay in the vault until collect_fees
+        let fee = math::fee(amount, farm.withdraw_fee_bps);
         
         // Transfer tokens from farm to user
+        let index = farm.index.to_le_bytes();
         let seeds = &[
             b"farm".as_ref(),
-            farm.authority.as_ref(),
+            farm.creator.as_ref(),
+            index.as_ref(),
             &[ctx.bumps.farm],
         ];
         let signer = &[&seeds[..]];
@@ -74,11 +148,28 @@ pub mod yield_farming {
         };
         let cpi_program = ctx.accounts.token_program.to_account_info();
         let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
-        token::transfer(cpi_ctx, amount)?;
+        token::transfer(cpi_ctx, amount - fee)?;
         
         // Update stake info
         user_stake.amount -= amount;
         farm.total_staked -= amount;
+        farm.accrued_stake_fees += fee;
+        sync_effective_stake(farm, user_stake)?;
+        
+        emit!(TokensUnstaked {
+            user: ctx.accounts.user.key(),
+            farm: farm.key(),
+            amount,
+            fee,
+            user_staked: user_stake.amount,
+            total_staked: farm.total_staked,
+            reward_per_token_stored: farm.reward_per_token_stored,
+            timestamp: current_time,
+        });
+        
+        if close_if_empty && user_stake.is_empty() {
+            close_position(user_stake, &ctx.accounts.user)?;
+        }
         
         Ok(())
     }
@@ -87,15 +178,28 @@ pub mod yield_farming {
         let farm = &mut ctx.accounts.farm;
         let user_stake = &mut ctx.accounts.user_stake;
         
-        update_reward(farm, user_stake)?;
+        require!(farm.vesting_period == 0, ErrorCode::RewardsMustVest);
+        
+        let current_time = Clock::get()?.unix_timestamp;
+        let receipt_info = ctx.accounts.receipt_account.to_account_info();
+        let balance = load_receipt_account(&receipt_info, &ctx.accounts.user.key(), &farm.receipt_mint)?
+            .map_or(0, |account| account.amount);
+        checkpoint_receipts(farm, user_stake, balance, ctx.accounts.receipt_mint.supply, current_time)?;
         
         let rewards = user_stake.rewards_earned;
         require!(rewards > 0, ErrorCode::NoRewards);
+        require!(
+            farm.claimable_reward_balance(&ctx.accounts.reward_token_account) >= rewards,
+            ErrorCode::InsufficientRewardFunds
+        );
+        let fee = math::fee(rewards, farm.performance_fee_bps);
         
         // Transfer reward tokens to user
+        let index = farm.index.to_le_bytes();
         let seeds = &[
             b"farm".as_ref(),
-            farm.authority.as_ref(),
+            farm.creator.as_ref(),
+            index.as_ref(),
             &[ctx.bumps.farm],
         ];
         let signer = &[&seeds[..]];
@@ -107,179 +211,4224 @@ pub mod yield_farming {
         };
         let cpi_program = ctx.accounts.token_program.to_account_info();
         let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
-        token::transfer(cpi_ctx, rewards)?;
+        token::transfer(cpi_ctx, rewards - fee)?;
         
         user_stake.rewards_earned = 0;
+        farm.reward_owed = farm.reward_owed.saturating_sub(rewards);
+        farm.accrued_reward_fees += fee;
+        
+        emit!(RewardsClaimed {
+            user: ctx.accounts.user.key(),
+            farm: farm.key(),
+            rewards_paid: rewards - fee,
+            fee,
+            total_staked: farm.total_staked,
+            reward_per_token_stored: farm.reward_per_token_stored,
+            timestamp: current_time,
+        });
         
         Ok(())
     }
-}
 
-fn update_reward(farm: &mut Farm, user_stake: &mut UserStake) -> Result<()> {
-    let current_time = Clock::get()?.unix_timestamp;
-    
-    if farm.total_staked > 0 {
-        let time_diff = current_time - farm.last_update_time;
-        let reward_per_token_increase = (farm.reward_rate as u128)
-            .checked_mul(time_diff as u128)
-            .unwrap()
-            .checked_div(farm.total_staked as u128)
-            .unwrap();
+    pub fn notify_reward_amount(ctx: Context<NotifyRewardAmount>, amount: u64) -> Result<()> {
+        require!(!ctx.accounts.farm.has_emission_schedule(), ErrorCode::EmissionScheduleActive);
+        
+        let current_time = Clock::get()?.unix_timestamp;
+        update_farm(&mut ctx.accounts.farm, current_time)?;
+        
+        // Transfer the new rewards into the reward vault
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.funder_token_account.to_account_info(),
+            to: ctx.accounts.reward_token_account.to_account_info(),
+            authority: ctx.accounts.authority.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        token::transfer(cpi_ctx, amount)?;
+        ctx.accounts.reward_token_account.reload()?;
+        
+        let farm = &mut ctx.accounts.farm;
+        let duration = farm.farm_duration as u128;
+        
+        // Roll whatever is left of the current period into the new one
+        let leftover = if current_time < farm.period_finish {
+            (farm.period_finish - current_time) as u128 * farm.reward_rate as u128
+        } else {
+            0
+        };
+        let reward_rate = (amount as u128 + leftover) / duration;
+        
+        // The vault must hold everything the new rate promises
+        require!(
+            reward_rate * duration
+                <= farm.uncommitted_reward_balance(&ctx.accounts.reward_token_account) as u128,
+            ErrorCode::InsufficientRewardBalance
+        );
+        
+        farm.reward_rate = u64::try_from(reward_rate).map_err(|_| ErrorCode::MathOverflow)?;
+        farm.last_update_time = current_time;
+        farm.period_finish = current_time + farm.farm_duration;
+        
+        emit!(RewardAdded {
+            farm: farm.key(),
+            amount,
+            reward_rate: farm.reward_rate,
+            period_finish: farm.period_finish,
+        });
         
-        farm.reward_per_token_stored += reward_per_token_increase as u64;
+        Ok(())
     }
-    
-    if user_stake.amount > 0 {
-        let user_reward_increase = (user_stake.amount as u128)
-            .checked_mul((farm.reward_per_token_stored - user_stake.reward_per_token_paid) as u128)
-            .unwrap()
-            .checked_div(1_000_000_000 as u128) // Scale factor
-            .unwrap();
+
+    pub fn add_reward_stream(ctx: Context<AddRewardStream>) -> Result<()> {
+        let farm = &mut ctx.accounts.farm;
+        let reward_token_mint = ctx.accounts.reward_token_mint.key();
+        
+        require!(
+            reward_token_mint != farm.reward_token_mint
+                && !farm.extra_rewards.iter().any(|stream| stream.reward_token_mint == reward_token_mint),
+            ErrorCode::DuplicateRewardStream
+        );
+        let index = farm
+            .extra_rewards
+            .iter()
+            .position(|stream| !stream.is_active())
+            .ok_or(ErrorCode::RewardStreamsFull)?;
+        
+        // The stream starts empty and is funded through fund_reward_stream
+        let current_time = Clock::get()?.unix_timestamp;
+        farm.extra_rewards[index] = RewardStream {
+            reward_token_mint,
+            reward_token_account: ctx.accounts.reward_token_account.key(),
+            reward_rate: 0,
+            period_finish: current_time,
+            last_update_time: current_time,
+            reward_per_token_stored: 0,
+            reward_dust: 0,
+            reward_owed: 0,
+        };
+        
+        emit!(RewardStreamAdded {
+            farm: farm.key(),
+            index: index as u8,
+            reward_token_mint,
+            reward_token_account: ctx.accounts.reward_token_account.key(),
+        });
         
-        user_stake.rewards_earned += user_reward_increase as u64;
+        Ok(())
     }
-    
-    user_stake.reward_per_token_paid = farm.reward_per_token_stored;
-    farm.last_update_time = current_time;
-    
-    Ok(())
-}
 
-#[derive(Accounts)]
-pub struct InitializeFarm<'info> {
-    #[account(
-        init,
-        payer = authority,
-        space = 8 + Farm::LEN,
-        seeds = [b"farm", authority.key().as_ref()],
-        bump
-    )]
-    pub farm: Account<'info, Farm>,
-    
-    #[account(mut)]
-    pub authority: Signer<'info>,
-    
-    pub reward_token_mint: Account<'info, token::Mint>,
-    pub staking_token_mint: Account<'info, token::Mint>,
-    
-    pub system_program: Program<'info, System>,
-}
+    pub fn fund_reward_stream(
+        ctx: Context<FundRewardStream>,
+        index: u8,
+        amount: u64,
+    ) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+        update_farm(&mut ctx.accounts.farm, current_time)?;
+        
+        let stream = ctx
+            .accounts
+            .farm
+            .extra_rewards
+            .get(index as usize)
+            .filter(|stream| stream.is_active())
+            .ok_or(ErrorCode::InvalidRewardStream)?;
+        require_keys_eq!(
+            stream.reward_token_account,
+            ctx.accounts.reward_token_account.key(),
+            ErrorCode::InvalidRewardVault
+        );
+        
+        // Transfer the new rewards into the stream's vault
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.funder_token_account.to_account_info(),
+            to: ctx.accounts.reward_token_account.to_account_info(),
+            authority: ctx.accounts.authority.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        token::transfer(cpi_ctx, amount)?;
+        ctx.accounts.reward_token_account.reload()?;
+        
+        let farm_key = ctx.accounts.farm.key();
+        let farm = &mut ctx.accounts.farm;
+        let farm_duration = farm.farm_duration;
+        let stream = &mut farm.extra_rewards[index as usize];
+        
+        // Roll whatever is left of the current period into the new one
+        let duration = farm_duration as u128;
+        let leftover = if current_time < stream.period_finish {
+            (stream.period_finish - current_time) as u128 * stream.reward_rate as u128
+        } else {
+            0
+        };
+        let reward_rate = (amount as u128 + leftover) / duration;
+        
+        // The vault must hold everything the new rate promises
+        require!(
+            reward_rate * duration <= stream.uncommitted_balance(&ctx.accounts.reward_token_account) as u128,
+            ErrorCode::InsufficientRewardBalance
+        );
+        
+        stream.reward_rate = u64::try_from(reward_rate).map_err(|_| ErrorCode::MathOverflow)?;
+        stream.last_update_time = current_time;
+        stream.period_finish = current_time + farm_duration;
+        
+        emit!(RewardStreamFunded {
+            farm: farm_key,
+            index,
+            amount,
+            reward_rate: stream.reward_rate,
+            period_finish: stream.period_finish,
+        });
+        
+        Ok(())
+    }
 
-#[derive(Accounts)]
-pub struct StakeTokens<'info> {
-    #[account(
-        mut,
-        seeds = [b"farm", farm.authority.as_ref()],
-        bump
-    )]
-    pub farm: Account<'info, Farm>,
-    
-    #[account(
-        init_if_needed,
-        payer = user,
-        space = 8 + UserStake::LEN,
-        seeds = [b"user_stake", farm.key().as_ref(), user.key().as_ref()],
-        bump
-    )]
-    pub user_stake: Account<'info, UserStake>,
-    
-    #[account(mut)]
-    pub user: Signer<'info>,
-    
-    #[account(mut)]
-    pub user_token_account: Account<'info, TokenAccount>,
-    
-    #[account(mut)]
-    pub farm_token_account: Account<'info, TokenAccount>,
-    
-    pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
-}
+    /// Pays the primary reward and every extra reward stream. `remaining_accounts`
+    /// holds a `[stream vault, user token account]` pair for each active stream, in
+    /// stream order. The performance fee applies to the primary reward only.
+    pub fn claim_all_rewards<'c: 'info, 'info>(
+        ctx: Context<'_, '_, 'c, 'info, ClaimRewards<'info>>,
+    ) -> Result<()> {
+        let farm = &mut ctx.accounts.farm;
+        let user_stake = &mut ctx.accounts.user_stake;
+        
+        let current_time = Clock::get()?.unix_timestamp;
+        let receipt_info = ctx.accounts.receipt_account.to_account_info();
+        let balance = load_receipt_account(&receipt_info, &ctx.accounts.user.key(), &farm.receipt_mint)?
+            .map_or(0, |account| account.amount);
+        checkpoint_receipts(farm, user_stake, balance, ctx.accounts.receipt_mint.supply, current_time)?;
+        
+        let active_streams: Vec<usize> = (0..MAX_EXTRA_REWARDS)
+            .filter(|&index| farm.extra_rewards[index].is_active())
+            .collect();
+        require!(
+            ctx.remaining_accounts.len() == active_streams.len() * 2,
+            ErrorCode::InvalidRewardStream
+        );
+        // Vesting farms leave the primary reward to `vest_rewards`
+        let rewards = if farm.vesting_period == 0 {
+            user_stake.rewards_earned
+        } else {
+            0
+        };
+        require!(
+            rewards > 0
+                || active_streams
+                    .iter()
+                    .any(|&index| user_stake.extra_rewards[index].rewards_earned > 0),
+            ErrorCode::NoRewards
+        );
+        
+        let farm_key = farm.key();
+        let creator = farm.creator;
+        let index = farm.index.to_le_bytes();
+        let seeds = &[
+            b"farm".as_ref(),
+            creator.as_ref(),
+            index.as_ref(),
+            &[ctx.bumps.farm],
+        ];
+        let signer = &[&seeds[..]];
+        
+        let mut fee = 0;
+        if rewards > 0 {
+            require!(
+                farm.claimable_reward_balance(&ctx.accounts.reward_token_account) >= rewards,
+                ErrorCode::InsufficientRewardFunds
+            );
+            fee = math::fee(rewards, farm.performance_fee_bps);
+            let cpi_accounts = Transfer {
+                from: ctx.accounts.reward_token_account.to_account_info(),
+                to: ctx.accounts.user_reward_account.to_account_info(),
+                authority: farm.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+            token::transfer(cpi_ctx, rewards - fee)?;
+            user_stake.rewards_earned = 0;
+            farm.reward_owed = farm.reward_owed.saturating_sub(rewards);
+            
+            emit!(RewardsClaimed {
+                user: ctx.accounts.user.key(),
+                farm: farm_key,
+                rewards_paid: rewards - fee,
+                fee,
+                total_staked: farm.total_staked,
+                reward_per_token_stored: farm.reward_per_token_stored,
+                timestamp: current_time,
+            });
+        }
+        
+        for (accounts, &index) in ctx.remaining_accounts.chunks(2).zip(&active_streams) {
+            let stream = farm.extra_rewards[index];
+            let rewards = user_stake.extra_rewards[index].rewards_earned;
+            if rewards == 0 {
+                continue;
+            }
+            
+            let vault = Account::<TokenAccount>::try_from(&accounts[0])?;
+            let user_account = Account::<TokenAccount>::try_from(&accounts[1])?;
+            require_keys_eq!(vault.key(), stream.reward_token_account, ErrorCode::InvalidRewardVault);
+            require_keys_eq!(user_account.mint, stream.reward_token_mint, ErrorCode::InvalidTokenMint);
+            require!(vault.amount >= rewards, ErrorCode::InsufficientRewardFunds);
+            
+            let cpi_accounts = Transfer {
+                from: accounts[0].clone(),
+                to: accounts[1].clone(),
+                authority: farm.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+            token::transfer(cpi_ctx, rewards)?;
+            user_stake.extra_rewards[index].rewards_earned = 0;
+            let stream = &mut farm.extra_rewards[index];
+            stream.reward_owed = stream.reward_owed.saturating_sub(rewards);
+            
+            emit!(StreamRewardsClaimed {
+                user: ctx.accounts.user.key(),
+                farm: farm_key,
+                index: index as u8,
+                reward_token_mint: stream.reward_token_mint,
+                rewards_paid: rewards,
+                reward_per_token_stored: stream.reward_per_token_stored,
+                timestamp: current_time,
+            });
+        }
+        farm.accrued_reward_fees += fee;
+        
+        Ok(())
+    }
 
-#[derive(Accounts)]
-pub struct UnstakeTokens<'info> {
-    #[account(
-        mut,
-        seeds = [b"farm", farm.authority.as_ref()],
-        bump
-    )]
-    pub farm: Account<'info, Farm>,
-    
-    #[account(
-        mut,
-        seeds = [b"user_stake", farm.key().as_ref(), user.key().as_ref()],
-        bump
-    )]
-    pub user_stake: Account<'info, UserStake>,
-    
-    #[account(mut)]
-    pub user: Signer<'info>,
-    
-    #[account(mut)]
-    pub user_token_account: Account<'info, TokenAccount>,
-    
-    #[account(mut)]
-    pub farm_token_account: Account<'info, TokenAccount>,
-    
-    pub token_program: Program<'info, Token>,
-}
+    pub fn pause_farm(ctx: Context<SetFarmStatus>) -> Result<()> {
+        set_farm_status(&mut ctx.accounts.farm, false)
+    }
 
-#[derive(Accounts)]
-pub struct ClaimRewards<'info> {
-    #[account(
-        mut,
-        seeds = [b"farm", farm.authority.as_ref()],
-        bump
-    )]
-    pub farm: Account<'info, Farm>,
-    
-    #[account(
-        mut,
-        seeds = [b"user_stake", farm.key().as_ref(), user.key().as_ref()],
-        bump
-    )]
-    pub user_stake: Account<'info, UserStake>,
-    
-    #[account(mut)]
-    pub user: Signer<'info>,
-    
-    #[account(mut)]
-    pub user_reward_account: Account<'info, TokenAccount>,
-    
-    #[account(mut)]
-    pub reward_token_account: Account<'info, TokenAccount>,
-    
-    pub token_program: Program<'info, Token>,
-}
+    pub fn unpause_farm(ctx: Context<SetFarmStatus>) -> Result<()> {
+        set_farm_status(&mut ctx.accounts.farm, true)
+    }
 
-#[account]
-pub struct Farm {
-    pub authority: Pubkey,
-    pub reward_token_mint: Pubkey,
-    pub staking_token_mint: Pubkey,
-    pub reward_rate: u64,
-    pub farm_duration: i64,
-    pub total_staked: u64,
-    pub last_update_time: i64,
-    pub reward_per_token_stored: u64,
-    pub is_active: bool,
-}
+    /// Returns the caller's whole stake, including tokens still unbonding,
+    /// free of the withdraw fee so the exit costs nothing beyond the rewards
+    /// left behind. Pending rewards on every stream are forfeited.
+    pub fn emergency_withdraw(ctx: Context<UnstakeTokens>) -> Result<()> {
+        let farm = &mut ctx.accounts.farm;
+        let user_stake = &mut ctx.accounts.user_stake;
+        let staked = user_stake.amount;
+        // Only stake still backed by the caller's receipts is paid out
+        let redeemed = staked.min(ctx.accounts.user_receipt_account.amount);
+        let amount = redeemed + user_stake.unbonding_amount;
+        
+        require!(amount > 0, ErrorCode::InsufficientStake);
+        
+        // Locks and cooldowns only give way once the farm is paused
+        let current_time = Clock::get()?.unix_timestamp;
+        if farm.is_active {
+            require!(current_time >= user_stake.lock_end, ErrorCode::StakeLocked);
+            require!(farm.cooldown_seconds == 0, ErrorCode::CooldownRequired);
+        }
+        
+        // Settle the farm so the stake leaving takes no one else's share
+        update_reward(farm, user_stake, current_time)?;
+        
+        burn_receipts(
+            farm,
+            ctx.bumps.farm,
+            &ctx.accounts.receipt_mint,
+            &ctx.accounts.user_receipt_account,
+            &ctx.accounts.user,
+            &ctx.accounts.token_program,
+            redeemed,
+        )?;
+        
+        // Transfer tokens from farm to user
+        let index = farm.index.to_le_bytes();
+        let seeds = &[
+            b"farm".as_ref(),
+            farm.creator.as_ref(),
+            index.as_ref(),
+            &[ctx.bumps.farm],
+        ];
+        let signer = &[&seeds[..]];
+        
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.farm_token_account.to_account_info(),
+            to: ctx.accounts.user_token_account.to_account_info(),
+            authority: farm.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::transfer(cpi_ctx, amount)?;
+        
+        // Drop the position and everything it had earned, freeing the
+        // forfeited rewards for later reward periods
+        let rewards_forfeited = user_stake.rewards_earned;
+        farm.reward_owed = farm.reward_owed.saturating_sub(rewards_forfeited);
+        for (stream, user_stream) in farm.extra_rewards.iter_mut().zip(&user_stake.extra_rewards) {
+            stream.reward_owed = stream.reward_owed.saturating_sub(user_stream.rewards_earned);
+        }
+        user_stake.amount = 0;
+        user_stake.rewards_earned = 0;
+        user_stake.reward_dust = 0;
+        user_stake.reward_per_token_paid = farm.reward_per_token_stored;
+        user_stake.extra_rewards = [UserRewardStream::default(); MAX_EXTRA_REWARDS];
+        user_stake.lock_weeks = 0;
+        user_stake.lock_end = 0;
+        user_stake.unbonding_amount = 0;
+        farm.total_staked -= staked;
+        sync_effective_stake(farm, user_stake)?;
+        
+        emit!(EmergencyWithdrawn {
+            user: ctx.accounts.user.key(),
+            farm: farm.key(),
+            amount,
+            rewards_forfeited,
+            total_staked: farm.total_staked,
+            timestamp: current_time,
+        });
+        
+        Ok(())
+    }
 
-impl Farm {
-    pub const LEN: usize = 32 + 32 + 32 + 8 + 8 + 8 + 8 + 8 + 1;
-}
+    /// Stakes `amount` (possibly zero) and locks the whole position for at
+    /// least `lock_weeks`, ending at the following week start. A lock can be
+    /// extended but never shortened, and any deposit into a locked position
+    /// restarts it.
+    pub fn stake_locked(ctx: Context<StakeTokens>, amount: u64, lock_weeks: u8) -> Result<()> {
+        let farm = &mut ctx.accounts.farm;
+        let user_stake = &mut ctx.accounts.user_stake;
+        
+        require!(farm.is_active, ErrorCode::FarmNotActive);
+        require!(
+            (1..=math::MAX_LOCK_WEEKS).contains(&lock_weeks),
+            ErrorCode::InvalidLockDuration
+        );
+        
+        // Receipts of a locked position cannot move until the lock ends
+        let current_time = Clock::get()?.unix_timestamp;
+        let fee = deposit(
+            farm,
+            user_stake,
+            ctx.accounts.user.key(),
+            &ctx.accounts.user,
+            &ctx.accounts.user_token_account,
+            &ctx.accounts.farm_token_account,
+            &ctx.accounts.receipt_mint,
+            &ctx.accounts.user_receipt_account,
+            &ctx.accounts.token_program,
+            ctx.bumps.farm,
+            amount,
+            true,
+            current_time,
+        )?;
+        
+        // Extend the lock
+        user_stake.lock_end = user_stake.lock_end.max(lock_end(current_time, lock_weeks));
+        user_stake.lock_weeks = user_stake.lock_weeks.max(lock_weeks);
+        sync_effective_stake(farm, user_stake)?;
+        
+        emit!(TokensLocked {
+            user: ctx.accounts.user.key(),
+            farm: farm.key(),
+            amount,
+            fee,
+            user_staked: user_stake.amount,
+            lock_weeks: user_stake.lock_weeks,
+            lock_end: user_stake.lock_end,
+            effective_amount: user_stake.effective_amount,
+            total_effective_staked: farm.total_effective_staked,
+            timestamp: current_time,
+        });
+        
+        Ok(())
+    }
 
-#[account]
-pub struct UserStake {
-    pub user: Pubkey,
-    pub farm: Pubkey,
-    pub amount: u64,
-    pub rewards_earned: u64,
-    pub reward_per_token_paid: u64,
-}
+    /// Drops the boost of a lock that has run out. Anyone can call this, since
+    /// an expired boost dilutes every other staker.
+    pub fn expire_lock(ctx: Context<ExpireLock>) -> Result<()> {
+        let farm = &mut ctx.accounts.farm;
+        let user_stake = &mut ctx.accounts.user_stake;
+        
+        let current_time = Clock::get()?.unix_timestamp;
+        require!(
+            user_stake.lock_weeks > 0 && current_time >= user_stake.lock_end,
+            ErrorCode::LockNotExpired
+        );
+        
+        // Settling releases the lock
+        update_reward(farm, user_stake, current_time)?;
+        
+        emit!(LockExpired {
+            user: user_stake.user,
+            farm: farm.key(),
+            effective_amount: user_stake.effective_amount,
+            total_effective_staked: farm.total_effective_staked,
+            timestamp: current_time,
+        });
+        
+        Ok(())
+    }
 
-impl UserStake {
-    pub const LEN: usize = 32 + 32 + 8 + 8 + 8;
+    pub fn set_cooldown(ctx: Context<SetFarmConfig>, cooldown_seconds: i64) -> Result<()> {
+        require!(
+            (0..=MAX_COOLDOWN_SECONDS).contains(&cooldown_seconds),
+            ErrorCode::InvalidCooldown
+        );
+        
+        let farm = &mut ctx.accounts.farm;
+        farm.cooldown_seconds = cooldown_seconds;
+        
+        emit!(CooldownUpdated {
+            farm: farm.key(),
+            cooldown_seconds,
+        });
+        
+        Ok(())
+    }
+
+    /// Limits new deposits for guarded launches. Zero leaves a cap off;
+    /// lowering a cap below current stake only blocks further deposits.
+    pub fn set_deposit_caps(
+        ctx: Context<SetFarmConfig>,
+        max_total_staked: u64,
+        max_stake_per_user: u64,
+    ) -> Result<()> {
+        let farm = &mut ctx.accounts.farm;
+        farm.max_total_staked = max_total_staked;
+        farm.max_stake_per_user = max_stake_per_user;
+        
+        emit!(DepositCapsUpdated {
+            farm: farm.key(),
+            max_total_staked,
+            max_stake_per_user,
+        });
+        
+        Ok(())
+    }
+
+    /// Stops `amount` from earning and starts the farm's cooldown. Requesting
+    /// again adds to the unbonding amount and restarts the timer.
+    pub fn request_unstake(ctx: Context<UnstakeTokens>, amount: u64) -> Result<()> {
+        let farm = &mut ctx.accounts.farm;
+        let user_stake = &mut ctx.accounts.user_stake;
+        
+        // Update rewards before changing stake
+        let current_time = Clock::get()?.unix_timestamp;
+        checkpoint_receipts(
+            farm,
+            user_stake,
+            ctx.accounts.user_receipt_account.amount,
+            ctx.accounts.receipt_mint.supply,
+            current_time,
+        )?;
+        
+        require!(user_stake.amount >= amount, ErrorCode::InsufficientStake);
+        require!(current_time >= user_stake.lock_end, ErrorCode::StakeLocked);
+        
+        burn_receipts(
+            farm,
+            ctx.bumps.farm,
+            &ctx.accounts.receipt_mint,
+            &ctx.accounts.user_receipt_account,
+            &ctx.accounts.user,
+            &ctx.accounts.token_program,
+            amount,
+        )?;
+        
+        // The tokens stay in the vault until withdraw_unstaked
+        user_stake.amount -= amount;
+        farm.total_staked -= amount;
+        sync_effective_stake(farm, user_stake)?;
+        user_stake.unbonding_amount += amount;
+        user_stake.unbonding_end = current_time
+            .checked_add(farm.cooldown_seconds)
+            .ok_or(ErrorCode::MathOverflow)?;
+        
+        emit!(UnstakeRequested {
+            user: ctx.accounts.user.key(),
+            farm: farm.key(),
+            amount,
+            unbonding_amount: user_stake.unbonding_amount,
+            unbonding_end: user_stake.unbonding_end,
+            total_staked: farm.total_staked,
+            timestamp: current_time,
+        });
+        
+        Ok(())
+    }
+
+    pub fn withdraw_unstaked(ctx: Context<UnstakeTokens>) -> Result<()> {
+        let farm = &mut ctx.accounts.farm;
+        let user_stake = &mut ctx.accounts.user_stake;
+        let amount = user_stake.unbonding_amount;
+        
+        require!(amount > 0, ErrorCode::NothingUnbonding);
+        
+        let current_time = Clock::get()?.unix_timestamp;
+        require!(current_time >= user_stake.unbonding_end, ErrorCode::CooldownNotElapsed);
+        let fee = math::fee(amount, farm.withdraw_fee_bps);
+        
+        // Transfer tokens from farm to user
+        let index = farm.index.to_le_bytes();
+        let seeds = &[
+            b"farm".as_ref(),
+            farm.creator.as_ref(),
+            index.as_ref(),
+            &[ctx.bumps.farm],
+        ];
+        let signer = &[&seeds[..]];
+        
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.farm_token_account.to_account_info(),
+            to: ctx.accounts.user_token_account.to_account_info(),
+            authority: farm.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::transfer(cpi_ctx, amount - fee)?;
+        
+        user_stake.unbonding_amount = 0;
+        farm.accrued_stake_fees += fee;
+        
+        emit!(UnstakedWithdrawn {
+            user: ctx.accounts.user.key(),
+            farm: farm.key(),
+            amount,
+            fee,
+            timestamp: current_time,
+        });
+        
+        Ok(())
+    }
+
+    pub fn set_fee_config(
+        ctx: Context<SetFarmConfig>,
+        deposit_fee_bps: u16,
+        withdraw_fee_bps: u16,
+        performance_fee_bps: u16,
+        fee_recipient: Pubkey,
+    ) -> Result<()> {
+        // Hard caps keep the authority from setting confiscatory rates
+        require!(
+            deposit_fee_bps <= MAX_DEPOSIT_FEE_BPS
+                && withdraw_fee_bps <= MAX_WITHDRAW_FEE_BPS
+                && performance_fee_bps <= MAX_PERFORMANCE_FEE_BPS,
+            ErrorCode::FeeTooHigh
+        );
+        
+        let farm = &mut ctx.accounts.farm;
+        farm.deposit_fee_bps = deposit_fee_bps;
+        farm.withdraw_fee_bps = withdraw_fee_bps;
+        farm.performance_fee_bps = performance_fee_bps;
+        farm.fee_recipient = fee_recipient;
+        
+        emit!(FeeConfigUpdated {
+            farm: farm.key(),
+            deposit_fee_bps,
+            withdraw_fee_bps,
+            performance_fee_bps,
+            fee_recipient,
+        });
+        
+        Ok(())
+    }
+
+    /// Changes the emission rate for the rest of the current period.
+    pub fn set_reward_rate(ctx: Context<SetRewardRate>, reward_rate: u64) -> Result<()> {
+        require!(!ctx.accounts.farm.has_emission_schedule(), ErrorCode::EmissionScheduleActive);
+        
+        let current_time = Clock::get()?.unix_timestamp;
+        update_farm(&mut ctx.accounts.farm, current_time)?;
+        
+        let farm = &mut ctx.accounts.farm;
+        let remaining = (farm.period_finish - current_time).max(0) as u128;
+        
+        // The vault must hold everything the new rate promises
+        require!(
+            reward_rate as u128 * remaining
+                <= farm.uncommitted_reward_balance(&ctx.accounts.reward_vault) as u128,
+            ErrorCode::InsufficientRewardBalance
+        );
+        
+        farm.reward_rate = reward_rate;
+        
+        emit!(RewardRateUpdated {
+            farm: farm.key(),
+            reward_rate,
+            period_finish: farm.period_finish,
+        });
+        
+        Ok(())
+    }
+
+    /// Sets the length of the next reward period started by `notify_reward_amount`.
+    pub fn set_duration(ctx: Context<SetFarmConfig>, farm_duration: i64) -> Result<()> {
+        require!(farm_duration > 0, ErrorCode::InvalidDuration);
+        
+        let farm = &mut ctx.accounts.farm;
+        let current_time = Clock::get()?.unix_timestamp;
+        require!(current_time >= farm.period_finish, ErrorCode::RewardPeriodActive);
+        
+        farm.farm_duration = farm_duration;
+        
+        emit!(DurationUpdated {
+            farm: farm.key(),
+            farm_duration,
+        });
+        
+        Ok(())
+    }
+
+    /// Replaces the flat reward rate with emission segments, sorted and not
+    /// overlapping. The reward vault must already hold everything the schedule
+    /// still has to emit. An empty schedule returns the farm to a flat rate.
+    pub fn set_emission_schedule(
+        ctx: Context<SetRewardRate>,
+        segments: Vec<EmissionSegment>,
+    ) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+        update_farm(&mut ctx.accounts.farm, current_time)?;
+        
+        require!(
+            segments.len() <= MAX_SCHEDULE_SEGMENTS
+                && segments.iter().all(EmissionSegment::is_valid)
+                && segments.windows(2).all(|pair| pair[0].end_time <= pair[1].start_time),
+            ErrorCode::InvalidEmissionSchedule
+        );
+        
+        let farm = &mut ctx.accounts.farm;
+        farm.emission_schedule = [EmissionSegment::default(); MAX_SCHEDULE_SEGMENTS];
+        farm.emission_schedule[..segments.len()].copy_from_slice(&segments);
+        // The schedule takes over from the flat rate, from now on
+        farm.reward_rate = 0;
+        farm.period_finish = farm.period_finish.min(current_time);
+        farm.last_update_time = current_time;
+        
+        let end_time = segments.last().map_or(current_time, |segment| segment.end_time);
+        let remaining = farm
+            .scheduled_emission(end_time.max(current_time))
+            .zip(farm.scheduled_emission(current_time))
+            .map(|(total, emitted)| total - emitted)
+            .ok_or(ErrorCode::MathOverflow)?;
+        require!(
+            remaining <= farm.uncommitted_reward_balance(&ctx.accounts.reward_vault) as u128,
+            ErrorCode::InsufficientRewardBalance
+        );
+        
+        emit!(EmissionScheduleUpdated {
+            farm: farm.key(),
+            segments: segments.len() as u8,
+            remaining_emission: remaining as u64,
+            end_time,
+        });
+        
+        Ok(())
+    }
+
+    /// Proposes a new authority, which takes over once it calls
+    /// `accept_authority`. The default key cancels a pending transfer.
+    pub fn set_authority(ctx: Context<SetFarmConfig>, new_authority: Pubkey) -> Result<()> {
+        let farm = &mut ctx.accounts.farm;
+        farm.pending_authority = new_authority;
+        
+        emit!(AuthorityTransferStarted {
+            farm: farm.key(),
+            authority: farm.authority,
+            pending_authority: new_authority,
+        });
+        
+        Ok(())
+    }
+
+    pub fn accept_authority(ctx: Context<AcceptAuthority>) -> Result<()> {
+        let farm = &mut ctx.accounts.farm;
+        let previous_authority = farm.authority;
+        farm.authority = farm.pending_authority;
+        farm.pending_authority = Pubkey::default();
+        
+        emit!(AuthorityTransferred {
+            farm: farm.key(),
+            previous_authority,
+            authority: farm.authority,
+        });
+        
+        Ok(())
+    }
+
+    /// Sends accrued fees to the fee recipient's token accounts. Anyone can
+    /// call this.
+    pub fn collect_fees(ctx: Context<CollectFees>) -> Result<()> {
+        let farm = &mut ctx.accounts.farm;
+        let stake_fees = farm.accrued_stake_fees;
+        let reward_fees = farm.accrued_reward_fees;
+        
+        let index = farm.index.to_le_bytes();
+        let seeds = &[
+            b"farm".as_ref(),
+            farm.creator.as_ref(),
+            index.as_ref(),
+            &[ctx.bumps.farm],
+        ];
+        let signer = &[&seeds[..]];
+        
+        if stake_fees > 0 {
+            let cpi_accounts = Transfer {
+                from: ctx.accounts.staking_vault.to_account_info(),
+                to: ctx.accounts.stake_fee_account.to_account_info(),
+                authority: farm.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+            token::transfer(cpi_ctx, stake_fees)?;
+        }
+        if reward_fees > 0 {
+            let cpi_accounts = Transfer {
+                from: ctx.accounts.reward_vault.to_account_info(),
+                to: ctx.accounts.reward_fee_account.to_account_info(),
+                authority: farm.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+            token::transfer(cpi_ctx, reward_fees)?;
+        }
+        
+        farm.accrued_stake_fees = 0;
+        farm.accrued_reward_fees = 0;
+        
+        emit!(FeesCollected {
+            farm: farm.key(),
+            fee_recipient: farm.fee_recipient,
+            stake_fees,
+            reward_fees,
+        });
+        
+        Ok(())
+    }
+
+    /// Restakes the caller's accrued rewards. Only for farms whose reward
+    /// token is also the staking token.
+    pub fn compound(ctx: Context<Compound>) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+        let (amount, fee, tip) = compound_position(
+            &mut ctx.accounts.farm,
+            &mut ctx.accounts.user_stake,
+            &ctx.accounts.reward_vault,
+            &ctx.accounts.staking_vault,
+            &ctx.accounts.receipt_mint,
+            &ctx.accounts.user_receipt_account,
+            None,
+            &ctx.accounts.token_program,
+            ctx.bumps.farm,
+            current_time,
+        )?;
+        
+        emit!(RewardsCompounded {
+            user: ctx.accounts.user.key(),
+            farm: ctx.accounts.farm.key(),
+            keeper: ctx.accounts.user.key(),
+            amount,
+            fee,
+            tip,
+            user_staked: ctx.accounts.user_stake.amount,
+            total_staked: ctx.accounts.farm.total_staked,
+            timestamp: current_time,
+        });
+        
+        Ok(())
+    }
+
+    pub fn set_auto_compound(ctx: Context<SetAutoCompound>, enabled: bool) -> Result<()> {
+        let user_stake = &mut ctx.accounts.user_stake;
+        user_stake.auto_compound = enabled;
+        
+        emit!(AutoCompoundUpdated {
+            user: ctx.accounts.user.key(),
+            farm: ctx.accounts.farm.key(),
+            enabled,
+        });
+        
+        Ok(())
+    }
+
+    /// Compounds on behalf of a user who opted in. Anyone can call this and
+    /// receives `COMPOUND_TIP_BPS` of the compounded rewards.
+    pub fn compound_for(ctx: Context<CompoundFor>) -> Result<()> {
+        require!(ctx.accounts.user_stake.auto_compound, ErrorCode::AutoCompoundDisabled);
+        
+        let current_time = Clock::get()?.unix_timestamp;
+        let keeper_reward_account = ctx.accounts.keeper_reward_account.to_account_info();
+        let (amount, fee, tip) = compound_position(
+            &mut ctx.accounts.farm,
+            &mut ctx.accounts.user_stake,
+            &ctx.accounts.reward_vault,
+            &ctx.accounts.staking_vault,
+            &ctx.accounts.receipt_mint,
+            &ctx.accounts.user_receipt_account,
+            Some(&keeper_reward_account),
+            &ctx.accounts.token_program,
+            ctx.bumps.farm,
+            current_time,
+        )?;
+        
+        emit!(RewardsCompounded {
+            user: ctx.accounts.user_stake.user,
+            farm: ctx.accounts.farm.key(),
+            keeper: ctx.accounts.keeper.key(),
+            amount,
+            fee,
+            tip,
+            user_staked: ctx.accounts.user_stake.amount,
+            total_staked: ctx.accounts.farm.total_staked,
+            timestamp: current_time,
+        });
+        
+        Ok(())
+    }
+
+    /// Turns vesting of claimed rewards on (`vesting_period > 0`) or off.
+    /// While it is on, primary rewards can only leave the farm through
+    /// `vest_rewards`.
+    pub fn set_vesting(
+        ctx: Context<SetVesting>,
+        vesting_period: i64,
+        early_exit_penalty_bps: u16,
+    ) -> Result<()> {
+        require!(vesting_period >= 0, ErrorCode::InvalidVestingPeriod);
+        require!(early_exit_penalty_bps <= MAX_EARLY_EXIT_PENALTY_BPS, ErrorCode::PenaltyTooHigh);
+        
+        let farm = &mut ctx.accounts.farm;
+        farm.vesting_vault = ctx.accounts.vesting_vault.key();
+        farm.vesting_period = vesting_period;
+        farm.early_exit_penalty_bps = early_exit_penalty_bps;
+        
+        emit!(VestingConfigUpdated {
+            farm: farm.key(),
+            vesting_period,
+            early_exit_penalty_bps,
+        });
+        
+        Ok(())
+    }
+
+    /// Claims the caller's rewards into their vesting schedule.
+    pub fn vest_rewards(ctx: Context<VestRewards>) -> Result<()> {
+        let farm = &mut ctx.accounts.farm;
+        let user_stake = &mut ctx.accounts.user_stake;
+        require!(farm.vesting_period > 0, ErrorCode::VestingDisabled);
+        
+        let current_time = Clock::get()?.unix_timestamp;
+        let receipt_info = ctx.accounts.receipt_account.to_account_info();
+        let balance = load_receipt_account(&receipt_info, &ctx.accounts.user.key(), &farm.receipt_mint)?
+            .map_or(0, |account| account.amount);
+        checkpoint_receipts(farm, user_stake, balance, ctx.accounts.receipt_mint.supply, current_time)?;
+        
+        let rewards = user_stake.rewards_earned;
+        require!(rewards > 0, ErrorCode::NoRewards);
+        require!(
+            farm.claimable_reward_balance(&ctx.accounts.reward_vault) >= rewards,
+            ErrorCode::InsufficientRewardFunds
+        );
+        let fee = math::fee(rewards, farm.performance_fee_bps);
+        
+        // Move the rewards into the vesting vault
+        let index = farm.index.to_le_bytes();
+        let seeds = &[
+            b"farm".as_ref(),
+            farm.creator.as_ref(),
+            index.as_ref(),
+            &[ctx.bumps.farm],
+        ];
+        let signer = &[&seeds[..]];
+        
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.reward_vault.to_account_info(),
+            to: ctx.accounts.vesting_vault.to_account_info(),
+            authority: farm.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::transfer(cpi_ctx, rewards - fee)?;
+        
+        user_stake.rewards_earned = 0;
+        farm.reward_owed = farm.reward_owed.saturating_sub(rewards);
+        farm.accrued_reward_fees += fee;
+        
+        let vesting = &mut ctx.accounts.vesting;
+        vesting.user = ctx.accounts.user.key();
+        vesting.farm = farm.key();
+        vesting.add(rewards - fee, current_time, farm.vesting_period);
+        
+        emit!(RewardsVested {
+            user: vesting.user,
+            farm: vesting.farm,
+            amount: rewards - fee,
+            fee,
+            vesting_amount: vesting.amount,
+            vesting_end: vesting.end_time,
+            timestamp: current_time,
+        });
+        
+        Ok(())
+    }
+
+    /// Pays out everything the caller's schedule has released so far.
+    pub fn withdraw_vested(ctx: Context<WithdrawVested>) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+        let vesting = &mut ctx.accounts.vesting;
+        let amount = vesting.releasable(current_time);
+        require!(amount > 0, ErrorCode::NothingVested);
+        
+        vesting.released =
+            math::vested_amount(vesting.amount, vesting.start_time, vesting.end_time, current_time);
+        vesting.unlocked = 0;
+        
+        let farm = &ctx.accounts.farm;
+        let index = farm.index.to_le_bytes();
+        let seeds = &[
+            b"farm".as_ref(),
+            farm.creator.as_ref(),
+            index.as_ref(),
+            &[ctx.bumps.farm],
+        ];
+        let signer = &[&seeds[..]];
+        
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.vesting_vault.to_account_info(),
+            to: ctx.accounts.user_reward_account.to_account_info(),
+            authority: farm.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::transfer(cpi_ctx, amount)?;
+        
+        emit!(VestedWithdrawn {
+            user: ctx.accounts.user.key(),
+            farm: farm.key(),
+            amount,
+            timestamp: current_time,
+        });
+        
+        Ok(())
+    }
+
+    /// Pays out the caller's whole schedule at once. The early-exit penalty
+    /// on the unvested part goes to the farm's current stakers.
+    pub fn exit_vesting(ctx: Context<ExitVesting>) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+        let farm = &mut ctx.accounts.farm;
+        let vesting = &mut ctx.accounts.vesting;
+        
+        let releasable = vesting.releasable(current_time);
+        let unvested = vesting.amount
+            - math::vested_amount(vesting.amount, vesting.start_time, vesting.end_time, current_time);
+        require!(releasable + unvested > 0, ErrorCode::NothingVested);
+        let penalty = math::fee(unvested, farm.early_exit_penalty_bps);
+        let amount = releasable + unvested - penalty;
+        
+        vesting.amount = 0;
+        vesting.released = 0;
+        vesting.unlocked = 0;
+        vesting.end_time = current_time;
+        
+        let index = farm.index.to_le_bytes();
+        let seeds = &[
+            b"farm".as_ref(),
+            farm.creator.as_ref(),
+            index.as_ref(),
+            &[ctx.bumps.farm],
+        ];
+        let signer = &[&seeds[..]];
+        
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.vesting_vault.to_account_info(),
+            to: ctx.accounts.user_reward_account.to_account_info(),
+            authority: farm.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::transfer(cpi_ctx, amount)?;
+        
+        if penalty > 0 {
+            // Hand the penalty back to the reward vault for the stakers
+            let cpi_accounts = Transfer {
+                from: ctx.accounts.vesting_vault.to_account_info(),
+                to: ctx.accounts.reward_vault.to_account_info(),
+                authority: farm.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+            token::transfer(cpi_ctx, penalty)?;
+            redistribute(farm, penalty, current_time)?;
+        }
+        
+        emit!(VestingExited {
+            user: ctx.accounts.user.key(),
+            farm: farm.key(),
+            amount,
+            penalty,
+            timestamp: current_time,
+        });
+        
+        Ok(())
+    }
+
+    /// Brings `owner`'s position in line with the receipts in their
+    /// associated token account. Anyone can call this, so both sides of a
+    /// receipt transfer can be checkpointed by whoever moved the tokens,
+    /// sender first. Also unfreezes receipts whose lock ended.
+    pub fn checkpoint(ctx: Context<Checkpoint>) -> Result<()> {
+        let farm = &mut ctx.accounts.farm;
+        let user_stake = &mut ctx.accounts.user_stake;
+        let owner = ctx.accounts.owner.key();
+        
+        let receipt_info = ctx.accounts.receipt_account.to_account_info();
+        let receipt_account = load_receipt_account(&receipt_info, &owner, &farm.receipt_mint)?;
+        let balance = receipt_account.as_ref().map_or(0, |account| account.amount);
+        
+        let current_time = Clock::get()?.unix_timestamp;
+        user_stake.user = owner;
+        user_stake.farm = farm.key();
+        checkpoint_receipts(farm, user_stake, balance, ctx.accounts.receipt_mint.supply, current_time)?;
+        
+        if let Some(receipt_account) = receipt_account {
+            if receipt_account.is_frozen() && current_time >= user_stake.lock_end {
+                let index = farm.index.to_le_bytes();
+                let seeds = &[
+                    b"farm".as_ref(),
+                    farm.creator.as_ref(),
+                    index.as_ref(),
+                    &[ctx.bumps.farm],
+                ];
+                let signer = &[&seeds[..]];
+                
+                let cpi_accounts = ThawAccount {
+                    account: receipt_info,
+                    mint: ctx.accounts.receipt_mint.to_account_info(),
+                    authority: farm.to_account_info(),
+                };
+                let cpi_program = ctx.accounts.token_program.to_account_info();
+                let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+                token::thaw_account(cpi_ctx)?;
+            }
+        }
+        
+        emit!(ReceiptsCheckpointed {
+            user: owner,
+            farm: farm.key(),
+            user_staked: user_stake.amount,
+            total_staked: farm.total_staked,
+            timestamp: current_time,
+        });
+        
+        Ok(())
+    }
+
+    /// Stakes the signer's tokens into `beneficiary`'s position. A locked
+    /// position cannot be topped up this way, since that restarts its lock.
+    pub fn stake_for(ctx: Context<StakeFor>, amount: u64) -> Result<()> {
+        let farm = &mut ctx.accounts.farm;
+        let user_stake = &mut ctx.accounts.user_stake;
+        
+        require!(farm.is_active, ErrorCode::FarmNotActive);
+        
+        let current_time = Clock::get()?.unix_timestamp;
+        require!(current_time >= user_stake.lock_end, ErrorCode::StakeLocked);
+        let fee = deposit(
+            farm,
+            user_stake,
+            ctx.accounts.beneficiary.key(),
+            &ctx.accounts.funder,
+            &ctx.accounts.funder_token_account,
+            &ctx.accounts.farm_token_account,
+            &ctx.accounts.receipt_mint,
+            &ctx.accounts.beneficiary_receipt_account,
+            &ctx.accounts.token_program,
+            ctx.bumps.farm,
+            amount,
+            false,
+            current_time,
+        )?;
+        
+        emit!(StakedFor {
+            funder: ctx.accounts.funder.key(),
+            user: user_stake.user,
+            farm: farm.key(),
+            amount,
+            fee,
+            user_staked: user_stake.amount,
+            total_staked: farm.total_staked,
+            timestamp: current_time,
+        });
+        
+        Ok(())
+    }
+
+    /// Hands the caller's whole stake to `new_owner`, lock included. Rewards
+    /// earned so far are settled and stay claimable by the caller; tokens
+    /// still unbonding stay with the caller too. A recipient already staking
+    /// must be locked on at least the same terms, or unlocked like the caller.
+    pub fn transfer_stake(ctx: Context<TransferStake>) -> Result<()> {
+        let farm = &mut ctx.accounts.farm;
+        let user_stake = &mut ctx.accounts.user_stake;
+        let new_user_stake = &mut ctx.accounts.new_user_stake;
+        
+        // Settle both positions before ownership changes
+        let current_time = Clock::get()?.unix_timestamp;
+        checkpoint_receipts(
+            farm,
+            user_stake,
+            ctx.accounts.user_receipt_account.amount,
+            ctx.accounts.receipt_mint.supply,
+            current_time,
+        )?;
+        new_user_stake.user = ctx.accounts.new_owner.key();
+        new_user_stake.farm = farm.key();
+        checkpoint_receipts(
+            farm,
+            new_user_stake,
+            ctx.accounts.new_owner_receipt_account.amount,
+            ctx.accounts.receipt_mint.supply,
+            current_time,
+        )?;
+        
+        let amount = user_stake.amount;
+        require!(amount > 0, ErrorCode::InsufficientStake);
+        
+        // Stake only joins a position held on at least the same lock terms,
+        // so neither side's stake is locked or boosted without its owner
+        let compatible = if user_stake.lock_weeks > 0 {
+            new_user_stake.lock_weeks >= user_stake.lock_weeks
+                && new_user_stake.lock_end >= user_stake.lock_end
+        } else {
+            new_user_stake.lock_weeks == 0
+        };
+        require!(new_user_stake.amount == 0 || compatible, ErrorCode::IncompatibleLock);
+        // The farm's total is unchanged, but the new owner's cap still applies
+        require!(
+            amount <= farm.user_stake_room(new_user_stake),
+            ErrorCode::UserStakeCapExceeded
+        );
+        
+        // The lock travels with the stake
+        if user_stake.lock_weeks > 0 {
+            new_user_stake.lock_end = new_user_stake.lock_end.max(user_stake.lock_end);
+            new_user_stake.lock_weeks = new_user_stake.lock_weeks.max(user_stake.lock_weeks);
+        }
+        
+        // Re-issue the receipts to the new owner
+        burn_receipts(
+            farm,
+            ctx.bumps.farm,
+            &ctx.accounts.receipt_mint,
+            &ctx.accounts.user_receipt_account,
+            &ctx.accounts.user,
+            &ctx.accounts.token_program,
+            amount,
+        )?;
+        mint_receipts(
+            farm,
+            ctx.bumps.farm,
+            &ctx.accounts.receipt_mint,
+            &ctx.accounts.new_owner_receipt_account,
+            &ctx.accounts.token_program,
+            amount,
+            current_time < new_user_stake.lock_end,
+        )?;
+        
+        user_stake.amount = 0;
+        user_stake.lock_weeks = 0;
+        user_stake.lock_end = 0;
+        sync_effective_stake(farm, user_stake)?;
+        new_user_stake.amount += amount;
+        sync_effective_stake(farm, new_user_stake)?;
+        
+        emit!(StakeTransferred {
+            from: user_stake.user,
+            to: new_user_stake.user,
+            farm: farm.key(),
+            amount,
+            user_staked: new_user_stake.amount,
+            lock_end: new_user_stake.lock_end,
+            timestamp: current_time,
+        });
+        
+        Ok(())
+    }
+
+    /// Closes the caller's position and refunds its rent. Pending rewards are
+    /// settled first, so the position must have nothing staked, unbonding or
+    /// left to claim.
+    pub fn close_user_stake(ctx: Context<CloseUserStake>) -> Result<()> {
+        let farm = &mut ctx.accounts.farm;
+        let user_stake = &mut ctx.accounts.user_stake;
+        
+        let receipt_info = ctx.accounts.receipt_account.to_account_info();
+        let balance = load_receipt_account(&receipt_info, &ctx.accounts.user.key(), &farm.receipt_mint)?
+            .map_or(0, |account| account.amount);
+        let current_time = Clock::get()?.unix_timestamp;
+        checkpoint_receipts(farm, user_stake, balance, ctx.accounts.receipt_mint.supply, current_time)?;
+        
+        close_position(user_stake, &ctx.accounts.user)
+    }
+
+    pub fn initialize_controller(ctx: Context<InitializeController>, emission_rate: u64) -> Result<()> {
+        let controller = &mut ctx.accounts.controller;
+        controller.authority = ctx.accounts.authority.key();
+        controller.reward_token_mint = ctx.accounts.reward_token_mint.key();
+        controller.reward_vault = ctx.accounts.reward_vault.key();
+        controller.emission_rate = emission_rate;
+        controller.total_alloc_point = 0;
+        controller.emission_per_point_stored = 0;
+        controller.emission_dust = 0;
+        controller.last_update_time = Clock::get()?.unix_timestamp;
+        
+        emit!(ControllerInitialized {
+            controller: controller.key(),
+            authority: controller.authority,
+            reward_token_mint: controller.reward_token_mint,
+            emission_rate,
+        });
+        
+        Ok(())
+    }
+
+    /// Changes the emission shared by every pool. Pools keep what they earned
+    /// at the old rate until they are next updated.
+    pub fn set_emission_rate(ctx: Context<SetEmissionRate>, emission_rate: u64) -> Result<()> {
+        let controller = &mut ctx.accounts.controller;
+        update_controller(controller, Clock::get()?.unix_timestamp)?;
+        controller.emission_rate = emission_rate;
+        
+        emit!(EmissionRateUpdated {
+            controller: controller.key(),
+            emission_rate,
+        });
+        
+        Ok(())
+    }
+
+    /// Gives `farm` a share of the controller's emission. Emissions the farm
+    /// earned under its old share are distributed first. Once the farm has a
+    /// gauge its share is the gauge's vote tally, set by `update_gauge`.
+    pub fn set_alloc_point(ctx: Context<SetAllocPoint>, alloc_point: u64) -> Result<()> {
+        require!(ctx.accounts.gauge.data_is_empty(), ErrorCode::GaugeControlsAllocation);
+        
+        let current_time = Clock::get()?.unix_timestamp;
+        update_controller(&mut ctx.accounts.controller, current_time)?;
+        distribute_emissions(
+            &ctx.accounts.controller,
+            ctx.bumps.controller,
+            &ctx.accounts.controller_vault.to_account_info(),
+            &mut ctx.accounts.farm,
+            &ctx.accounts.farm_reward_vault.to_account_info(),
+            &ctx.accounts.token_program.to_account_info(),
+            current_time,
+        )?;
+        
+        reallocate(&mut ctx.accounts.controller, &mut ctx.accounts.farm, alloc_point)
+    }
+
+    /// Pays each pool the emissions it has earned. `remaining_accounts` holds a
+    /// writable `[farm, farm reward vault]` pair per pool. Anyone can call this.
+    pub fn mass_update_pools<'c: 'info, 'info>(
+        ctx: Context<'_, '_, 'c, 'info, MassUpdatePools<'info>>,
+    ) -> Result<()> {
+        require!(
+            ctx.remaining_accounts.len().is_multiple_of(2),
+            ErrorCode::InvalidPoolAccounts
+        );
+        
+        let current_time = Clock::get()?.unix_timestamp;
+        update_controller(&mut ctx.accounts.controller, current_time)?;
+        
+        let controller_vault = ctx.accounts.controller_vault.to_account_info();
+        let token_program = ctx.accounts.token_program.to_account_info();
+        for accounts in ctx.remaining_accounts.chunks(2) {
+            let mut farm = Account::<Farm>::try_from(&accounts[0])?;
+            require_keys_eq!(accounts[1].key(), farm.reward_vault, ErrorCode::InvalidRewardVault);
+            
+            distribute_emissions(
+                &ctx.accounts.controller,
+                ctx.bumps.controller,
+                &controller_vault,
+                &mut farm,
+                &accounts[1],
+                &token_program,
+                current_time,
+            )?;
+            farm.exit(&crate::ID)?;
+        }
+        
+        Ok(())
+    }
+
+    /// Lets holders of the governance program's token direct the controller
+    /// emission by locking it. Epochs of `epoch_duration` seconds start now.
+    pub fn initialize_gauges(ctx: Context<InitializeGauges>, epoch_duration: i64) -> Result<()> {
+        require!(epoch_duration > 0, ErrorCode::InvalidEpochDuration);
+        
+        let gauge_controller = &mut ctx.accounts.gauge_controller;
+        gauge_controller.governance_token_mint = ctx.accounts.governance_token_mint.key();
+        gauge_controller.lock_vault = ctx.accounts.lock_vault.key();
+        gauge_controller.epoch_duration = epoch_duration;
+        gauge_controller.genesis_time = Clock::get()?.unix_timestamp;
+        
+        emit!(GaugesInitialized {
+            governance_token_mint: gauge_controller.governance_token_mint,
+            epoch_duration,
+            genesis_time: gauge_controller.genesis_time,
+        });
+        
+        Ok(())
+    }
+
+    /// Makes `farm` eligible for gauge votes.
+    pub fn create_gauge(ctx: Context<CreateGauge>) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+        let gauge = &mut ctx.accounts.gauge;
+        gauge.farm = ctx.accounts.farm.key();
+        gauge.vote_epoch = ctx.accounts.gauge_controller.epoch_at(current_time);
+        gauge.votes = 0;
+        gauge.previous_votes = 0;
+        // The first tally applied is the one for the current epoch
+        gauge.applied_epoch = gauge.vote_epoch;
+        
+        emit!(GaugeCreated {
+            gauge: gauge.key(),
+            farm: gauge.farm,
+        });
+        
+        Ok(())
+    }
+
+    /// Locks governance tokens as gauge voting weight until `unlock_time`.
+    /// Adding to a lock can only keep or extend its unlock time.
+    pub fn lock_governance_tokens(
+        ctx: Context<LockGovernanceTokens>,
+        amount: u64,
+        unlock_time: i64,
+    ) -> Result<()> {
+        let voter_lock = &mut ctx.accounts.voter_lock;
+        let current_time = Clock::get()?.unix_timestamp;
+        require!(
+            unlock_time > current_time && unlock_time >= voter_lock.unlock_time,
+            ErrorCode::InvalidUnlockTime
+        );
+        
+        if amount > 0 {
+            let cpi_accounts = Transfer {
+                from: ctx.accounts.owner_token_account.to_account_info(),
+                to: ctx.accounts.lock_vault.to_account_info(),
+                authority: ctx.accounts.owner.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+            token::transfer(cpi_ctx, amount)?;
+        }
+        
+        voter_lock.owner = ctx.accounts.owner.key();
+        voter_lock.amount = voter_lock.amount.checked_add(amount).ok_or(ErrorCode::MathOverflow)?;
+        voter_lock.unlock_time = unlock_time;
+        
+        emit!(GovernanceTokensLocked {
+            owner: voter_lock.owner,
+            amount,
+            locked: voter_lock.amount,
+            unlock_time,
+        });
+        
+        Ok(())
+    }
+
+    pub fn withdraw_governance_tokens(ctx: Context<WithdrawGovernanceTokens>) -> Result<()> {
+        let voter_lock = &mut ctx.accounts.voter_lock;
+        let current_time = Clock::get()?.unix_timestamp;
+        require!(current_time >= voter_lock.unlock_time, ErrorCode::GovernanceTokensStillLocked);
+        
+        let amount = voter_lock.amount;
+        let seeds = &[b"gauge_controller".as_ref(), &[ctx.bumps.gauge_controller]];
+        let signer = &[&seeds[..]];
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.lock_vault.to_account_info(),
+            to: ctx.accounts.owner_token_account.to_account_info(),
+            authority: ctx.accounts.gauge_controller.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token::transfer(cpi_ctx, amount)?;
+        voter_lock.amount = 0;
+        
+        emit!(GovernanceTokensWithdrawn {
+            owner: voter_lock.owner,
+            amount,
+        });
+        
+        Ok(())
+    }
+
+    /// Splits the voter's locked weight across gauges for the current epoch.
+    /// `remaining_accounts` holds a writable gauge per entry of `weights_bps`.
+    /// Each voter votes once per epoch, with a lock lasting until it ends.
+    pub fn vote_gauges<'c: 'info, 'info>(
+        ctx: Context<'_, '_, 'c, 'info, VoteGauges<'info>>,
+        weights_bps: Vec<u16>,
+    ) -> Result<()> {
+        let gauge_controller = &ctx.accounts.gauge_controller;
+        let voter_lock = &mut ctx.accounts.voter_lock;
+        let current_time = Clock::get()?.unix_timestamp;
+        let epoch = gauge_controller.epoch_at(current_time);
+        
+        require!(voter_lock.next_vote_epoch <= epoch, ErrorCode::AlreadyVotedThisEpoch);
+        require!(
+            voter_lock.unlock_time >= gauge_controller.epoch_end(epoch),
+            ErrorCode::LockEndsBeforeEpoch
+        );
+        require!(
+            ctx.remaining_accounts.len() == weights_bps.len()
+                && weights_bps.iter().map(|&bps| bps as u64).sum::<u64>() <= math::BPS_DENOMINATOR,
+            ErrorCode::InvalidGaugeWeights
+        );
+        
+        for (account, &weight_bps) in ctx.remaining_accounts.iter().zip(&weights_bps) {
+            let mut gauge = Account::<Gauge>::try_from(account)?;
+            let votes = (voter_lock.amount as u128 * weight_bps as u128
+                / math::BPS_DENOMINATOR as u128) as u64;
+            gauge.roll(epoch);
+            gauge.votes = gauge.votes.checked_add(votes).ok_or(ErrorCode::MathOverflow)?;
+            gauge.exit(&crate::ID)?;
+            
+            emit!(GaugeVoted {
+                voter: voter_lock.owner,
+                farm: gauge.farm,
+                epoch,
+                votes,
+                total_votes: gauge.votes,
+            });
+        }
+        voter_lock.next_vote_epoch = epoch + 1;
+        
+        Ok(())
+    }
+
+    /// Sets the gauge's farm share of the controller emission to the votes it
+    /// received in the epoch that just ended. Anyone can call this once per
+    /// epoch and gauge.
+    pub fn update_gauge(ctx: Context<UpdateGauge>) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+        let epoch = ctx.accounts.gauge_controller.epoch_at(current_time);
+        let gauge = &mut ctx.accounts.gauge;
+        require!(gauge.applied_epoch < epoch, ErrorCode::EpochNotEnded);
+        
+        let votes = gauge.votes_before(epoch);
+        gauge.applied_epoch = epoch;
+        
+        update_controller(&mut ctx.accounts.controller, current_time)?;
+        distribute_emissions(
+            &ctx.accounts.controller,
+            ctx.bumps.controller,
+            &ctx.accounts.controller_vault.to_account_info(),
+            &mut ctx.accounts.farm,
+            &ctx.accounts.farm_reward_vault.to_account_info(),
+            &ctx.accounts.token_program.to_account_info(),
+            current_time,
+        )?;
+        reallocate(&mut ctx.accounts.controller, &mut ctx.accounts.farm, votes)?;
+        
+        emit!(GaugeApplied {
+            farm: ctx.accounts.farm.key(),
+            epoch,
+            votes,
+        });
+        
+        Ok(())
+    }
+}
+
+/// Sets `farm`'s share of the controller emission. The farm must have been
+/// paid up to now first.
+fn reallocate(controller: &mut EmissionController, farm: &mut Account<Farm>, alloc_point: u64) -> Result<()> {
+    controller.total_alloc_point = controller
+        .total_alloc_point
+        .checked_sub(farm.alloc_point)
+        .and_then(|total| total.checked_add(alloc_point))
+        .ok_or(ErrorCode::MathOverflow)?;
+    farm.alloc_point = alloc_point;
+    
+    emit!(AllocPointUpdated {
+        farm: farm.key(),
+        alloc_point,
+        total_alloc_point: controller.total_alloc_point,
+    });
+    
+    Ok(())
+}
+
+/// Accrues the controller's emission per allocation point up to `current_time`.
+fn update_controller(controller: &mut EmissionController, current_time: i64) -> Result<()> {
+    let elapsed = current_time.saturating_sub(controller.last_update_time).max(0) as u64;
+    (controller.emission_per_point_stored, controller.emission_dust) = math::accrue_reward_per_token(
+        controller.emission_per_point_stored,
+        controller.emission_dust,
+        controller.emission_rate,
+        elapsed,
+        controller.total_alloc_point,
+    )
+    .ok_or(ErrorCode::MathOverflow)?;
+    controller.last_update_time = current_time;
+    
+    Ok(())
+}
+
+/// Moves the emissions `farm` has earned since its last update from the
+/// controller vault into the farm's reward vault and shares them among its
+/// stakers. Emissions earned while nothing is staked, or that the vault
+/// cannot cover yet, are owed to the farm until they can be paid.
+fn distribute_emissions<'info>(
+    controller: &Account<'info, EmissionController>,
+    controller_bump: u8,
+    controller_vault: &AccountInfo<'info>,
+    farm: &mut Account<'info, Farm>,
+    farm_reward_vault: &AccountInfo<'info>,
+    token_program: &AccountInfo<'info>,
+    current_time: i64,
+) -> Result<()> {
+    let (amount, dust) = math::earned(
+        farm.alloc_point,
+        controller.emission_per_point_stored,
+        farm.emission_per_point_paid,
+        farm.emission_dust,
+    )
+    .ok_or(ErrorCode::MathOverflow)?;
+    farm.emission_per_point_paid = controller.emission_per_point_stored;
+    farm.emission_dust = dust;
+    
+    // Carry emissions until someone is staked to share them and the vault
+    // holds enough to pay them
+    let owed = farm
+        .unpaid_emissions
+        .checked_add(amount)
+        .ok_or(ErrorCode::MathOverflow)?;
+    if farm.total_effective_staked == 0 {
+        farm.unpaid_emissions = owed;
+        return Ok(());
+    }
+    let amount = owed.min(token::accessor::amount(controller_vault)?);
+    farm.unpaid_emissions = owed - amount;
+    if amount == 0 {
+        return Ok(());
+    }
+    
+    let seeds = &[b"controller".as_ref(), &[controller_bump]];
+    let signer = &[&seeds[..]];
+    let cpi_accounts = Transfer {
+        from: controller_vault.clone(),
+        to: farm_reward_vault.clone(),
+        authority: controller.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new_with_signer(token_program.clone(), cpi_accounts, signer);
+    token::transfer(cpi_ctx, amount)?;
+    redistribute(farm, amount, current_time)?;
+    
+    emit!(EmissionsDistributed {
+        farm: farm.key(),
+        amount,
+        reward_per_token_stored: farm.reward_per_token_stored,
+        timestamp: current_time,
+    });
+    
+    Ok(())
+}
+
+/// Closes an empty position, sending its rent to `user`.
+fn close_position<'info>(
+    user_stake: &mut Account<'info, UserStake>,
+    user: &Signer<'info>,
+) -> Result<()> {
+    require!(user_stake.is_empty(), ErrorCode::PositionNotEmpty);
+    user_stake.close(user.to_account_info())?;
+    
+    emit!(UserStakeClosed {
+        user: user_stake.user,
+        farm: user_stake.farm,
+    });
+    
+    Ok(())
+}
+
+/// Moves a position's rewards from the reward vault into its stake, less the
+/// performance fee and an optional keeper tip. Returns the amount compounded,
+/// the fee and the tip.
+#[allow(clippy::too_many_arguments)]
+fn compound_position<'info>(
+    farm: &mut Account<'info, Farm>,
+    user_stake: &mut Account<'info, UserStake>,
+    reward_vault: &Account<'info, TokenAccount>,
+    staking_vault: &Account<'info, TokenAccount>,
+    receipt_mint: &Account<'info, token::Mint>,
+    receipt_account: &Account<'info, TokenAccount>,
+    keeper_reward_account: Option<&AccountInfo<'info>>,
+    token_program: &Program<'info, Token>,
+    farm_bump: u8,
+    current_time: i64,
+) -> Result<(u64, u64, u64)> {
+    require!(farm.is_active, ErrorCode::FarmNotActive);
+    require_keys_eq!(farm.reward_token_mint, farm.staking_token_mint, ErrorCode::NotSingleAsset);
+    require!(farm.vesting_period == 0, ErrorCode::RewardsMustVest);
+    
+    checkpoint_receipts(farm, user_stake, receipt_account.amount, receipt_mint.supply, current_time)?;
+    
+    let rewards = user_stake.rewards_earned;
+    require!(rewards > 0, ErrorCode::NoRewards);
+    require!(
+        farm.claimable_reward_balance(reward_vault) >= rewards,
+        ErrorCode::InsufficientRewardFunds
+    );
+    let fee = math::fee(rewards, farm.performance_fee_bps);
+    let tip = if keeper_reward_account.is_some() {
+        math::fee(rewards - fee, COMPOUND_TIP_BPS)
+    } else {
+        0
+    };
+    let amount = rewards - fee - tip;
+    check_stake_caps(farm, user_stake, amount)?;
+    
+    let index = farm.index.to_le_bytes();
+    let seeds = &[
+        b"farm".as_ref(),
+        farm.creator.as_ref(),
+        index.as_ref(),
+        &[farm_bump],
+    ];
+    let signer = &[&seeds[..]];
+    
+    // Move the rewards into the stake vault
+    let cpi_accounts = Transfer {
+        from: reward_vault.to_account_info(),
+        to: staking_vault.to_account_info(),
+        authority: farm.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new_with_signer(token_program.to_account_info(), cpi_accounts, signer);
+    token::transfer(cpi_ctx, amount)?;
+    
+    if let Some(keeper_reward_account) = keeper_reward_account {
+        let cpi_accounts = Transfer {
+            from: reward_vault.to_account_info(),
+            to: keeper_reward_account.clone(),
+            authority: farm.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(token_program.to_account_info(), cpi_accounts, signer);
+        token::transfer(cpi_ctx, tip)?;
+    }
+    
+    mint_receipts(
+        farm,
+        farm_bump,
+        receipt_mint,
+        receipt_account,
+        token_program,
+        amount,
+        current_time < user_stake.lock_end,
+    )?;
+    
+    // Update stake info
+    user_stake.rewards_earned = 0;
+    farm.reward_owed = farm.reward_owed.saturating_sub(rewards);
+    user_stake.amount += amount;
+    farm.total_staked += amount;
+    farm.accrued_reward_fees += fee;
+    sync_effective_stake(farm, user_stake)?;
+    
+    Ok((amount, fee, tip))
+}
+
+/// Moves `amount` from `funder` into the stake vault and credits it, less
+/// the deposit fee, to `owner`'s position. Returns the fee.
+#[allow(clippy::too_many_arguments)]
+fn deposit<'info>(
+    farm: &mut Account<'info, Farm>,
+    user_stake: &mut Account<'info, UserStake>,
+    owner: Pubkey,
+    funder: &Signer<'info>,
+    funder_token_account: &Account<'info, TokenAccount>,
+    staking_vault: &Account<'info, TokenAccount>,
+    receipt_mint: &Account<'info, token::Mint>,
+    receipt_account: &Account<'info, TokenAccount>,
+    token_program: &Program<'info, Token>,
+    farm_bump: u8,
+    amount: u64,
+    lock: bool,
+    current_time: i64,
+) -> Result<u64> {
+    // Update rewards before changing stake
+    user_stake.user = owner;
+    user_stake.farm = farm.key();
+    checkpoint_receipts(farm, user_stake, receipt_account.amount, receipt_mint.supply, current_time)?;
+    
+    // Caps apply to the stake left after the deposit fee
+    let fee = math::fee(amount, farm.deposit_fee_bps);
+    check_stake_caps(farm, user_stake, amount - fee)?;
+    
+    // Topping up a lock restarts it, so new tokens stay locked as long as
+    // the boost they earn
+    if amount > 0 && user_stake.lock_weeks > 0 {
+        user_stake.lock_end = lock_end(current_time, user_stake.lock_weeks);
+    }
+    
+    // Transfer tokens from funder to farm
+    let cpi_accounts = Transfer {
+        from: funder_token_account.to_account_info(),
+        to: staking_vault.to_account_info(),
+        authority: funder.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new(token_program.to_account_info(), cpi_accounts);
+    token::transfer(cpi_ctx, amount)?;
+    
+    // Deposit fees stay in the vault until collect_fees
+    farm.accrued_stake_fees += fee;
+    
+    mint_receipts(
+        farm,
+        farm_bump,
+        receipt_mint,
+        receipt_account,
+        token_program,
+        amount - fee,
+        lock || current_time < user_stake.lock_end,
+    )?;
+    
+    // Update stake info
+    user_stake.amount += amount - fee;
+    farm.total_staked += amount - fee;
+    sync_effective_stake(farm, user_stake)?;
+    
+    Ok(fee)
+}
+
+/// Settles `user_stake` on the receipts it is backed by. A position whose
+/// receipts left the wallet is settled at its old balance, and what the moved
+/// receipts earned since the last checkpoint goes back to every staker still
+/// counted. Receipts that arrived are only credited once no other position
+/// still counts them, which keeps a receipt from earning twice. A recipient
+/// therefore earns nothing on them until both sides are checkpointed, and
+/// receipts beyond the deposit caps stay uncredited until the caps leave room.
+fn checkpoint_receipts(
+    farm: &mut Farm,
+    user_stake: &mut UserStake,
+    receipt_balance: u64,
+    receipt_supply: u64,
+    current_time: i64,
+) -> Result<()> {
+    update_farm(farm, current_time)?;
+    
+    // Drop stake the owner no longer holds, forfeiting its share of the
+    // rewards settled on the old balance
+    let held = user_stake.amount;
+    if receipt_balance < held {
+        let earned = user_stake.all_rewards_earned();
+        update_reward(farm, user_stake, current_time)?;
+        farm.total_staked -= held - receipt_balance;
+        user_stake.amount = receipt_balance;
+        sync_effective_stake(farm, user_stake)?;
+        forfeit_earnings(farm, user_stake, earned, held - receipt_balance, held, current_time)?;
+    }
+    update_reward(farm, user_stake, current_time)?;
+    
+    // Every receipt backs one unit of `total_staked`, so any excess is still
+    // counted by a sender that has not been checkpointed. Receipts beyond the
+    // deposit caps are not credited either, rather than failing the caller.
+    let uncounted = receipt_supply.saturating_sub(farm.total_staked);
+    let credited = (receipt_balance - user_stake.amount)
+        .min(uncounted)
+        .min(farm.user_stake_room(user_stake))
+        .min(farm.total_stake_room());
+    user_stake.amount += credited;
+    farm.total_staked += credited;
+    sync_effective_stake(farm, user_stake)
+}
+
+/// End of a `lock_weeks` lock taken out at `current_time`, rounded up to the
+/// next week start so `update_farm` snapshots the reward per token there.
+fn lock_end(current_time: i64, lock_weeks: u8) -> i64 {
+    let end = current_time + lock_weeks as i64 * SECONDS_PER_WEEK;
+    end + (-end).rem_euclid(SECONDS_PER_WEEK)
+}
+
+/// Fails unless `amount` of new stake fits under both deposit caps.
+fn check_stake_caps(farm: &Farm, user_stake: &UserStake, amount: u64) -> Result<()> {
+    require!(
+        amount <= farm.user_stake_room(user_stake),
+        ErrorCode::UserStakeCapExceeded
+    );
+    require!(amount <= farm.total_stake_room(), ErrorCode::TotalStakeCapExceeded);
+    
+    Ok(())
+}
+
+/// Reads an owner's receipt account. A closed or reassigned account holds no
+/// receipts for them.
+fn load_receipt_account(
+    receipt_info: &AccountInfo,
+    owner: &Pubkey,
+    receipt_mint: &Pubkey,
+) -> Result<Option<TokenAccount>> {
+    if *receipt_info.owner != token::ID {
+        return Ok(None);
+    }
+    let account = TokenAccount::try_deserialize(&mut &receipt_info.try_borrow_data()?[..]).ok();
+    Ok(account.filter(|account| account.owner == *owner && account.mint == *receipt_mint))
+}
+
+/// Mints `amount` receipts into `receipt_account`, leaving it frozen when
+/// the position is `locked`.
+fn mint_receipts<'info>(
+    farm: &Account<'info, Farm>,
+    farm_bump: u8,
+    receipt_mint: &Account<'info, token::Mint>,
+    receipt_account: &Account<'info, TokenAccount>,
+    token_program: &Program<'info, Token>,
+    amount: u64,
+    locked: bool,
+) -> Result<()> {
+    let index = farm.index.to_le_bytes();
+    let seeds = &[
+        b"farm".as_ref(),
+        farm.creator.as_ref(),
+        index.as_ref(),
+        &[farm_bump],
+    ];
+    let signer = &[&seeds[..]];
+    
+    // Frozen accounts cannot receive tokens
+    if receipt_account.is_frozen() {
+        let cpi_accounts = ThawAccount {
+            account: receipt_account.to_account_info(),
+            mint: receipt_mint.to_account_info(),
+            authority: farm.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(token_program.to_account_info(), cpi_accounts, signer);
+        token::thaw_account(cpi_ctx)?;
+    }
+    
+    let cpi_accounts = MintTo {
+        mint: receipt_mint.to_account_info(),
+        to: receipt_account.to_account_info(),
+        authority: farm.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new_with_signer(token_program.to_account_info(), cpi_accounts, signer);
+    token::mint_to(cpi_ctx, amount)?;
+    
+    if locked {
+        let cpi_accounts = FreezeAccount {
+            account: receipt_account.to_account_info(),
+            mint: receipt_mint.to_account_info(),
+            authority: farm.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(token_program.to_account_info(), cpi_accounts, signer);
+        token::freeze_account(cpi_ctx)?;
+    }
+    
+    Ok(())
+}
+
+/// Burns `amount` of `owner`'s receipts. Callers check the position is no
+/// longer locked, so a frozen account is thawed first.
+fn burn_receipts<'info>(
+    farm: &Account<'info, Farm>,
+    farm_bump: u8,
+    receipt_mint: &Account<'info, token::Mint>,
+    receipt_account: &Account<'info, TokenAccount>,
+    owner: &Signer<'info>,
+    token_program: &Program<'info, Token>,
+    amount: u64,
+) -> Result<()> {
+    if receipt_account.is_frozen() {
+        let index = farm.index.to_le_bytes();
+        let seeds = &[
+            b"farm".as_ref(),
+            farm.creator.as_ref(),
+            index.as_ref(),
+            &[farm_bump],
+        ];
+        let signer = &[&seeds[..]];
+        
+        let cpi_accounts = ThawAccount {
+            account: receipt_account.to_account_info(),
+            mint: receipt_mint.to_account_info(),
+            authority: farm.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(token_program.to_account_info(), cpi_accounts, signer);
+        token::thaw_account(cpi_ctx)?;
+    }
+    
+    let cpi_accounts = Burn {
+        mint: receipt_mint.to_account_info(),
+        from: receipt_account.to_account_info(),
+        authority: owner.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new(token_program.to_account_info(), cpi_accounts);
+    token::burn(cpi_ctx, amount)
+}
+
+fn sync_effective_stake(farm: &mut Farm, user_stake: &mut UserStake) -> Result<()> {
+    let effective_amount = math::effective_amount(user_stake.amount, user_stake.lock_weeks)
+        .ok_or(ErrorCode::MathOverflow)?;
+    farm.total_effective_staked = farm
+        .total_effective_staked
+        .checked_sub(user_stake.effective_amount)
+        .and_then(|total| total.checked_add(effective_amount))
+        .ok_or(ErrorCode::MathOverflow)?;
+    user_stake.effective_amount = effective_amount;
+    
+    Ok(())
+}
+
+/// Shares `amount` reward tokens already in the reward vault among the
+/// current stakers. With nobody staked they stay in the vault unallocated.
+fn redistribute(farm: &mut Farm, amount: u64, current_time: i64) -> Result<()> {
+    update_farm(farm, current_time)?;
+    (farm.reward_per_token_stored, farm.reward_dust) = math::accrue_reward_per_token(
+        farm.reward_per_token_stored,
+        farm.reward_dust,
+        amount,
+        1,
+        farm.total_effective_staked,
+    )
+    .ok_or(ErrorCode::MathOverflow)?;
+    if farm.total_effective_staked > 0 {
+        farm.reward_owed = farm.reward_owed.checked_add(amount).ok_or(ErrorCode::MathOverflow)?;
+    }
+    
+    Ok(())
+}
+
+/// Shares `amount` tokens already in `stream`'s vault among `total_staked`.
+/// With nobody staked they stay in the vault unallocated.
+fn redistribute_stream(stream: &mut RewardStream, amount: u64, total_staked: u64) -> Result<()> {
+    (stream.reward_per_token_stored, stream.reward_dust) = math::accrue_reward_per_token(
+        stream.reward_per_token_stored,
+        stream.reward_dust,
+        amount,
+        1,
+        total_staked,
+    )
+    .ok_or(ErrorCode::MathOverflow)?;
+    if total_staked > 0 {
+        stream.reward_owed = stream.reward_owed.checked_add(amount).ok_or(ErrorCode::MathOverflow)?;
+    }
+    
+    Ok(())
+}
+
+fn set_farm_status(farm: &mut Account<Farm>, is_active: bool) -> Result<()> {
+    farm.is_active = is_active;
+    
+    emit!(FarmStatusChanged {
+        farm: farm.key(),
+        is_active,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+    
+    Ok(())
+}
+
+fn update_farm(farm: &mut Farm, current_time: i64) -> Result<()> {
+    // Stop at each week start crossed, where locks end, to record where the
+    // reward per token stood. Only the last `WEEK_SNAPSHOTS` are kept.
+    let current_week = current_time.div_euclid(SECONDS_PER_WEEK);
+    let first_week = (farm.snapshot_week + 1).max(current_week - WEEK_SNAPSHOTS as i64 + 1);
+    for week in first_week..=current_week {
+        accrue_farm(farm, week * SECONDS_PER_WEEK)?;
+        farm.week_snapshots[week.rem_euclid(WEEK_SNAPSHOTS as i64) as usize] = WeekSnapshot {
+            week,
+            reward_per_token: farm.reward_per_token_stored,
+            stream_reward_per_token: farm.stream_reward_per_token(),
+        };
+    }
+    farm.snapshot_week = farm.snapshot_week.max(current_week);
+    
+    accrue_farm(farm, current_time)
+}
+
+fn accrue_farm(farm: &mut Farm, current_time: i64) -> Result<()> {
+    // Rewards are shared by lock-boosted stake
+    let total_staked = farm.total_effective_staked;
+    if farm.has_emission_schedule() {
+        accrue_scheduled(farm, total_staked, current_time)?;
+    } else {
+        let distributed = accrue(
+            &mut farm.reward_per_token_stored,
+            &mut farm.reward_dust,
+            &mut farm.last_update_time,
+            farm.reward_rate,
+            farm.period_finish,
+            total_staked,
+            current_time,
+        )?;
+        farm.reward_owed = farm.reward_owed.checked_add(distributed).ok_or(ErrorCode::MathOverflow)?;
+    }
+    for stream in farm.extra_rewards.iter_mut().filter(|stream| stream.is_active()) {
+        let distributed = accrue(
+            &mut stream.reward_per_token_stored,
+            &mut stream.reward_dust,
+            &mut stream.last_update_time,
+            stream.reward_rate,
+            stream.period_finish,
+            total_staked,
+            current_time,
+        )?;
+        stream.reward_owed = stream.reward_owed.checked_add(distributed).ok_or(ErrorCode::MathOverflow)?;
+    }
+    
+    Ok(())
+}
+
+/// Distributes what the emission schedule released since the last update.
+/// Taking differences of the cumulative emission keeps accrual exact across
+/// segment boundaries and however the updates are spaced.
+fn accrue_scheduled(farm: &mut Farm, total_staked: u64, current_time: i64) -> Result<()> {
+    let current_time = current_time.max(farm.last_update_time);
+    let emitted = farm
+        .scheduled_emission(current_time)
+        .zip(farm.scheduled_emission(farm.last_update_time))
+        .and_then(|(to, from)| u64::try_from(to - from).ok())
+        .ok_or(ErrorCode::MathOverflow)?;
+    (farm.reward_per_token_stored, farm.reward_dust) = math::accrue_reward_per_token(
+        farm.reward_per_token_stored,
+        farm.reward_dust,
+        emitted,
+        1,
+        total_staked,
+    )
+    .ok_or(ErrorCode::MathOverflow)?;
+    if total_staked > 0 {
+        farm.reward_owed = farm.reward_owed.checked_add(emitted).ok_or(ErrorCode::MathOverflow)?;
+    }
+    farm.last_update_time = current_time;
+    
+    Ok(())
+}
+
+/// Accrues a reward rate up to `current_time` and returns the rewards that
+/// became owed to stakers, none while nobody is staked.
+fn accrue(
+    reward_per_token_stored: &mut u128,
+    reward_dust: &mut u128,
+    last_update_time: &mut i64,
+    reward_rate: u64,
+    period_finish: i64,
+    total_staked: u64,
+    current_time: i64,
+) -> Result<u64> {
+    // Nothing accrues past the end of the reward period
+    let reward_time = current_time.min(period_finish);
+    let elapsed = reward_time.saturating_sub(*last_update_time).max(0) as u64;
+    (*reward_per_token_stored, *reward_dust) = math::accrue_reward_per_token(
+        *reward_per_token_stored,
+        *reward_dust,
+        reward_rate,
+        elapsed,
+        total_staked,
+    )
+    .ok_or(ErrorCode::MathOverflow)?;
+    *last_update_time = (*last_update_time).max(reward_time);
+    
+    if total_staked == 0 {
+        return Ok(0);
+    }
+    reward_rate.checked_mul(elapsed).ok_or(error!(ErrorCode::MathOverflow))
+}
+
+fn update_reward(farm: &mut Farm, user_stake: &mut UserStake, current_time: i64) -> Result<()> {
+    update_farm(farm, current_time)?;
+    
+    if user_stake.lock_weeks == 0 || current_time < user_stake.lock_end {
+        return settle_position(
+            farm,
+            user_stake,
+            farm.reward_per_token_stored,
+            farm.stream_reward_per_token(),
+        );
+    }
+    
+    // An expired lock boosts only up to its end. Once the snapshot taken
+    // there is overwritten, the whole unsettled stretch earns base weight.
+    if let Some(snapshot) = farm.week_snapshot(user_stake.lock_end) {
+        settle_position(
+            farm,
+            user_stake,
+            snapshot.reward_per_token,
+            snapshot.stream_reward_per_token,
+        )?;
+    }
+    let earned = user_stake.all_rewards_earned();
+    settle_position(
+        farm,
+        user_stake,
+        farm.reward_per_token_stored,
+        farm.stream_reward_per_token(),
+    )?;
+    let boosted = user_stake.effective_amount;
+    user_stake.lock_weeks = 0;
+    sync_effective_stake(farm, user_stake)?;
+    
+    // The boost still counted since the lock ended goes back to the pool
+    let boost = boosted - user_stake.effective_amount;
+    forfeit_earnings(farm, user_stake, earned, boost, boosted, current_time)
+}
+
+/// Settles `user_stake` at its current weight up to the given reward per token
+/// of the primary reward and each stream.
+fn settle_position(
+    farm: &Farm,
+    user_stake: &mut UserStake,
+    reward_per_token: u128,
+    stream_reward_per_token: [u128; MAX_EXTRA_REWARDS],
+) -> Result<()> {
+    let amount = user_stake.effective_amount;
+    settle(
+        amount,
+        reward_per_token,
+        &mut user_stake.reward_per_token_paid,
+        &mut user_stake.reward_dust,
+        &mut user_stake.rewards_earned,
+    )?;
+    for ((stream, user_stream), reward_per_token) in farm
+        .extra_rewards
+        .iter()
+        .zip(user_stake.extra_rewards.iter_mut())
+        .zip(stream_reward_per_token)
+    {
+        if stream.is_active() {
+            settle(
+                amount,
+                reward_per_token,
+                &mut user_stream.reward_per_token_paid,
+                &mut user_stream.reward_dust,
+                &mut user_stream.rewards_earned,
+            )?;
+        }
+    }
+    
+    Ok(())
+}
+
+/// Takes `part / whole` of what `user_stake` earned on each reward since
+/// `earned` was read and shares it among every staker still counted.
+fn forfeit_earnings(
+    farm: &mut Farm,
+    user_stake: &mut UserStake,
+    earned: (u64, [u64; MAX_EXTRA_REWARDS]),
+    part: u64,
+    whole: u64,
+    current_time: i64,
+) -> Result<()> {
+    if part == 0 {
+        return Ok(());
+    }
+    let share = |earned_now: u64, earned_before: u64| {
+        ((earned_now - earned_before) as u128 * part as u128 / whole as u128) as u64
+    };
+    
+    let forfeited = share(user_stake.rewards_earned, earned.0);
+    user_stake.rewards_earned -= forfeited;
+    farm.reward_owed = farm.reward_owed.saturating_sub(forfeited);
+    redistribute(farm, forfeited, current_time)?;
+    
+    let total_staked = farm.total_effective_staked;
+    for ((stream, user_stream), earned) in farm
+        .extra_rewards
+        .iter_mut()
+        .zip(user_stake.extra_rewards.iter_mut())
+        .zip(earned.1)
+    {
+        let forfeited = share(user_stream.rewards_earned, earned);
+        user_stream.rewards_earned -= forfeited;
+        stream.reward_owed = stream.reward_owed.saturating_sub(forfeited);
+        redistribute_stream(stream, forfeited, total_staked)?;
+    }
+    
+    Ok(())
+}
+
+fn settle(
+    amount: u64,
+    reward_per_token_stored: u128,
+    reward_per_token_paid: &mut u128,
+    reward_dust: &mut u128,
+    rewards_earned: &mut u64,
+) -> Result<()> {
+    let (rewards, dust) = math::earned(amount, reward_per_token_stored, *reward_per_token_paid, *reward_dust)
+        .ok_or(ErrorCode::MathOverflow)?;
+    *rewards_earned = rewards_earned.checked_add(rewards).ok_or(ErrorCode::MathOverflow)?;
+    *reward_dust = dust;
+    *reward_per_token_paid = reward_per_token_stored;
+    
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct InitializeRegistry<'info> {
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + FarmRegistry::space(0),
+        seeds = [b"registry"],
+        bump
+    )]
+    pub registry: Account<'info, FarmRegistry>,
+    
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeFarm<'info> {
+    #[account(
+        mut,
+        seeds = [b"registry"],
+        bump,
+        realloc = 8 + FarmRegistry::space(registry.farms.len() + 1),
+        realloc::payer = authority,
+        realloc::zero = false
+    )]
+    pub registry: Account<'info, FarmRegistry>,
+    
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + Farm::LEN,
+        seeds = [b"farm", authority.key().as_ref(), &(registry.farms.len() as u64).to_le_bytes()],
+        bump
+    )]
+    pub farm: Account<'info, Farm>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub reward_token_mint: Account<'info, token::Mint>,
+    pub staking_token_mint: Account<'info, token::Mint>,
+    
+    #[account(
+        init,
+        payer = authority,
+        seeds = [b"stake_vault", farm.key().as_ref()],
+        bump,
+        token::mint = staking_token_mint,
+        token::authority = farm
+    )]
+    pub staking_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        init,
+        payer = authority,
+        seeds = [b"reward_vault", farm.key().as_ref()],
+        bump,
+        token::mint = reward_token_mint,
+        token::authority = farm
+    )]
+    pub reward_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        init,
+        payer = authority,
+        seeds = [b"receipt_mint", farm.key().as_ref()],
+        bump,
+        mint::decimals = staking_token_mint.decimals,
+        mint::authority = farm,
+        mint::freeze_authority = farm
+    )]
+    pub receipt_mint: Account<'info, token::Mint>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct StakeTokens<'info> {
+    #[account(
+        mut,
+        seeds = [b"farm", farm.creator.as_ref(), &farm.index.to_le_bytes()],
+        bump
+    )]
+    pub farm: Account<'info, Farm>,
+    
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + UserStake::LEN,
+        seeds = [b"user_stake", farm.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub user_stake: Account<'info, UserStake>,
+    
+    #[account(mut)]
+    pub user: Signer<'info>,
+    
+    #[account(
+        mut,
+        constraint = user_token_account.mint == farm.staking_token_mint @ ErrorCode::InvalidTokenMint
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        address = farm.staking_vault @ ErrorCode::InvalidStakeVault
+    )]
+    pub farm_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        address = farm.receipt_mint @ ErrorCode::InvalidReceiptMint
+    )]
+    pub receipt_mint: Account<'info, token::Mint>,
+    
+    #[account(
+        mut,
+        address = get_associated_token_address(&user.key(), &farm.receipt_mint) @ ErrorCode::InvalidReceiptAccount,
+        constraint = user_receipt_account.owner == user.key() @ ErrorCode::InvalidReceiptAccount
+    )]
+    pub user_receipt_account: Account<'info, TokenAccount>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UnstakeTokens<'info> {
+    #[account(
+        mut,
+        seeds = [b"farm", farm.creator.as_ref(), &farm.index.to_le_bytes()],
+        bump
+    )]
+    pub farm: Account<'info, Farm>,
+    
+    #[account(
+        mut,
+        seeds = [b"user_stake", farm.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub user_stake: Account<'info, UserStake>,
+    
+    #[account(mut)]
+    pub user: Signer<'info>,
+    
+    #[account(
+        mut,
+        constraint = user_token_account.mint == farm.staking_token_mint @ ErrorCode::InvalidTokenMint
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        address = farm.staking_vault @ ErrorCode::InvalidStakeVault
+    )]
+    pub farm_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        address = farm.receipt_mint @ ErrorCode::InvalidReceiptMint
+    )]
+    pub receipt_mint: Account<'info, token::Mint>,
+    
+    #[account(
+        mut,
+        address = get_associated_token_address(&user.key(), &farm.receipt_mint) @ ErrorCode::InvalidReceiptAccount,
+        constraint = user_receipt_account.owner == user.key() @ ErrorCode::InvalidReceiptAccount
+    )]
+    pub user_receipt_account: Account<'info, TokenAccount>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimRewards<'info> {
+    #[account(
+        mut,
+        seeds = [b"farm", farm.creator.as_ref(), &farm.index.to_le_bytes()],
+        bump
+    )]
+    pub farm: Account<'info, Farm>,
+    
+    #[account(
+        mut,
+        seeds = [b"user_stake", farm.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub user_stake: Account<'info, UserStake>,
+    
+    #[account(mut)]
+    pub user: Signer<'info>,
+    
+    #[account(
+        mut,
+        constraint = user_reward_account.mint == farm.reward_token_mint @ ErrorCode::InvalidTokenMint
+    )]
+    pub user_reward_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        address = farm.reward_vault @ ErrorCode::InvalidRewardVault
+    )]
+    pub reward_token_account: Account<'info, TokenAccount>,
+    
+    #[account(address = farm.receipt_mint @ ErrorCode::InvalidReceiptMint)]
+    pub receipt_mint: Account<'info, token::Mint>,
+    
+    /// CHECK: The user's associated receipt account, which may be closed.
+    #[account(
+        address = get_associated_token_address(&user.key(), &farm.receipt_mint) @ ErrorCode::InvalidReceiptAccount
+    )]
+    pub receipt_account: UncheckedAccount<'info>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct NotifyRewardAmount<'info> {
+    #[account(
+        mut,
+        seeds = [b"farm", farm.creator.as_ref(), &farm.index.to_le_bytes()],
+        bump,
+        has_one = authority
+    )]
+    pub farm: Account<'info, Farm>,
+    
+    pub authority: Signer<'info>,
+    
+    #[account(mut)]
+    pub funder_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        address = farm.reward_vault @ ErrorCode::InvalidRewardVault
+    )]
+    pub reward_token_account: Account<'info, TokenAccount>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct AddRewardStream<'info> {
+    #[account(
+        mut,
+        seeds = [b"farm", farm.creator.as_ref(), &farm.index.to_le_bytes()],
+        bump,
+        has_one = authority
+    )]
+    pub farm: Account<'info, Farm>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub reward_token_mint: Account<'info, token::Mint>,
+    
+    #[account(
+        init,
+        payer = authority,
+        seeds = [b"reward_vault", farm.key().as_ref(), reward_token_mint.key().as_ref()],
+        bump,
+        token::mint = reward_token_mint,
+        token::authority = farm
+    )]
+    pub reward_token_account: Account<'info, TokenAccount>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct FundRewardStream<'info> {
+    #[account(
+        mut,
+        seeds = [b"farm", farm.creator.as_ref(), &farm.index.to_le_bytes()],
+        bump,
+        has_one = authority
+    )]
+    pub farm: Account<'info, Farm>,
+    
+    pub authority: Signer<'info>,
+    
+    #[account(mut)]
+    pub funder_token_account: Account<'info, TokenAccount>,
+    
+    #[account(mut)]
+    pub reward_token_account: Account<'info, TokenAccount>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct SetFarmStatus<'info> {
+    #[account(
+        mut,
+        seeds = [b"farm", farm.creator.as_ref(), &farm.index.to_le_bytes()],
+        bump,
+        has_one = authority
+    )]
+    pub farm: Account<'info, Farm>,
+    
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetFarmConfig<'info> {
+    #[account(
+        mut,
+        seeds = [b"farm", farm.creator.as_ref(), &farm.index.to_le_bytes()],
+        bump,
+        has_one = authority
+    )]
+    pub farm: Account<'info, Farm>,
+    
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetRewardRate<'info> {
+    #[account(
+        mut,
+        seeds = [b"farm", farm.creator.as_ref(), &farm.index.to_le_bytes()],
+        bump,
+        has_one = authority
+    )]
+    pub farm: Account<'info, Farm>,
+    
+    pub authority: Signer<'info>,
+    
+    #[account(address = farm.reward_vault @ ErrorCode::InvalidRewardVault)]
+    pub reward_vault: Account<'info, TokenAccount>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptAuthority<'info> {
+    #[account(
+        mut,
+        seeds = [b"farm", farm.creator.as_ref(), &farm.index.to_le_bytes()],
+        bump,
+        constraint = farm.pending_authority == pending_authority.key() @ ErrorCode::NotPendingAuthority
+    )]
+    pub farm: Account<'info, Farm>,
+    
+    pub pending_authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CollectFees<'info> {
+    #[account(
+        mut,
+        seeds = [b"farm", farm.creator.as_ref(), &farm.index.to_le_bytes()],
+        bump
+    )]
+    pub farm: Account<'info, Farm>,
+    
+    #[account(
+        mut,
+        address = farm.staking_vault @ ErrorCode::InvalidStakeVault
+    )]
+    pub staking_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        address = farm.reward_vault @ ErrorCode::InvalidRewardVault
+    )]
+    pub reward_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = stake_fee_account.owner == farm.fee_recipient @ ErrorCode::InvalidFeeAccount,
+        constraint = stake_fee_account.mint == farm.staking_token_mint @ ErrorCode::InvalidFeeAccount
+    )]
+    pub stake_fee_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = reward_fee_account.owner == farm.fee_recipient @ ErrorCode::InvalidFeeAccount,
+        constraint = reward_fee_account.mint == farm.reward_token_mint @ ErrorCode::InvalidFeeAccount
+    )]
+    pub reward_fee_account: Account<'info, TokenAccount>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct Compound<'info> {
+    #[account(
+        mut,
+        seeds = [b"farm", farm.creator.as_ref(), &farm.index.to_le_bytes()],
+        bump
+    )]
+    pub farm: Account<'info, Farm>,
+    
+    #[account(
+        mut,
+        seeds = [b"user_stake", farm.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub user_stake: Account<'info, UserStake>,
+    
+    pub user: Signer<'info>,
+    
+    #[account(
+        mut,
+        address = farm.staking_vault @ ErrorCode::InvalidStakeVault
+    )]
+    pub staking_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        address = farm.reward_vault @ ErrorCode::InvalidRewardVault
+    )]
+    pub reward_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        address = farm.receipt_mint @ ErrorCode::InvalidReceiptMint
+    )]
+    pub receipt_mint: Account<'info, token::Mint>,
+    
+    #[account(
+        mut,
+        address = get_associated_token_address(&user.key(), &farm.receipt_mint) @ ErrorCode::InvalidReceiptAccount,
+        constraint = user_receipt_account.owner == user.key() @ ErrorCode::InvalidReceiptAccount
+    )]
+    pub user_receipt_account: Account<'info, TokenAccount>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct SetAutoCompound<'info> {
+    #[account(
+        seeds = [b"farm", farm.creator.as_ref(), &farm.index.to_le_bytes()],
+        bump
+    )]
+    pub farm: Account<'info, Farm>,
+    
+    #[account(
+        mut,
+        seeds = [b"user_stake", farm.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub user_stake: Account<'info, UserStake>,
+    
+    pub user: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CompoundFor<'info> {
+    #[account(
+        mut,
+        seeds = [b"farm", farm.creator.as_ref(), &farm.index.to_le_bytes()],
+        bump
+    )]
+    pub farm: Account<'info, Farm>,
+    
+    #[account(
+        mut,
+        seeds = [b"user_stake", farm.key().as_ref(), user_stake.user.as_ref()],
+        bump
+    )]
+    pub user_stake: Account<'info, UserStake>,
+    
+    pub keeper: Signer<'info>,
+    
+    #[account(
+        mut,
+        constraint = keeper_reward_account.mint == farm.reward_token_mint @ ErrorCode::InvalidTokenMint
+    )]
+    pub keeper_reward_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        address = farm.staking_vault @ ErrorCode::InvalidStakeVault
+    )]
+    pub staking_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        address = farm.reward_vault @ ErrorCode::InvalidRewardVault
+    )]
+    pub reward_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        address = farm.receipt_mint @ ErrorCode::InvalidReceiptMint
+    )]
+    pub receipt_mint: Account<'info, token::Mint>,
+    
+    #[account(
+        mut,
+        address = get_associated_token_address(&user_stake.user, &farm.receipt_mint) @ ErrorCode::InvalidReceiptAccount,
+        constraint = user_receipt_account.owner == user_stake.user @ ErrorCode::InvalidReceiptAccount
+    )]
+    pub user_receipt_account: Account<'info, TokenAccount>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CloseUserStake<'info> {
+    #[account(
+        mut,
+        seeds = [b"farm", farm.creator.as_ref(), &farm.index.to_le_bytes()],
+        bump
+    )]
+    pub farm: Account<'info, Farm>,
+    
+    #[account(
+        mut,
+        seeds = [b"user_stake", farm.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub user_stake: Account<'info, UserStake>,
+    
+    #[account(mut)]
+    pub user: Signer<'info>,
+    
+    #[account(address = farm.receipt_mint @ ErrorCode::InvalidReceiptMint)]
+    pub receipt_mint: Account<'info, token::Mint>,
+    
+    /// CHECK: The user's associated receipt account, which may be closed.
+    #[account(
+        address = get_associated_token_address(&user.key(), &farm.receipt_mint) @ ErrorCode::InvalidReceiptAccount
+    )]
+    pub receipt_account: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeController<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + EmissionController::LEN,
+        seeds = [b"controller"],
+        bump
+    )]
+    pub controller: Account<'info, EmissionController>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub reward_token_mint: Account<'info, token::Mint>,
+    
+    #[account(
+        init,
+        payer = authority,
+        seeds = [b"controller_vault"],
+        bump,
+        token::mint = reward_token_mint,
+        token::authority = controller
+    )]
+    pub reward_vault: Account<'info, TokenAccount>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetEmissionRate<'info> {
+    #[account(
+        mut,
+        seeds = [b"controller"],
+        bump,
+        has_one = authority
+    )]
+    pub controller: Account<'info, EmissionController>,
+    
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetAllocPoint<'info> {
+    #[account(
+        mut,
+        seeds = [b"controller"],
+        bump,
+        has_one = authority
+    )]
+    pub controller: Account<'info, EmissionController>,
+    
+    pub authority: Signer<'info>,
+    
+    #[account(
+        mut,
+        address = controller.reward_vault @ ErrorCode::InvalidRewardVault
+    )]
+    pub controller_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [b"farm", farm.creator.as_ref(), &farm.index.to_le_bytes()],
+        bump,
+        constraint = farm.reward_token_mint == controller.reward_token_mint @ ErrorCode::InvalidTokenMint
+    )]
+    pub farm: Account<'info, Farm>,
+    
+    #[account(
+        mut,
+        address = farm.reward_vault @ ErrorCode::InvalidRewardVault
+    )]
+    pub farm_reward_vault: Account<'info, TokenAccount>,
+    
+    /// CHECK: The farm's gauge address, which must not hold a gauge yet.
+    #[account(seeds = [b"gauge", farm.key().as_ref()], bump)]
+    pub gauge: UncheckedAccount<'info>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct MassUpdatePools<'info> {
+    #[account(
+        mut,
+        seeds = [b"controller"],
+        bump
+    )]
+    pub controller: Account<'info, EmissionController>,
+    
+    #[account(
+        mut,
+        address = controller.reward_vault @ ErrorCode::InvalidRewardVault
+    )]
+    pub controller_vault: Account<'info, TokenAccount>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeGauges<'info> {
+    #[account(
+        seeds = [b"controller"],
+        bump,
+        has_one = authority
+    )]
+    pub controller: Account<'info, EmissionController>,
+    
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + GaugeController::LEN,
+        seeds = [b"gauge_controller"],
+        bump
+    )]
+    pub gauge_controller: Account<'info, GaugeController>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    #[account(seeds = [b"governance"], bump, seeds::program = governance::ID)]
+    pub governance: Account<'info, governance::Governance>,
+    
+    #[account(address = governance.governance_token_mint @ ErrorCode::InvalidTokenMint)]
+    pub governance_token_mint: Account<'info, token::Mint>,
+    
+    #[account(
+        init,
+        payer = authority,
+        seeds = [b"gauge_lock_vault"],
+        bump,
+        token::mint = governance_token_mint,
+        token::authority = gauge_controller
+    )]
+    pub lock_vault: Account<'info, TokenAccount>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CreateGauge<'info> {
+    #[account(
+        seeds = [b"controller"],
+        bump,
+        has_one = authority
+    )]
+    pub controller: Account<'info, EmissionController>,
+    
+    #[account(
+        seeds = [b"gauge_controller"],
+        bump
+    )]
+    pub gauge_controller: Account<'info, GaugeController>,
+    
+    #[account(
+        seeds = [b"farm", farm.creator.as_ref(), &farm.index.to_le_bytes()],
+        bump,
+        constraint = farm.reward_token_mint == controller.reward_token_mint @ ErrorCode::InvalidTokenMint
+    )]
+    pub farm: Account<'info, Farm>,
+    
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + Gauge::LEN,
+        seeds = [b"gauge", farm.key().as_ref()],
+        bump
+    )]
+    pub gauge: Account<'info, Gauge>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct LockGovernanceTokens<'info> {
+    #[account(
+        seeds = [b"gauge_controller"],
+        bump
+    )]
+    pub gauge_controller: Account<'info, GaugeController>,
+    
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = 8 + VoterLock::LEN,
+        seeds = [b"voter_lock", owner.key().as_ref()],
+        bump
+    )]
+    pub voter_lock: Account<'info, VoterLock>,
+    
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    
+    #[account(mut)]
+    pub owner_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        address = gauge_controller.lock_vault @ ErrorCode::InvalidLockVault
+    )]
+    pub lock_vault: Account<'info, TokenAccount>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawGovernanceTokens<'info> {
+    #[account(
+        seeds = [b"gauge_controller"],
+        bump
+    )]
+    pub gauge_controller: Account<'info, GaugeController>,
+    
+    #[account(
+        mut,
+        seeds = [b"voter_lock", owner.key().as_ref()],
+        bump
+    )]
+    pub voter_lock: Account<'info, VoterLock>,
+    
+    pub owner: Signer<'info>,
+    
+    #[account(
+        mut,
+        constraint = owner_token_account.mint == gauge_controller.governance_token_mint @ ErrorCode::InvalidTokenMint
+    )]
+    pub owner_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        address = gauge_controller.lock_vault @ ErrorCode::InvalidLockVault
+    )]
+    pub lock_vault: Account<'info, TokenAccount>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct VoteGauges<'info> {
+    #[account(
+        seeds = [b"gauge_controller"],
+        bump
+    )]
+    pub gauge_controller: Account<'info, GaugeController>,
+    
+    #[account(
+        mut,
+        seeds = [b"voter_lock", owner.key().as_ref()],
+        bump
+    )]
+    pub voter_lock: Account<'info, VoterLock>,
+    
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateGauge<'info> {
+    #[account(
+        mut,
+        seeds = [b"controller"],
+        bump
+    )]
+    pub controller: Account<'info, EmissionController>,
+    
+    #[account(
+        mut,
+        address = controller.reward_vault @ ErrorCode::InvalidRewardVault
+    )]
+    pub controller_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        seeds = [b"gauge_controller"],
+        bump
+    )]
+    pub gauge_controller: Account<'info, GaugeController>,
+    
+    #[account(
+        mut,
+        seeds = [b"gauge", farm.key().as_ref()],
+        bump
+    )]
+    pub gauge: Account<'info, Gauge>,
+    
+    #[account(
+        mut,
+        seeds = [b"farm", farm.creator.as_ref(), &farm.index.to_le_bytes()],
+        bump
+    )]
+    pub farm: Account<'info, Farm>,
+    
+    #[account(
+        mut,
+        address = farm.reward_vault @ ErrorCode::InvalidRewardVault
+    )]
+    pub farm_reward_vault: Account<'info, TokenAccount>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct StakeFor<'info> {
+    #[account(
+        mut,
+        seeds = [b"farm", farm.creator.as_ref(), &farm.index.to_le_bytes()],
+        bump
+    )]
+    pub farm: Account<'info, Farm>,
+    
+    #[account(
+        init_if_needed,
+        payer = funder,
+        space = 8 + UserStake::LEN,
+        seeds = [b"user_stake", farm.key().as_ref(), beneficiary.key().as_ref()],
+        bump
+    )]
+    pub user_stake: Account<'info, UserStake>,
+    
+    /// CHECK: Any wallet; it owns the resulting position.
+    pub beneficiary: UncheckedAccount<'info>,
+    
+    #[account(mut)]
+    pub funder: Signer<'info>,
+    
+    #[account(
+        mut,
+        constraint = funder_token_account.mint == farm.staking_token_mint @ ErrorCode::InvalidTokenMint
+    )]
+    pub funder_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        address = farm.staking_vault @ ErrorCode::InvalidStakeVault
+    )]
+    pub farm_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        address = farm.receipt_mint @ ErrorCode::InvalidReceiptMint
+    )]
+    pub receipt_mint: Account<'info, token::Mint>,
+    
+    #[account(
+        mut,
+        address = get_associated_token_address(&beneficiary.key(), &farm.receipt_mint) @ ErrorCode::InvalidReceiptAccount,
+        constraint = beneficiary_receipt_account.owner == beneficiary.key() @ ErrorCode::InvalidReceiptAccount
+    )]
+    pub beneficiary_receipt_account: Account<'info, TokenAccount>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct TransferStake<'info> {
+    #[account(
+        mut,
+        seeds = [b"farm", farm.creator.as_ref(), &farm.index.to_le_bytes()],
+        bump
+    )]
+    pub farm: Account<'info, Farm>,
+    
+    #[account(
+        mut,
+        seeds = [b"user_stake", farm.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub user_stake: Account<'info, UserStake>,
+    
+    #[account(mut)]
+    pub user: Signer<'info>,
+    
+    /// CHECK: Any wallet other than the caller; it receives the position.
+    #[account(constraint = new_owner.key() != user.key() @ ErrorCode::SameOwner)]
+    pub new_owner: UncheckedAccount<'info>,
+    
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + UserStake::LEN,
+        seeds = [b"user_stake", farm.key().as_ref(), new_owner.key().as_ref()],
+        bump
+    )]
+    pub new_user_stake: Account<'info, UserStake>,
+    
+    #[account(
+        mut,
+        address = farm.receipt_mint @ ErrorCode::InvalidReceiptMint
+    )]
+    pub receipt_mint: Account<'info, token::Mint>,
+    
+    #[account(
+        mut,
+        address = get_associated_token_address(&user.key(), &farm.receipt_mint) @ ErrorCode::InvalidReceiptAccount,
+        constraint = user_receipt_account.owner == user.key() @ ErrorCode::InvalidReceiptAccount
+    )]
+    pub user_receipt_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        address = get_associated_token_address(&new_owner.key(), &farm.receipt_mint) @ ErrorCode::InvalidReceiptAccount,
+        constraint = new_owner_receipt_account.owner == new_owner.key() @ ErrorCode::InvalidReceiptAccount
+    )]
+    pub new_owner_receipt_account: Account<'info, TokenAccount>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Checkpoint<'info> {
+    #[account(
+        mut,
+        seeds = [b"farm", farm.creator.as_ref(), &farm.index.to_le_bytes()],
+        bump
+    )]
+    pub farm: Account<'info, Farm>,
+    
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + UserStake::LEN,
+        seeds = [b"user_stake", farm.key().as_ref(), owner.key().as_ref()],
+        bump
+    )]
+    pub user_stake: Account<'info, UserStake>,
+    
+    /// CHECK: Any wallet; only used to derive its position and receipt account.
+    pub owner: UncheckedAccount<'info>,
+    
+    #[account(address = farm.receipt_mint @ ErrorCode::InvalidReceiptMint)]
+    pub receipt_mint: Account<'info, token::Mint>,
+    
+    /// CHECK: The owner's associated receipt account, which may be closed.
+    #[account(
+        mut,
+        address = get_associated_token_address(&owner.key(), &farm.receipt_mint) @ ErrorCode::InvalidReceiptAccount
+    )]
+    pub receipt_account: UncheckedAccount<'info>,
+    
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetVesting<'info> {
+    #[account(
+        mut,
+        seeds = [b"farm", farm.creator.as_ref(), &farm.index.to_le_bytes()],
+        bump,
+        has_one = authority,
+        has_one = reward_token_mint @ ErrorCode::InvalidTokenMint
+    )]
+    pub farm: Account<'info, Farm>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub reward_token_mint: Account<'info, token::Mint>,
+    
+    #[account(
+        init_if_needed,
+        payer = authority,
+        token::mint = reward_token_mint,
+        token::authority = farm,
+        seeds = [b"vesting_vault", farm.key().as_ref()],
+        bump
+    )]
+    pub vesting_vault: Account<'info, TokenAccount>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct VestRewards<'info> {
+    #[account(
+        mut,
+        seeds = [b"farm", farm.creator.as_ref(), &farm.index.to_le_bytes()],
+        bump
+    )]
+    pub farm: Account<'info, Farm>,
+    
+    #[account(
+        mut,
+        seeds = [b"user_stake", farm.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub user_stake: Account<'info, UserStake>,
+    
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + RewardVesting::LEN,
+        seeds = [b"vesting", farm.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub vesting: Account<'info, RewardVesting>,
+    
+    #[account(mut)]
+    pub user: Signer<'info>,
+    
+    #[account(
+        mut,
+        address = farm.reward_vault @ ErrorCode::InvalidRewardVault
+    )]
+    pub reward_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        address = farm.vesting_vault @ ErrorCode::InvalidVestingVault
+    )]
+    pub vesting_vault: Account<'info, TokenAccount>,
+    
+    #[account(address = farm.receipt_mint @ ErrorCode::InvalidReceiptMint)]
+    pub receipt_mint: Account<'info, token::Mint>,
+    
+    /// CHECK: The user's associated receipt account, which may be closed.
+    #[account(
+        address = get_associated_token_address(&user.key(), &farm.receipt_mint) @ ErrorCode::InvalidReceiptAccount
+    )]
+    pub receipt_account: UncheckedAccount<'info>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawVested<'info> {
+    #[account(
+        seeds = [b"farm", farm.creator.as_ref(), &farm.index.to_le_bytes()],
+        bump
+    )]
+    pub farm: Account<'info, Farm>,
+    
+    #[account(
+        mut,
+        seeds = [b"vesting", farm.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub vesting: Account<'info, RewardVesting>,
+    
+    pub user: Signer<'info>,
+    
+    #[account(
+        mut,
+        constraint = user_reward_account.mint == farm.reward_token_mint @ ErrorCode::InvalidTokenMint
+    )]
+    pub user_reward_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        address = farm.vesting_vault @ ErrorCode::InvalidVestingVault
+    )]
+    pub vesting_vault: Account<'info, TokenAccount>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ExitVesting<'info> {
+    #[account(
+        mut,
+        seeds = [b"farm", farm.creator.as_ref(), &farm.index.to_le_bytes()],
+        bump
+    )]
+    pub farm: Account<'info, Farm>,
+    
+    #[account(
+        mut,
+        seeds = [b"vesting", farm.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub vesting: Account<'info, RewardVesting>,
+    
+    pub user: Signer<'info>,
+    
+    #[account(
+        mut,
+        constraint = user_reward_account.mint == farm.reward_token_mint @ ErrorCode::InvalidTokenMint
+    )]
+    pub user_reward_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        address = farm.vesting_vault @ ErrorCode::InvalidVestingVault
+    )]
+    pub vesting_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        address = farm.reward_vault @ ErrorCode::InvalidRewardVault
+    )]
+    pub reward_vault: Account<'info, TokenAccount>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ExpireLock<'info> {
+    #[account(
+        mut,
+        seeds = [b"farm", farm.creator.as_ref(), &farm.index.to_le_bytes()],
+        bump
+    )]
+    pub farm: Account<'info, Farm>,
+    
+    #[account(
+        mut,
+        seeds = [b"user_stake", farm.key().as_ref(), user_stake.user.as_ref()],
+        bump
+    )]
+    pub user_stake: Account<'info, UserStake>,
+}
+
+pub const SECONDS_PER_WEEK: i64 = 7 * 24 * 60 * 60;
+
+/// Upper bounds on the fees `set_fee_config` accepts, in basis points.
+pub const MAX_DEPOSIT_FEE_BPS: u16 = 200;
+pub const MAX_WITHDRAW_FEE_BPS: u16 = 200;
+pub const MAX_PERFORMANCE_FEE_BPS: u16 = 2_000;
+
+/// Longest unbonding period `set_cooldown` accepts, so principal cannot be
+/// held back indefinitely.
+pub const MAX_COOLDOWN_SECONDS: i64 = 4 * SECONDS_PER_WEEK;
+
+/// Share of compounded rewards paid to whoever runs `compound_for`.
+pub const COMPOUND_TIP_BPS: u16 = 50;
+
+pub const MAX_EARLY_EXIT_PENALTY_BPS: u16 = 5_000;
+
+/// Maximum number of reward streams a farm can run besides its primary reward.
+pub const MAX_EXTRA_REWARDS: usize = 3;
+
+/// Number of segments a farm's emission schedule can hold.
+pub const MAX_SCHEDULE_SEGMENTS: usize = 8;
+
+/// Weeks of reward-per-token history a farm keeps to settle expired locks.
+pub const WEEK_SNAPSHOTS: usize = 8;
+
+/// Lists every farm, in creation order.
+#[account]
+pub struct FarmRegistry {
+    pub farms: Vec<Pubkey>,
+}
+
+impl FarmRegistry {
+    pub fn space(farm_count: usize) -> usize {
+        4 + 32 * farm_count
+    }
+}
+
+#[account]
+pub struct Farm {
+    pub authority: Pubkey,
+    /// Proposed authority awaiting `accept_authority`; the default key when none.
+    pub pending_authority: Pubkey,
+    /// Authority the farm was created by, part of its seeds.
+    pub creator: Pubkey,
+    /// Position of the farm in the registry, part of its seeds.
+    pub index: u64,
+    pub reward_token_mint: Pubkey,
+    pub staking_token_mint: Pubkey,
+    /// Program-owned vault holding staked tokens.
+    pub staking_vault: Pubkey,
+    /// Program-owned vault paying `reward_token_mint` rewards.
+    pub reward_vault: Pubkey,
+    /// Receipt token minted 1:1 for staked tokens; the farm can freeze it.
+    pub receipt_mint: Pubkey,
+    pub reward_rate: u64,
+    pub farm_duration: i64,
+    /// Unbonding period enforced by `request_unstake`; zero allows direct unstaking.
+    pub cooldown_seconds: i64,
+    /// Cap on `total_staked` checked on deposits; zero means no cap.
+    pub max_total_staked: u64,
+    /// Cap on a single position checked on deposits; zero means no cap.
+    pub max_stake_per_user: u64,
+    pub total_staked: u64,
+    /// Sum of every position's lock-boosted `effective_amount`.
+    pub total_effective_staked: u64,
+    pub last_update_time: i64,
+    /// Rewards stop accruing at this timestamp.
+    pub period_finish: i64,
+    /// Cumulative rewards per staked token, scaled by `math::REWARD_PRECISION`.
+    pub reward_per_token_stored: u128,
+    /// Scaled rewards not yet reflected in `reward_per_token_stored`.
+    pub reward_dust: u128,
+    /// Staking is blocked while false.
+    pub is_active: bool,
+    /// Owner of the token accounts `collect_fees` pays out to.
+    pub fee_recipient: Pubkey,
+    pub deposit_fee_bps: u16,
+    pub withdraw_fee_bps: u16,
+    pub performance_fee_bps: u16,
+    /// Fees held in `staking_vault` awaiting `collect_fees`.
+    pub accrued_stake_fees: u64,
+    /// Fees held in `reward_vault` awaiting `collect_fees`.
+    pub accrued_reward_fees: u64,
+    /// Rewards distributed to stakers that have not been claimed, compounded
+    /// or vested yet.
+    pub reward_owed: u64,
+    /// Program-owned vault holding vesting rewards, set by `set_vesting`.
+    pub vesting_vault: Pubkey,
+    /// Length of the linear release of vested rewards; zero pays claims out directly.
+    pub vesting_period: i64,
+    /// Share of the unvested rewards forfeited by `exit_vesting`.
+    pub early_exit_penalty_bps: u16,
+    /// Additional reward tokens paid alongside `reward_token_mint`.
+    pub extra_rewards: [RewardStream; MAX_EXTRA_REWARDS],
+    /// Share of the emission controller's emission, out of its `total_alloc_point`.
+    pub alloc_point: u64,
+    /// Controller `emission_per_point_stored` when the farm was last paid.
+    pub emission_per_point_paid: u128,
+    /// Scaled emissions owed to the farm below one token.
+    pub emission_dust: u128,
+    /// Emissions earned but not yet paid, because nothing was staked to share
+    /// them or the controller vault ran short.
+    pub unpaid_emissions: u64,
+    /// Primary reward emission replacing `reward_rate` while any segment is set.
+    pub emission_schedule: [EmissionSegment; MAX_SCHEDULE_SEGMENTS],
+    /// Latest week whose start `week_snapshots` recorded.
+    pub snapshot_week: i64,
+    /// Reward per token at the start of recent weeks, indexed by week number.
+    pub week_snapshots: [WeekSnapshot; WEEK_SNAPSHOTS],
+}
+
+impl Farm {
+    pub const LEN: usize = 32 + 32 + 32 + 8 + 32 + 32 + 32 + 32 + 32 + 8 + 8 + 8 + 8 + 8
+        + 8 + 8 + 8 + 8 + 16 + 16 + 1
+        + 32 + 2 + 2 + 2 + 8 + 8 + 8 + 32 + 8 + 2
+        + RewardStream::LEN * MAX_EXTRA_REWARDS + 8 + 16 + 16 + 8
+        + EmissionSegment::LEN * MAX_SCHEDULE_SEGMENTS
+        + 8 + WeekSnapshot::LEN * WEEK_SNAPSHOTS;
+
+    pub fn has_emission_schedule(&self) -> bool {
+        self.emission_schedule.iter().any(EmissionSegment::is_active)
+    }
+
+    /// Tokens the emission schedule has released by `time`, or `None` on overflow.
+    pub fn scheduled_emission(&self, time: i64) -> Option<u128> {
+        self.emission_schedule
+            .iter()
+            .try_fold(0u128, |total, segment| total.checked_add(segment.emitted_by(time)?))
+    }
+
+    /// Reward per token of each extra stream, inactive slots included.
+    pub fn stream_reward_per_token(&self) -> [u128; MAX_EXTRA_REWARDS] {
+        self.extra_rewards.map(|stream| stream.reward_per_token_stored)
+    }
+
+    /// Snapshot taken at `time`, if that is a week start still kept.
+    pub fn week_snapshot(&self, time: i64) -> Option<&WeekSnapshot> {
+        let week = time.div_euclid(SECONDS_PER_WEEK);
+        let snapshot = &self.week_snapshots[week.rem_euclid(WEEK_SNAPSHOTS as i64) as usize];
+        (time.rem_euclid(SECONDS_PER_WEEK) == 0 && snapshot.week == week).then_some(snapshot)
+    }
+
+    /// Stake `user_stake` can still take on under `max_stake_per_user`.
+    pub fn user_stake_room(&self, user_stake: &UserStake) -> u64 {
+        match self.max_stake_per_user {
+            0 => u64::MAX,
+            cap => cap.saturating_sub(user_stake.amount),
+        }
+    }
+
+    /// Stake the farm can still take on under `max_total_staked`.
+    pub fn total_stake_room(&self) -> u64 {
+        match self.max_total_staked {
+            0 => u64::MAX,
+            cap => cap.saturating_sub(self.total_staked),
+        }
+    }
+
+    /// Reward vault balance not set aside for fees.
+    pub fn claimable_reward_balance(&self, reward_vault: &TokenAccount) -> u64 {
+        reward_vault.amount.saturating_sub(self.accrued_reward_fees)
+    }
+
+    /// Reward vault balance neither set aside for fees nor owed to stakers,
+    /// which is all a new reward period may promise.
+    pub fn uncommitted_reward_balance(&self, reward_vault: &TokenAccount) -> u64 {
+        self.claimable_reward_balance(reward_vault).saturating_sub(self.reward_owed)
+    }
+}
+
+#[account]
+pub struct UserStake {
+    pub user: Pubkey,
+    pub farm: Pubkey,
+    pub amount: u64,
+    /// `amount` weighted by the lock multiplier; rewards accrue on this.
+    pub effective_amount: u64,
+    /// Lock length the boost is based on, zero when unlocked.
+    pub lock_weeks: u8,
+    /// Unstaking is blocked until this timestamp.
+    pub lock_end: i64,
+    /// Tokens requested for withdrawal; they no longer earn rewards.
+    pub unbonding_amount: u64,
+    /// `unbonding_amount` can be withdrawn from this timestamp.
+    pub unbonding_end: i64,
+    /// Lets anyone compound this position through `compound_for`.
+    pub auto_compound: bool,
+    pub rewards_earned: u64,
+    pub reward_per_token_paid: u128,
+    /// Fraction of a reward token owed, scaled by `math::REWARD_PRECISION`.
+    pub reward_dust: u128,
+    /// Accrual state for each of the farm's `extra_rewards`, by index.
+    pub extra_rewards: [UserRewardStream; MAX_EXTRA_REWARDS],
+}
+
+impl UserStake {
+    pub const LEN: usize = 32 + 32 + 8 + 8 + 1 + 8 + 8 + 8 + 1 + 8 + 16 + 16 + UserRewardStream::LEN * MAX_EXTRA_REWARDS;
+
+    /// Unclaimed rewards of the primary reward and of each stream.
+    pub fn all_rewards_earned(&self) -> (u64, [u64; MAX_EXTRA_REWARDS]) {
+        (self.rewards_earned, self.extra_rewards.map(|stream| stream.rewards_earned))
+    }
+
+    /// True once nothing is staked, unbonding or waiting to be claimed.
+    pub fn is_empty(&self) -> bool {
+        self.amount == 0
+            && self.unbonding_amount == 0
+            && self.rewards_earned == 0
+            && self.extra_rewards.iter().all(|stream| stream.rewards_earned == 0)
+    }
+}
+
+/// Claimed rewards released linearly to `user`. Tokens added while an older
+/// batch is still vesting share one schedule ending at their weighted
+/// average end time.
+#[account]
+pub struct RewardVesting {
+    pub user: Pubkey,
+    pub farm: Pubkey,
+    /// Tokens released between `start_time` and `end_time`.
+    pub amount: u64,
+    /// Part of `amount` already withdrawn.
+    pub released: u64,
+    /// Tokens fully released by earlier schedules and not yet withdrawn.
+    pub unlocked: u64,
+    pub start_time: i64,
+    pub end_time: i64,
+}
+
+impl RewardVesting {
+    pub const LEN: usize = 32 + 32 + 8 + 8 + 8 + 8 + 8;
+
+    /// Tokens `withdraw_vested` would pay out at `current_time`.
+    pub fn releasable(&self, current_time: i64) -> u64 {
+        self.unlocked + math::vested_amount(self.amount, self.start_time, self.end_time, current_time) - self.released
+    }
+
+    fn add(&mut self, amount: u64, current_time: i64, vesting_period: i64) {
+        let vested = math::vested_amount(self.amount, self.start_time, self.end_time, current_time);
+        self.unlocked += vested - self.released;
+        
+        // Merge what is still vesting with the new tokens
+        let remaining = self.amount - vested;
+        let new_end = current_time + vesting_period;
+        let weighted_end = (remaining as i128 * self.end_time.max(current_time) as i128
+            + amount as i128 * new_end as i128)
+            / (remaining as i128 + amount as i128);
+        self.amount = remaining + amount;
+        self.released = 0;
+        self.start_time = current_time;
+        self.end_time = weighted_end as i64;
+    }
+}
+
+#[account]
+pub struct EmissionController {
+    pub authority: Pubkey,
+    pub reward_token_mint: Pubkey,
+    /// Program-owned vault the controller pays pools from.
+    pub reward_vault: Pubkey,
+    /// Tokens emitted per second across every pool.
+    pub emission_rate: u64,
+    pub total_alloc_point: u64,
+    /// Cumulative emission per allocation point, scaled by `math::REWARD_PRECISION`.
+    pub emission_per_point_stored: u128,
+    pub emission_dust: u128,
+    pub last_update_time: i64,
+}
+
+impl EmissionController {
+    pub const LEN: usize = 32 + 32 + 32 + 8 + 8 + 16 + 16 + 8;
+}
+
+/// Epoch clock and locked-token vault for gauge voting.
+#[account]
+pub struct GaugeController {
+    pub governance_token_mint: Pubkey,
+    /// Program-owned vault holding locked governance tokens.
+    pub lock_vault: Pubkey,
+    pub epoch_duration: i64,
+    /// Start of epoch zero.
+    pub genesis_time: i64,
+}
+
+impl GaugeController {
+    pub const LEN: usize = 32 + 32 + 8 + 8;
+
+    pub fn epoch_at(&self, current_time: i64) -> u64 {
+        ((current_time - self.genesis_time).max(0) / self.epoch_duration) as u64
+    }
+
+    pub fn epoch_end(&self, epoch: u64) -> i64 {
+        self.genesis_time + (epoch as i64 + 1) * self.epoch_duration
+    }
+}
+
+/// Vote tally for one farm.
+#[account]
+pub struct Gauge {
+    pub farm: Pubkey,
+    /// Epoch `votes` were cast in.
+    pub vote_epoch: u64,
+    pub votes: u64,
+    /// Votes cast in the epoch before `vote_epoch`.
+    pub previous_votes: u64,
+    /// Last epoch whose start applied a tally to the farm.
+    pub applied_epoch: u64,
+}
+
+impl Gauge {
+    pub const LEN: usize = 32 + 8 + 8 + 8 + 8;
+
+    /// Votes cast in the epoch before `epoch`.
+    pub fn votes_before(&self, epoch: u64) -> u64 {
+        if self.vote_epoch + 1 == epoch {
+            self.votes
+        } else if self.vote_epoch == epoch {
+            self.previous_votes
+        } else {
+            0
+        }
+    }
+
+    /// Starts the tally for `epoch`, keeping the one it replaces if that was
+    /// the previous epoch.
+    fn roll(&mut self, epoch: u64) {
+        if self.vote_epoch != epoch {
+            self.previous_votes = self.votes_before(epoch);
+            self.votes = 0;
+            self.vote_epoch = epoch;
+        }
+    }
+}
+
+/// Governance tokens a voter has locked for gauge voting.
+#[account]
+pub struct VoterLock {
+    pub owner: Pubkey,
+    pub amount: u64,
+    pub unlock_time: i64,
+    /// First epoch the voter can vote in again.
+    pub next_vote_epoch: u64,
+}
+
+impl VoterLock {
+    pub const LEN: usize = 32 + 8 + 8 + 8;
+}
+
+/// A stretch of a farm's emission schedule. The rate moves linearly from
+/// `rate` to `end_rate`, or halves every `halving_period` seconds.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct EmissionSegment {
+    pub start_time: i64,
+    pub end_time: i64,
+    /// Tokens per second at `start_time`.
+    pub rate: u64,
+    /// Tokens per second reached at `end_time`; equal to `rate` for a flat segment.
+    pub end_rate: u64,
+    /// Zero disables halving. A halving segment must keep `end_rate` equal to `rate`.
+    pub halving_period: i64,
+}
+
+impl EmissionSegment {
+    pub const LEN: usize = 8 + 8 + 8 + 8 + 8;
+
+    pub fn is_active(&self) -> bool {
+        self.end_time > self.start_time
+    }
+
+    fn is_valid(&self) -> bool {
+        self.is_active()
+            && self.halving_period >= 0
+            && (self.halving_period == 0 || self.end_rate == self.rate)
+    }
+
+    /// Tokens the segment has emitted by `time`, or `None` on overflow.
+    pub fn emitted_by(&self, time: i64) -> Option<u128> {
+        if !self.is_active() || time <= self.start_time {
+            return Some(0);
+        }
+        let duration = (self.end_time - self.start_time) as u64;
+        let elapsed = (time.min(self.end_time) - self.start_time) as u64;
+        if self.halving_period > 0 {
+            math::halving_emission(self.rate, self.halving_period as u64, elapsed)
+        } else {
+            math::linear_emission(self.rate, self.end_rate, duration, elapsed)
+        }
+    }
+}
+
+/// A secondary reward token with its own vault, rate and reward period.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct RewardStream {
+    pub reward_token_mint: Pubkey,
+    pub reward_token_account: Pubkey,
+    pub reward_rate: u64,
+    pub period_finish: i64,
+    pub last_update_time: i64,
+    pub reward_per_token_stored: u128,
+    pub reward_dust: u128,
+    /// Rewards distributed to stakers that have not been claimed yet.
+    pub reward_owed: u64,
+}
+
+impl RewardStream {
+    pub const LEN: usize = 32 + 32 + 8 + 8 + 8 + 16 + 16 + 8;
+
+    pub fn is_active(&self) -> bool {
+        self.reward_token_mint != Pubkey::default()
+    }
+
+    /// Stream vault balance not yet owed to stakers.
+    pub fn uncommitted_balance(&self, reward_vault: &TokenAccount) -> u64 {
+        reward_vault.amount.saturating_sub(self.reward_owed)
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct UserRewardStream {
+    pub reward_per_token_paid: u128,
+    pub reward_dust: u128,
+    pub rewards_earned: u64,
+}
+
+impl UserRewardStream {
+    pub const LEN: usize = 16 + 16 + 8;
+}
+
+/// Reward per token at the start of a week, where every lock ends.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct WeekSnapshot {
+    /// Week number, the week start divided by `SECONDS_PER_WEEK`.
+    pub week: i64,
+    pub reward_per_token: u128,
+    pub stream_reward_per_token: [u128; MAX_EXTRA_REWARDS],
+}
+
+impl WeekSnapshot {
+    pub const LEN: usize = 8 + 16 + 16 * MAX_EXTRA_REWARDS;
+}
+
+#[event]
+pub struct FarmCreated {
+    pub farm: Pubkey,
+    pub authority: Pubkey,
+    pub index: u64,
+    pub reward_token_mint: Pubkey,
+    pub staking_token_mint: Pubkey,
+}
+
+#[event]
+pub struct TokensStaked {
+    pub user: Pubkey,
+    pub farm: Pubkey,
+    pub amount: u64,
+    pub fee: u64,
+    pub user_staked: u64,
+    pub total_staked: u64,
+    pub reward_per_token_stored: u128,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct TokensUnstaked {
+    pub user: Pubkey,
+    pub farm: Pubkey,
+    pub amount: u64,
+    pub fee: u64,
+    pub user_staked: u64,
+    pub total_staked: u64,
+    pub reward_per_token_stored: u128,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct RewardsClaimed {
+    pub user: Pubkey,
+    pub farm: Pubkey,
+    pub rewards_paid: u64,
+    pub fee: u64,
+    pub total_staked: u64,
+    pub reward_per_token_stored: u128,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct RewardAdded {
+    pub farm: Pubkey,
+    pub amount: u64,
+    pub reward_rate: u64,
+    pub period_finish: i64,
+}
+
+#[event]
+pub struct RewardStreamAdded {
+    pub farm: Pubkey,
+    pub index: u8,
+    pub reward_token_mint: Pubkey,
+    pub reward_token_account: Pubkey,
+}
+
+#[event]
+pub struct RewardStreamFunded {
+    pub farm: Pubkey,
+    pub index: u8,
+    pub amount: u64,
+    pub reward_rate: u64,
+    pub period_finish: i64,
+}
+
+#[event]
+pub struct StreamRewardsClaimed {
+    pub user: Pubkey,
+    pub farm: Pubkey,
+    pub index: u8,
+    pub reward_token_mint: Pubkey,
+    pub rewards_paid: u64,
+    pub reward_per_token_stored: u128,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct TokensLocked {
+    pub user: Pubkey,
+    pub farm: Pubkey,
+    pub amount: u64,
+    pub fee: u64,
+    pub user_staked: u64,
+    pub lock_weeks: u8,
+    pub lock_end: i64,
+    pub effective_amount: u64,
+    pub total_effective_staked: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct LockExpired {
+    pub user: Pubkey,
+    pub farm: Pubkey,
+    pub effective_amount: u64,
+    pub total_effective_staked: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct CooldownUpdated {
+    pub farm: Pubkey,
+    pub cooldown_seconds: i64,
+}
+
+#[event]
+pub struct UnstakeRequested {
+    pub user: Pubkey,
+    pub farm: Pubkey,
+    pub amount: u64,
+    pub unbonding_amount: u64,
+    pub unbonding_end: i64,
+    pub total_staked: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct UnstakedWithdrawn {
+    pub user: Pubkey,
+    pub farm: Pubkey,
+    pub amount: u64,
+    pub fee: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct FeeConfigUpdated {
+    pub farm: Pubkey,
+    pub deposit_fee_bps: u16,
+    pub withdraw_fee_bps: u16,
+    pub performance_fee_bps: u16,
+    pub fee_recipient: Pubkey,
+}
+
+#[event]
+pub struct RewardRateUpdated {
+    pub farm: Pubkey,
+    pub reward_rate: u64,
+    pub period_finish: i64,
+}
+
+#[event]
+pub struct DurationUpdated {
+    pub farm: Pubkey,
+    pub farm_duration: i64,
+}
+
+#[event]
+pub struct AuthorityTransferStarted {
+    pub farm: Pubkey,
+    pub authority: Pubkey,
+    pub pending_authority: Pubkey,
+}
+
+#[event]
+pub struct AuthorityTransferred {
+    pub farm: Pubkey,
+    pub previous_authority: Pubkey,
+    pub authority: Pubkey,
+}
+
+#[event]
+pub struct FeesCollected {
+    pub farm: Pubkey,
+    pub fee_recipient: Pubkey,
+    pub stake_fees: u64,
+    pub reward_fees: u64,
+}
+
+#[event]
+pub struct RewardsCompounded {
+    pub user: Pubkey,
+    pub farm: Pubkey,
+    pub keeper: Pubkey,
+    pub amount: u64,
+    pub fee: u64,
+    pub tip: u64,
+    pub user_staked: u64,
+    pub total_staked: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AutoCompoundUpdated {
+    pub user: Pubkey,
+    pub farm: Pubkey,
+    pub enabled: bool,
+}
+
+#[event]
+pub struct VestingConfigUpdated {
+    pub farm: Pubkey,
+    pub vesting_period: i64,
+    pub early_exit_penalty_bps: u16,
+}
+
+#[event]
+pub struct RewardsVested {
+    pub user: Pubkey,
+    pub farm: Pubkey,
+    pub amount: u64,
+    pub fee: u64,
+    pub vesting_amount: u64,
+    pub vesting_end: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct VestedWithdrawn {
+    pub user: Pubkey,
+    pub farm: Pubkey,
+    pub amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct VestingExited {
+    pub user: Pubkey,
+    pub farm: Pubkey,
+    pub amount: u64,
+    pub penalty: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ReceiptsCheckpointed {
+    pub user: Pubkey,
+    pub farm: Pubkey,
+    pub user_staked: u64,
+    pub total_staked: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct StakedFor {
+    pub funder: Pubkey,
+    pub user: Pubkey,
+    pub farm: Pubkey,
+    pub amount: u64,
+    pub fee: u64,
+    pub user_staked: u64,
+    pub total_staked: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct StakeTransferred {
+    pub from: Pubkey,
+    pub to: Pubkey,
+    pub farm: Pubkey,
+    pub amount: u64,
+    pub user_staked: u64,
+    pub lock_end: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct UserStakeClosed {
+    pub user: Pubkey,
+    pub farm: Pubkey,
+}
+
+#[event]
+pub struct FarmStatusChanged {
+    pub farm: Pubkey,
+    pub is_active: bool,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct EmergencyWithdrawn {
+    pub user: Pubkey,
+    pub farm: Pubkey,
+    pub amount: u64,
+    pub rewards_forfeited: u64,
+    pub total_staked: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ControllerInitialized {
+    pub controller: Pubkey,
+    pub authority: Pubkey,
+    pub reward_token_mint: Pubkey,
+    pub emission_rate: u64,
+}
+
+#[event]
+pub struct EmissionRateUpdated {
+    pub controller: Pubkey,
+    pub emission_rate: u64,
+}
+
+#[event]
+pub struct AllocPointUpdated {
+    pub farm: Pubkey,
+    pub alloc_point: u64,
+    pub total_alloc_point: u64,
+}
+
+#[event]
+pub struct EmissionsDistributed {
+    pub farm: Pubkey,
+    pub amount: u64,
+    pub reward_per_token_stored: u128,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct GaugesInitialized {
+    pub governance_token_mint: Pubkey,
+    pub epoch_duration: i64,
+    pub genesis_time: i64,
+}
+
+#[event]
+pub struct GaugeCreated {
+    pub gauge: Pubkey,
+    pub farm: Pubkey,
+}
+
+#[event]
+pub struct GovernanceTokensLocked {
+    pub owner: Pubkey,
+    pub amount: u64,
+    pub locked: u64,
+    pub unlock_time: i64,
+}
+
+#[event]
+pub struct GovernanceTokensWithdrawn {
+    pub owner: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct GaugeVoted {
+    pub voter: Pubkey,
+    pub farm: Pubkey,
+    pub epoch: u64,
+    pub votes: u64,
+    pub total_votes: u64,
+}
+
+#[event]
+pub struct GaugeApplied {
+    pub farm: Pubkey,
+    pub epoch: u64,
+    pub votes: u64,
+}
+
+#[event]
+pub struct EmissionScheduleUpdated {
+    pub farm: Pubkey,
+    pub segments: u8,
+    pub remaining_emission: u64,
+    pub end_time: i64,
+}
+
+#[event]
+pub struct DepositCapsUpdated {
+    pub farm: Pubkey,
+    pub max_total_staked: u64,
+    pub max_stake_per_user: u64,
 }
 
 #[error_code]
@@ -290,4 +4439,100 @@ pub enum ErrorCode {
     NoRewards,
     #[msg("Farm is not active")]
     FarmNotActive,
+    #[msg("Reward math overflowed")]
+    MathOverflow,
+    #[msg("Farm duration must be positive")]
+    InvalidDuration,
+    #[msg("Reward vault cannot cover the payout")]
+    InsufficientRewardFunds,
+    #[msg("Reward vault cannot cover the promised emissions")]
+    InsufficientRewardBalance,
+    #[msg("Reward vault does not belong to this farm")]
+    InvalidRewardVault,
+    #[msg("Farm already runs the maximum number of reward streams")]
+    RewardStreamsFull,
+    #[msg("Farm already pays this reward token")]
+    DuplicateRewardStream,
+    #[msg("Reward stream does not exist or its accounts are missing")]
+    InvalidRewardStream,
+    #[msg("Stake vault does not belong to this farm")]
+    InvalidStakeVault,
+    #[msg("Token account mint does not match the farm")]
+    InvalidTokenMint,
+    #[msg("Lock must be between 1 and 52 weeks")]
+    InvalidLockDuration,
+    #[msg("Stake is locked")]
+    StakeLocked,
+    #[msg("Stake has no expired lock")]
+    LockNotExpired,
+    #[msg("Cooldown must be between zero and MAX_COOLDOWN_SECONDS")]
+    InvalidCooldown,
+    #[msg("Farm requires request_unstake and a cooldown")]
+    CooldownRequired,
+    #[msg("Nothing is unbonding")]
+    NothingUnbonding,
+    #[msg("Cooldown has not elapsed")]
+    CooldownNotElapsed,
+    #[msg("Fee exceeds its hard cap")]
+    FeeTooHigh,
+    #[msg("Fee account does not belong to the fee recipient")]
+    InvalidFeeAccount,
+    #[msg("Farm does not pay rewards in its staking token")]
+    NotSingleAsset,
+    #[msg("User has not opted in to auto-compounding")]
+    AutoCompoundDisabled,
+    #[msg("Vesting period cannot be negative")]
+    InvalidVestingPeriod,
+    #[msg("Early-exit penalty exceeds the maximum")]
+    PenaltyTooHigh,
+    #[msg("Farm does not vest rewards")]
+    VestingDisabled,
+    #[msg("Farm vests rewards; use vest_rewards")]
+    RewardsMustVest,
+    #[msg("Nothing to withdraw from vesting")]
+    NothingVested,
+    #[msg("Vesting vault does not match farm")]
+    InvalidVestingVault,
+    #[msg("Receipt mint does not match farm")]
+    InvalidReceiptMint,
+    #[msg("Receipt account is not the owner's associated token account")]
+    InvalidReceiptAccount,
+    #[msg("Stake cannot be transferred to its current owner")]
+    SameOwner,
+    #[msg("Position still holds stake or unclaimed rewards")]
+    PositionNotEmpty,
+    #[msg("Reward period has not finished")]
+    RewardPeriodActive,
+    #[msg("Signer is not the pending authority")]
+    NotPendingAuthority,
+    #[msg("Pools must be passed as farm and reward vault pairs")]
+    InvalidPoolAccounts,
+    #[msg("Unlock time must be in the future and not earlier than the current one")]
+    InvalidUnlockTime,
+    #[msg("Governance tokens are still locked")]
+    GovernanceTokensStillLocked,
+    #[msg("Voter already voted this epoch")]
+    AlreadyVotedThisEpoch,
+    #[msg("Lock must last until the end of the epoch")]
+    LockEndsBeforeEpoch,
+    #[msg("Gauge weights must match the gauges and sum to at most 10000")]
+    InvalidGaugeWeights,
+    #[msg("Gauge was already updated this epoch")]
+    EpochNotEnded,
+    #[msg("Invalid lock vault")]
+    InvalidLockVault,
+    #[msg("Epoch duration must be positive")]
+    InvalidEpochDuration,
+    #[msg("Emission segments must be valid, sorted and not overlapping")]
+    InvalidEmissionSchedule,
+    #[msg("Farm emissions follow a schedule")]
+    EmissionScheduleActive,
+    #[msg("Deposit would exceed the farm's total stake cap")]
+    TotalStakeCapExceeded,
+    #[msg("Deposit would exceed the per-user stake cap")]
+    UserStakeCapExceeded,
+    #[msg("Recipient's position is locked on different terms")]
+    IncompatibleLock,
+    #[msg("The farm's share of emissions is set by its gauge")]
+    GaugeControlsAllocation,
 }
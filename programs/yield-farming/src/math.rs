@@ -0,0 +1,275 @@
+//! Fixed-point reward accounting.
+//!
+//! `reward_per_token_stored` is the cumulative reward paid per staked token,
+//! scaled by [`REWARD_PRECISION`]. Division remainders are carried forward
+//! instead of being dropped: the farm keeps undistributed scaled rewards in
+//! `reward_dust`, and each user keeps the fraction of a token they are owed in
+//! their own `reward_dust`. Payouts therefore never exceed what was emitted,
+//! and only sub-token remainders are held back.
+
+/// Scale applied to `reward_per_token_stored` (12 decimal places).
+pub const REWARD_PRECISION: u128 = 1_000_000_000_000;
+
+/// Distributes `reward_rate * elapsed` tokens over `total_staked`.
+///
+/// Returns the new `(reward_per_token_stored, reward_dust)`, or `None` on
+/// overflow. Nothing accrues while the pool is empty.
+pub fn accrue_reward_per_token(
+    reward_per_token_stored: u128,
+    reward_dust: u128,
+    reward_rate: u64,
+    elapsed: u64,
+    total_staked: u64,
+) -> Option<(u128, u128)> {
+    if total_staked == 0 || elapsed == 0 {
+        return Some((reward_per_token_stored, reward_dust));
+    }
+    let scaled_rewards = (reward_rate as u128)
+        .checked_mul(elapsed as u128)?
+        .checked_mul(REWARD_PRECISION)?
+        .checked_add(reward_dust)?;
+    let total_staked = total_staked as u128;
+    Some((
+        reward_per_token_stored.checked_add(scaled_rewards / total_staked)?,
+        scaled_rewards % total_staked,
+    ))
+}
+
+/// Rewards accrued by `amount` staked tokens since `reward_per_token_paid`.
+///
+/// Returns the whole tokens earned and the user's new `reward_dust`, or
+/// `None` on overflow.
+pub fn earned(
+    amount: u64,
+    reward_per_token_stored: u128,
+    reward_per_token_paid: u128,
+    reward_dust: u128,
+) -> Option<(u64, u128)> {
+    let scaled_rewards = (amount as u128)
+        .checked_mul(reward_per_token_stored.checked_sub(reward_per_token_paid)?)?
+        .checked_add(reward_dust)?;
+    let rewards = u64::try_from(scaled_rewards / REWARD_PRECISION).ok()?;
+    Some((rewards, scaled_rewards % REWARD_PRECISION))
+}
+
+/// Basis-point denominator for multipliers and fees.
+pub const BPS_DENOMINATOR: u64 = 10_000;
+
+/// `fee_bps` of `amount`, rounded down in the payer's favour.
+pub fn fee(amount: u64, fee_bps: u16) -> u64 {
+    (amount as u128 * fee_bps as u128 / BPS_DENOMINATOR as u128) as u64
+}
+
+/// Longest lock a position can take.
+pub const MAX_LOCK_WEEKS: u8 = 52;
+
+/// Boost on top of 1x earned by a `MAX_LOCK_WEEKS` lock, in basis points.
+pub const MAX_LOCK_BOOST_BPS: u64 = 10_000;
+
+/// Reward weight of a position locked for `lock_weeks`, in basis points.
+/// Grows linearly from 1x unlocked to 2x at `MAX_LOCK_WEEKS`.
+pub fn lock_multiplier_bps(lock_weeks: u8) -> u64 {
+    BPS_DENOMINATOR + MAX_LOCK_BOOST_BPS * lock_weeks as u64 / MAX_LOCK_WEEKS as u64
+}
+
+/// Stake that `amount` tokens locked for `lock_weeks` count as when
+/// distributing rewards, or `None` on overflow.
+pub fn effective_amount(amount: u64, lock_weeks: u8) -> Option<u64> {
+    let boosted = amount as u128 * lock_multiplier_bps(lock_weeks) as u128;
+    u64::try_from(boosted / BPS_DENOMINATOR as u128).ok()
+}
+
+/// Part of `amount` released by a linear schedule from `start_time` to
+/// `end_time` at `current_time`.
+pub fn vested_amount(amount: u64, start_time: i64, end_time: i64, current_time: i64) -> u64 {
+    if current_time >= end_time {
+        return amount;
+    }
+    if current_time <= start_time {
+        return 0;
+    }
+    let elapsed = (current_time - start_time) as u128;
+    let duration = (end_time - start_time) as u128;
+    (amount as u128 * elapsed / duration) as u64
+}
+
+/// Tokens emitted during the first `elapsed` seconds by `rate`, halving every
+/// `halving_period` seconds. Returns `None` on overflow.
+pub fn halving_emission(rate: u64, halving_period: u64, elapsed: u64) -> Option<u128> {
+    let halvings = elapsed / halving_period;
+    let mut emitted = 0u128;
+    // The rate reaches zero after 64 halvings
+    for halving in 0..halvings.min(64) {
+        emitted = emitted.checked_add((rate >> halving) as u128 * halving_period as u128)?;
+    }
+    if halvings < 64 {
+        let partial = (elapsed - halvings * halving_period) as u128;
+        emitted = emitted.checked_add((rate >> halvings) as u128 * partial)?;
+    }
+    Some(emitted)
+}
+
+/// Tokens emitted during the first `elapsed` of `duration` seconds by a rate
+/// moving linearly from `start_rate` to `end_rate`, rounded down. Returns
+/// `None` on overflow.
+///
+/// Rounding the running total instead of each interval keeps the sum over
+/// any split of the segment equal to the emission of the whole segment.
+pub fn linear_emission(start_rate: u64, end_rate: u64, duration: u64, elapsed: u64) -> Option<u128> {
+    // r(t) = a + (b - a) t / d integrates to (2 a d t + (b - a) t^2) / 2d
+    let duration = duration as i128;
+    let elapsed = elapsed.min(duration as u64) as i128;
+    let slope = end_rate as i128 - start_rate as i128;
+    let numerator = duration
+        .checked_mul(2 * start_rate as i128)?
+        .checked_mul(elapsed)?
+        .checked_add(slope.checked_mul(elapsed * elapsed)?)?;
+    u128::try_from(numerator / (2 * duration)).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn small_rate_accrues_in_large_pool() {
+        let (stored, dust) = accrue_reward_per_token(0, 0, 1, 10, 1_000_000_000_000).unwrap();
+        assert_eq!(earned(1_000_000_000_000, stored, 0, 0), Some((10, 0)));
+        assert_eq!(dust, 0);
+    }
+
+    #[test]
+    fn dust_is_carried_between_updates() {
+        // 1 token per second over 3 tokens staked does not divide evenly.
+        let mut stored = 0;
+        let mut dust = 0;
+        for _ in 0..3 {
+            (stored, dust) = accrue_reward_per_token(stored, dust, 1, 1, 3).unwrap();
+        }
+        assert_eq!(dust, 0);
+        assert_eq!(earned(3, stored, 0, 0), Some((3, 0)));
+    }
+
+    #[test]
+    fn lock_multiplier_scales_linearly() {
+        assert_eq!(lock_multiplier_bps(0), 10_000);
+        assert_eq!(lock_multiplier_bps(26), 15_000);
+        assert_eq!(lock_multiplier_bps(MAX_LOCK_WEEKS), 20_000);
+        assert_eq!(effective_amount(1_000, 52), Some(2_000));
+        assert_eq!(effective_amount(u64::MAX, 52), None);
+    }
+
+    #[test]
+    fn vesting_releases_linearly() {
+        assert_eq!(vested_amount(1_000, 100, 200, 50), 0);
+        assert_eq!(vested_amount(1_000, 100, 200, 125), 250);
+        assert_eq!(vested_amount(1_000, 100, 200, 300), 1_000);
+        // A schedule without duration releases everything at once.
+        assert_eq!(vested_amount(1_000, 100, 100, 100), 1_000);
+    }
+
+    #[test]
+    fn halving_emission_sums_each_period() {
+        assert_eq!(halving_emission(1_000, 10, 5), Some(5_000));
+        assert_eq!(halving_emission(1_000, 10, 25), Some(10_000 + 5_000 + 5 * 250));
+        // The rate runs out instead of overflowing the shift.
+        assert_eq!(halving_emission(1, 1, 1_000), Some(1));
+    }
+
+    #[test]
+    fn linear_emission_integrates_the_rate() {
+        assert_eq!(linear_emission(100, 100, 50, 20), Some(2_000));
+        // Decaying to zero emits half of the flat amount.
+        assert_eq!(linear_emission(100, 0, 100, 100), Some(5_000));
+        assert_eq!(linear_emission(100, 0, 100, 50), Some(3_750));
+        assert_eq!(linear_emission(100, 0, 100, 500), Some(5_000));
+    }
+
+    proptest! {
+        #[test]
+        fn linear_emission_is_monotonic(
+            start_rate in 0u64..1_000_000_000_000,
+            end_rate in 0u64..1_000_000_000_000,
+            duration in 1u64..100_000_000,
+            a in 0u64..100_000_000,
+            b in 0u64..100_000_000,
+        ) {
+            let (from, to) = (a.min(b), a.max(b));
+            let emitted_from = linear_emission(start_rate, end_rate, duration, from).unwrap();
+            let emitted_to = linear_emission(start_rate, end_rate, duration, to).unwrap();
+            prop_assert!(emitted_from <= emitted_to);
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct Step {
+        elapsed: u64,
+        user: usize,
+        new_amount: u64,
+    }
+
+    fn step() -> impl Strategy<Value = Step> {
+        (0u64..100_000, 0usize..4, 0u64..1_000_000_000_000).prop_map(|(elapsed, user, new_amount)| {
+            Step {
+                elapsed,
+                user,
+                new_amount,
+            }
+        })
+    }
+
+    #[derive(Default, Clone, Copy)]
+    struct User {
+        amount: u64,
+        paid: u128,
+        dust: u128,
+        rewards: u64,
+    }
+
+    proptest! {
+        #[test]
+        fn payouts_never_exceed_emissions(
+            reward_rate in 0u64..1_000_000_000_000,
+            steps in prop::collection::vec(step(), 1..50),
+        ) {
+            let mut users = [User::default(); 4];
+            let mut stored = 0u128;
+            let mut farm_dust = 0u128;
+            let mut emitted = 0u128;
+            let mut max_total_staked = 0u128;
+
+            let settle = |user: &mut User, stored: u128| {
+                let (rewards, dust) = earned(user.amount, stored, user.paid, user.dust).unwrap();
+                user.rewards += rewards;
+                user.dust = dust;
+                user.paid = stored;
+            };
+
+            for step in &steps {
+                let total_staked: u64 = users.iter().map(|user| user.amount).sum();
+                max_total_staked = max_total_staked.max(total_staked as u128);
+                if total_staked > 0 {
+                    emitted += reward_rate as u128 * step.elapsed as u128;
+                }
+                (stored, farm_dust) = accrue_reward_per_token(
+                    stored, farm_dust, reward_rate, step.elapsed, total_staked,
+                ).unwrap();
+
+                let user = &mut users[step.user];
+                settle(user, stored);
+                user.amount = step.new_amount;
+            }
+            for user in users.iter_mut() {
+                settle(user, stored);
+            }
+
+            let paid: u128 = users.iter().map(|user| user.rewards as u128).sum();
+            prop_assert!(paid <= emitted);
+            // Users hold back less than one token each, the farm less than
+            // `total_staked / REWARD_PRECISION` plus one.
+            let held_back = users.len() as u128 + max_total_staked / REWARD_PRECISION + 1;
+            prop_assert!(emitted - paid <= held_back);
+        }
+    }
+}